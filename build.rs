@@ -1,4 +1,8 @@
 fn main() -> anyhow::Result<()> {
+    // Host builds of the library have no ESP-IDF configuration to propagate.
+    if std::env::var_os("CARGO_FEATURE_ESP").is_none() {
+        return Ok(());
+    }
     // Propagate ESP-IDF configuration and link arguments across Cargo's build-script boundary.
     embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
     embuild::build::LinkArgs::output_propagated("ESP_IDF")
@@ -0,0 +1,65 @@
+//! Conditions that stop the machine until the operator clears them.
+
+use core::fmt;
+
+use crate::{config::AXIS_NAMES, homing::HomingError, runaway::ThermalFault};
+
+/// The cause of a latched alarm. Motion commands are refused until it is unlocked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alarm {
+    /// Endstops on the axes in this bit mask triggered outside homing. Step generation was
+    /// killed mid-move, so the machine position may be lost.
+    HardLimit(u8),
+    /// The homing cycle failed.
+    Homing(HomingError),
+    /// The safety door opened while homing, abandoning the cycle.
+    DoorDuringHoming,
+    /// Runaway protection shut the named heater down, and every other heater with it. Only an
+    /// unlock clears it, so that homing cannot skip acknowledging it.
+    Thermal {
+        heater: &'static str,
+        fault: ThermalFault,
+    },
+}
+
+impl Alarm {
+    /// Returns `true` if a successful homing cycle may clear the alarm.
+    pub fn clears_by_homing(&self) -> bool {
+        !matches!(self, Self::Thermal { .. })
+    }
+}
+
+impl fmt::Display for Alarm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HardLimit(axes) => {
+                let names = AXIS_NAMES
+                    .iter()
+                    .enumerate()
+                    .filter(|(axis, _)| axes & 1 << axis != 0)
+                    .map(|(_, name)| *name)
+                    .collect::<String>();
+                write!(f, "hard limit triggered on {names}")
+            }
+            Self::Homing(error) => write!(f, "homing failed: {error}"),
+            Self::DoorDuringHoming => f.write_str("safety door opened during homing"),
+            Self::Thermal { heater, fault } => write!(f, "{heater} heater shut down: {fault}"),
+        }
+    }
+}
+
+impl std::error::Error for Alarm {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Homing(error) => Some(error),
+            Self::Thermal { fault, .. } => Some(fault),
+            Self::HardLimit(_) | Self::DoorDuringHoming => None,
+        }
+    }
+}
+
+impl From<HomingError> for Alarm {
+    fn from(error: HomingError) -> Self {
+        Self::Homing(error)
+    }
+}
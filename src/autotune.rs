@@ -0,0 +1,383 @@
+//! Relay-feedback PID autotuning.
+//!
+//! [`Autotune`] drives a heater like a relay, switching between a high and a low power whenever
+//! the temperature crosses the target, which makes it oscillate at the plant's ultimate period.
+//! The amplitude of that oscillation gives the ultimate gain (Åström–Hägglund), from which a
+//! [`TuningRule`] derives PID gains. The relay's midpoint is shifted after each cycle so that the
+//! heating and cooling halves take equally long, as in Marlin.
+
+use core::{f32::consts::PI, fmt};
+use std::time::Duration;
+
+use crate::{heater::PidGains, temperature::SensorFault};
+
+/// Band in °C around the target inside which the relay keeps its state, so that sensor noise
+/// cannot switch it.
+const HYSTERESIS: f32 = 0.5;
+/// Cycles left out of the result while the oscillation settles from the initial approach.
+const SETTLING_CYCLES: u32 = 2;
+/// Distance in °C above the target at which tuning is abandoned.
+const MAX_OVERSHOOT: f32 = 20.0;
+/// Longest the relay may stay in one state before tuning is abandoned.
+const MAX_HALF_CYCLE: Duration = Duration::from_secs(20 * 60);
+/// Limits of the relay's midpoint as a share of the maximum power, which keep some swing.
+const BIAS_LIMIT: f32 = 0.08;
+
+/// How PID gains are derived from the ultimate gain and period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TuningRule {
+    /// Classic Ziegler–Nichols: fast, with noticeable overshoot.
+    ZieglerNichols,
+    /// Tyreus–Luyben: slower and better damped, which suits plants with a long dead time.
+    TyreusLuyben,
+}
+
+impl TuningRule {
+    pub const ALL: [Self; 2] = [Self::ZieglerNichols, Self::TyreusLuyben];
+
+    /// Returns the rule's name as used by `/autotune`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ZieglerNichols => "ziegler-nichols",
+            Self::TyreusLuyben => "tyreus-luyben",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+
+    /// Returns the gains for a measured oscillation.
+    pub fn gains(&self, oscillation: &Oscillation) -> PidGains {
+        let ku = oscillation.ultimate_gain;
+        let tu = oscillation.ultimate_period;
+        // Proportional gain, integral time, and derivative time.
+        let (kp, ti, td) = match self {
+            Self::ZieglerNichols => (0.6 * ku, tu / 2.0, tu / 8.0),
+            Self::TyreusLuyben => (ku / 2.2, 2.2 * tu, tu / 6.3),
+        };
+        PidGains {
+            kp,
+            ki: kp / ti,
+            kd: kp * td,
+        }
+    }
+}
+
+/// The sustained oscillation measured under relay feedback, averaged over the measured cycles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Oscillation {
+    /// Power per °C at which proportional control alone would oscillate.
+    pub ultimate_gain: f32,
+    /// Oscillation period in seconds.
+    pub ultimate_period: f32,
+    /// Half the peak-to-peak temperature swing in °C.
+    pub amplitude: f32,
+}
+
+/// Why tuning stopped without a result.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutotuneError {
+    Sensor(SensorFault),
+    /// The temperature rose too far past the target.
+    Overshoot {
+        celsius: f32,
+    },
+    /// The temperature did not cross the target in time, as when the heater is too weak or
+    /// disconnected.
+    Timeout,
+    Aborted,
+}
+
+impl fmt::Display for AutotuneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sensor(fault) => write!(f, "sensor {fault}"),
+            Self::Overshoot { celsius } => write!(f, "temperature reached {celsius:.1} °C"),
+            Self::Timeout => f.write_str("temperature did not cross the target in time"),
+            Self::Aborted => f.write_str("aborted"),
+        }
+    }
+}
+
+impl std::error::Error for AutotuneError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutotuneStatus {
+    Running,
+    Completed(Oscillation),
+    Failed(AutotuneError),
+}
+
+impl AutotuneStatus {
+    /// Returns the status's name as reported by `/autotune`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Completed(_) => "completed",
+            Self::Failed(_) => "failed",
+        }
+    }
+}
+
+/// A relay-feedback tuning run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Autotune {
+    target: f32,
+    cycles: u32,
+    max_power: f32,
+    status: AutotuneStatus,
+    /// Seconds since the run started.
+    time: f32,
+    heating: bool,
+    /// Midpoint of the relay's two powers.
+    bias: f32,
+    /// Distance of each relay power from the midpoint.
+    swing: f32,
+    /// When the relay last switched on and off.
+    heating_since: f32,
+    cooling_since: f32,
+    /// Length of the last heating half-cycle.
+    heating_time: f32,
+    /// Highest temperature since the relay last switched off, and lowest since it switched on.
+    high: f32,
+    low: f32,
+    completed: u32,
+    /// Sums over the measured cycles.
+    gain_sum: f32,
+    period_sum: f32,
+    amplitude_sum: f32,
+}
+
+impl Autotune {
+    /// Starts tuning around `target` °C for `cycles` full oscillations, of which the first few
+    /// are discarded, with powers up to `max_power`.
+    pub fn new(target: f32, cycles: u32, max_power: f32) -> Self {
+        Self {
+            target,
+            cycles: cycles.max(SETTLING_CYCLES + 1),
+            max_power,
+            status: AutotuneStatus::Running,
+            time: 0.0,
+            heating: true,
+            bias: max_power / 2.0,
+            swing: max_power / 2.0,
+            heating_since: 0.0,
+            cooling_since: 0.0,
+            heating_time: 0.0,
+            high: f32::NEG_INFINITY,
+            low: f32::INFINITY,
+            completed: 0,
+            gain_sum: 0.0,
+            period_sum: 0.0,
+            amplitude_sum: 0.0,
+        }
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn cycles(&self) -> u32 {
+        self.cycles
+    }
+
+    /// Returns the number of full oscillations so far.
+    pub fn completed_cycles(&self) -> u32 {
+        self.completed
+    }
+
+    pub fn status(&self) -> AutotuneStatus {
+        self.status
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status == AutotuneStatus::Running
+    }
+
+    pub fn abort(&mut self) {
+        self.fail(AutotuneError::Aborted);
+    }
+
+    /// Takes a reading `interval` after the previous one and returns the power to apply.
+    pub fn update(&mut self, reading: Option<Result<f32, SensorFault>>, interval: Duration) -> f32 {
+        if !self.is_running() {
+            return 0.0;
+        }
+        let celsius = match reading {
+            Some(Ok(celsius)) => celsius,
+            Some(Err(fault)) => {
+                self.fail(AutotuneError::Sensor(fault));
+                return 0.0;
+            }
+            // No reading, as after a failed ADC read; stay off until one arrives.
+            None => return 0.0,
+        };
+        self.time += interval.as_secs_f32();
+        self.high = self.high.max(celsius);
+        self.low = self.low.min(celsius);
+
+        if celsius > self.target + MAX_OVERSHOOT {
+            self.fail(AutotuneError::Overshoot { celsius });
+            return 0.0;
+        }
+        let since = match self.heating {
+            true => self.heating_since,
+            false => self.cooling_since,
+        };
+        if self.time - since > MAX_HALF_CYCLE.as_secs_f32() {
+            self.fail(AutotuneError::Timeout);
+            return 0.0;
+        }
+
+        if self.heating && celsius > self.target + HYSTERESIS {
+            self.heating = false;
+            self.heating_time = self.time - self.heating_since;
+            self.cooling_since = self.time;
+            self.high = celsius;
+        } else if !self.heating && celsius < self.target - HYSTERESIS {
+            self.heating = true;
+            let cooling_time = self.time - self.cooling_since;
+            self.heating_since = self.time;
+            self.end_cycle(self.heating_time, cooling_time);
+            self.low = celsius;
+        }
+        self.power()
+    }
+
+    /// Measures the cycle that just ended and rebalances the relay.
+    fn end_cycle(&mut self, heating_time: f32, cooling_time: f32) {
+        self.completed += 1;
+        let period = heating_time + cooling_time;
+        // The first cycles carry the approach from ambient, so their peaks and timing say little
+        // about the steady oscillation.
+        if self.completed > SETTLING_CYCLES {
+            // The relay switches at the edges of the hysteresis band rather than at the target,
+            // which shifts the describing function by the band's width.
+            let amplitude = (self.high - self.low) / 2.0;
+            let effective = (amplitude * amplitude - HYSTERESIS * HYSTERESIS)
+                .max(f32::EPSILON)
+                .sqrt();
+            self.gain_sum += 4.0 * self.swing / (PI * effective);
+            self.period_sum += period;
+            self.amplitude_sum += amplitude;
+        }
+        if self.completed > 1 && period > 0.0 {
+            // Favour whichever half was shorter, so that the plant spends equal time on each side.
+            let limit = BIAS_LIMIT * self.max_power;
+            self.bias += self.swing * (heating_time - cooling_time) / period;
+            self.bias = self.bias.clamp(limit, self.max_power - limit);
+            self.swing = self.bias.min(self.max_power - self.bias);
+        }
+
+        if self.completed >= self.cycles {
+            let measured = (self.completed - SETTLING_CYCLES) as f32;
+            self.status = AutotuneStatus::Completed(Oscillation {
+                ultimate_gain: self.gain_sum / measured,
+                ultimate_period: self.period_sum / measured,
+                amplitude: self.amplitude_sum / measured,
+            });
+        }
+    }
+
+    /// Returns the relay's current power.
+    fn power(&self) -> f32 {
+        match (self.is_running(), self.heating) {
+            (false, _) => 0.0,
+            (true, true) => self.bias + self.swing,
+            (true, false) => self.bias - self.swing,
+        }
+    }
+
+    fn fail(&mut self, error: AutotuneError) {
+        if self.is_running() {
+            self.status = AutotuneStatus::Failed(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(100);
+
+    /// Runs `autotune` against a 40 W heater block whose sensor lags it, for at most `seconds`,
+    /// and returns the final sensor temperature.
+    fn run(autotune: &mut Autotune, seconds: f32) -> f32 {
+        let (mut sensor, mut block) = (25.0_f32, 25.0_f32);
+        let dt = TICK.as_secs_f32();
+        for _ in 0..(seconds / dt) as usize {
+            let power = autotune.update(Some(Ok(sensor)), TICK);
+            block += (40.0 * power - (block - sensor) * 2.0) * dt / 8.0;
+            sensor += ((block - sensor) * 2.0 - (sensor - 25.0) * 0.15) * dt / 12.0;
+            if !autotune.is_running() {
+                break;
+            }
+        }
+        sensor
+    }
+
+    #[test]
+    fn relay_measures_the_oscillation() {
+        let mut autotune = Autotune::new(200.0, 6, 1.0);
+        let sensor = run(&mut autotune, 2.0 * 3_600.0);
+        let AutotuneStatus::Completed(oscillation) = autotune.status() else {
+            panic!("tuning ended {:?}", autotune.status());
+        };
+        assert_eq!(autotune.completed_cycles(), 6);
+        assert!((sensor - 200.0).abs() < 10.0);
+        assert!(oscillation.amplitude > HYSTERESIS && oscillation.amplitude < 10.0);
+        assert!(oscillation.ultimate_gain > 0.0 && oscillation.ultimate_period > 0.0);
+        assert_eq!(autotune.update(Some(Ok(150.0)), TICK), 0.0);
+
+        // Tyreus–Luyben trades speed for damping against Ziegler–Nichols.
+        let fast = TuningRule::ZieglerNichols.gains(&oscillation);
+        let damped = TuningRule::TyreusLuyben.gains(&oscillation);
+        assert!(damped.kp < fast.kp && damped.ki < fast.ki);
+    }
+
+    #[test]
+    fn faults_end_the_run() {
+        let mut autotune = Autotune::new(200.0, 5, 1.0);
+        assert_eq!(autotune.update(Some(Ok(25.0)), TICK), 1.0);
+        assert_eq!(autotune.update(None, TICK), 0.0);
+        assert!(autotune.is_running());
+        assert_eq!(autotune.update(Some(Err(SensorFault::Short)), TICK), 0.0);
+        assert_eq!(
+            autotune.status(),
+            AutotuneStatus::Failed(AutotuneError::Sensor(SensorFault::Short))
+        );
+
+        let mut autotune = Autotune::new(200.0, 5, 1.0);
+        autotune.update(Some(Ok(221.0)), TICK);
+        assert_eq!(
+            autotune.status(),
+            AutotuneStatus::Failed(AutotuneError::Overshoot { celsius: 221.0 })
+        );
+
+        // A heater that never warms leaves the relay heating until the half-cycle limit.
+        let mut autotune = Autotune::new(200.0, 5, 1.0);
+        let ticks = MAX_HALF_CYCLE.as_millis() / TICK.as_millis() + 10;
+        (0..ticks).for_each(|_| _ = autotune.update(Some(Ok(25.0)), TICK));
+        assert_eq!(
+            autotune.status(),
+            AutotuneStatus::Failed(AutotuneError::Timeout)
+        );
+
+        let mut autotune = Autotune::new(200.0, 5, 1.0);
+        autotune.abort();
+        assert_eq!(
+            autotune.status(),
+            AutotuneStatus::Failed(AutotuneError::Aborted)
+        );
+    }
+
+    #[test]
+    fn rules_are_named() {
+        for rule in TuningRule::ALL {
+            assert_eq!(TuningRule::from_name(rule.name()), Some(rule));
+        }
+        assert_eq!(TuningRule::from_name("cohen-coon"), None);
+    }
+}
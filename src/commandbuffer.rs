@@ -2,16 +2,47 @@
 
 use core::cmp;
 
+use crate::config::{AXES, MachineConfig};
+
+/// Which override scales a move's speed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MoveKind {
+    /// A programmed feed move, scaled by the feed override.
+    #[default]
+    Feed,
+    /// A `G0` traverse, scaled by the rapid override.
+    Rapid,
+    /// A homing move or jog, which ignores overrides.
+    System,
+}
+
 /// One buffered move expressed as per-axis steps and a step-rate profile.
 #[derive(Default, Clone)]
 pub struct Block {
     pub steps: Steps,
-    /// Requested linear feed rate in millimetres per minute.
+    /// Position at the end of the move, in steps from the planner's origin.
+    pub target: Target,
+    pub kind: MoveKind,
+    /// Linear feed rate in millimetres per minute, after limiting to each axis's maximum rate.
     pub feed_rate: f32,
+    /// Highest feed rate the axes allow along this move, in millimetres per minute.
+    pub max_feed_rate: f32,
+    /// Euclidean length of the move in millimetres.
+    pub millimetres: f32,
+    /// Direction of travel as a unit vector in X, Y, Z, E order.
+    pub unit_vector: [f32; AXES],
     /// Linear acceleration in millimetres per second squared.
     pub acceleration: f32,
     /// Linear deceleration in millimetres per second squared.
     pub deceleration: f32,
+    /// Plateau speed in millimetres per second.
+    pub nominal_speed: f32,
+    /// Planned speed squared at the start of the move, in mm²/s².
+    pub entry_speed_sqr: f32,
+    /// Junction and nominal-speed limit on `entry_speed_sqr`, in mm²/s².
+    pub max_entry_speed_sqr: f32,
+    /// Limit on `entry_speed_sqr` from the corner with the previous move alone, in mm²/s².
+    pub max_junction_speed_sqr: f32,
     /// Plateau rate in step events per second.
     pub nominal_rate: f32,
     /// Rate at the first step event.
@@ -24,31 +55,49 @@ pub struct Block {
     pub decel_after: i32,
 }
 
-const DEFAULT_ACCELERATION: f32 = 1_200.0;
-const DEFAULT_DECELERATION: f32 = 1_200.0;
-
 impl Block {
-    /// Creates a move with the default acceleration limits.
-    pub fn new(target: Target, feed_rate: f32) -> Self {
-        let steps = Steps::new(target);
+    /// Creates a move limited by the slowest axis it drives.
+    ///
+    /// `delta` is the move's per-axis displacement in millimetres, used for its length and
+    /// direction. Speed and acceleration along the path are reduced until no axis exceeds its
+    /// configured maximum. The entry speed starts at rest until the planner's look-ahead raises it.
+    pub fn new(
+        steps: Steps,
+        delta: [f32; AXES],
+        feed_rate: f32,
+        kind: MoveKind,
+        config: &MachineConfig,
+    ) -> Self {
+        let millimetres = delta.iter().map(|axis| axis * axis).sum::<f32>().sqrt();
+        let unit_vector = delta.map(|axis| axis / millimetres);
+        let max_feed_rate = config.max_rate_along(&unit_vector);
+        let feed_rate = feed_rate.min(max_feed_rate);
+        let acceleration = config.max_acceleration_along(&unit_vector);
         Self {
             steps,
+            kind,
             feed_rate,
-            acceleration: DEFAULT_ACCELERATION,
-            deceleration: DEFAULT_DECELERATION,
-            nominal_rate: 300.0,
-            entry_rate: 300.0,
-            exit_rate: 300.0,
-            accel_until: 128,
-            decel_after: 128,
+            max_feed_rate,
+            millimetres,
+            unit_vector,
+            acceleration,
+            deceleration: acceleration,
+            nominal_speed: feed_rate / 60.0,
+            ..Self::default()
         }
     }
 
+    /// Sets the plateau speed to `percent` of the feed rate, but no faster than the axes allow.
+    pub fn set_speed_percent(&mut self, percent: u16) {
+        let feed_rate = self.feed_rate * f32::from(percent) / 100.0;
+        self.nominal_speed = feed_rate.min(self.max_feed_rate) / 60.0;
+    }
+
     /// Computes this move's trapezoidal step-rate profile.
     ///
-    /// `previous_exit_rate` carries velocity continuity from the preceding block. The current
-    /// prototype always ends each block at rest; junction look-ahead is not implemented yet.
-    pub fn calculate_trapezoid(&mut self, previous_exit_rate: f32) {
+    /// The profile starts at the planned `entry_speed_sqr` and ends at `exit_speed_sqr`, the
+    /// following block's entry speed or zero for the last queued move.
+    pub fn calculate_trapezoid(&mut self, exit_speed_sqr: f32) {
         let steps_total = self.steps.step_event_count as f32;
         if steps_total <= 0.0 {
             self.nominal_rate = 0.0;
@@ -59,34 +108,35 @@ impl Block {
             return;
         }
 
-        let dominant_axis_steps = cmp::max(
-            cmp::max(self.steps.x.abs(), self.steps.y.abs()),
-            cmp::max(self.steps.z.abs(), self.steps.e.abs()),
-        ) as f32;
-        let target_rate = (self.feed_rate / 60.0) * dominant_axis_steps / steps_total;
-
-        self.nominal_rate = target_rate;
-
-        let acceleration = self.acceleration * dominant_axis_steps / steps_total;
-        let deceleration = self.deceleration * dominant_axis_steps / steps_total;
+        // Rates are measured in events of the dominant axis, which advances once per step event.
+        let steps_per_millimetre = steps_total / self.millimetres;
+        self.nominal_rate = self.nominal_speed * steps_per_millimetre;
+        self.entry_rate =
+            (self.entry_speed_sqr.sqrt() * steps_per_millimetre).min(self.nominal_rate);
+        self.exit_rate = (exit_speed_sqr.sqrt() * steps_per_millimetre).min(self.nominal_rate);
 
-        self.entry_rate = previous_exit_rate.min(target_rate);
-        self.exit_rate = 0.0;
+        let acceleration = self.acceleration * steps_per_millimetre;
+        let deceleration = self.deceleration * steps_per_millimetre;
 
-        let accel_steps = ((target_rate.powi(2) - self.entry_rate.powi(2)) / (2.0 * acceleration))
+        let accel_steps = ((self.nominal_rate.powi(2) - self.entry_rate.powi(2))
+            / (2.0 * acceleration))
             .ceil()
             .max(0.0);
 
-        let decel_steps = ((target_rate.powi(2) - self.exit_rate.powi(2)) / (2.0 * deceleration))
-            .ceil()
+        let decel_steps = ((self.nominal_rate.powi(2) - self.exit_rate.powi(2))
+            / (2.0 * deceleration))
+            .floor()
             .max(0.0);
 
         let plateau_steps = steps_total - accel_steps - decel_steps;
         let (accel_steps, decel_steps) = if plateau_steps < 0.0 {
-            // Overlapping ramps form a triangular profile, so scale them to meet once.
-            let accel = accel_steps / (accel_steps + decel_steps) * steps_total;
-            let decel = steps_total - accel;
-            (accel, decel)
+            // Overlapping ramps form a triangular profile that peaks where they intersect.
+            let accel = ((2.0 * deceleration * steps_total + self.exit_rate.powi(2)
+                - self.entry_rate.powi(2))
+                / (2.0 * (acceleration + deceleration)))
+                .ceil()
+                .clamp(0.0, steps_total);
+            (accel, steps_total - accel)
         } else {
             (accel_steps, decel_steps)
         };
@@ -96,8 +146,8 @@ impl Block {
     }
 }
 
-/// A move target measured in whole steps from the planner's origin.
-#[derive(Clone)]
+/// A machine position measured in whole steps from the planner's origin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Target {
     pub x: i32,
     pub y: i32,
@@ -106,6 +156,8 @@ pub struct Target {
 }
 
 /// Per-axis step counts and the number of synchronized step events they require.
+///
+/// Counts are signed relative moves; a negative count drives its axis toward the origin.
 #[derive(Default, Clone)]
 pub struct Steps {
     pub x: i32,
@@ -116,18 +168,58 @@ pub struct Steps {
 }
 
 impl Steps {
-    /// Converts a target into synchronized per-axis step counts.
-    pub fn new(target: Target) -> Self {
-        let step_event_count = cmp::max(
-            cmp::max(target.x.abs(), target.y.abs()),
-            cmp::max(target.z.abs(), target.e.abs()),
-        );
+    /// Computes the signed per-axis steps that move from `from` to `to`.
+    pub fn between(from: &Target, to: &Target) -> Self {
+        let (x, y, z, e) = (to.x - from.x, to.y - from.y, to.z - from.z, to.e - from.e);
+        let step_event_count = cmp::max(cmp::max(x.abs(), y.abs()), cmp::max(z.abs(), e.abs()));
         Self {
-            x: target.x,
-            y: target.y,
-            z: target.z,
-            e: target.e,
+            x,
+            y,
+            z,
+            e,
             step_event_count,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_takes_the_limits_of_its_slowest_axis() {
+        let mut config = MachineConfig::default();
+        config.axes[1].max_rate = 600.0;
+        config.axes[1].max_acceleration = 300.0;
+        let steps = Steps::between(
+            &Target::default(),
+            &Target {
+                x: 30,
+                y: 40,
+                z: 0,
+                e: 0,
+            },
+        );
+
+        let block = Block::new(
+            steps.clone(),
+            [3.0, 4.0, 0.0, 0.0],
+            6_000.0,
+            MoveKind::Feed,
+            &config,
+        );
+        assert_eq!(block.millimetres, 5.0);
+        assert_eq!(block.unit_vector, [0.6, 0.8, 0.0, 0.0]);
+        assert_eq!((block.max_feed_rate, block.feed_rate), (750.0, 750.0));
+        assert_eq!((block.acceleration, block.deceleration), (375.0, 375.0));
+        assert_eq!(block.nominal_speed, 12.5);
+
+        // A slower programmed feed rate is kept, and overrides stop at the axis limit.
+        let mut block = Block::new(steps, [3.0, 4.0, 0.0, 0.0], 300.0, MoveKind::Feed, &config);
+        assert_eq!(block.nominal_speed, 5.0);
+        block.set_speed_percent(200);
+        assert_eq!(block.nominal_speed, 10.0);
+        block.set_speed_percent(1_000);
+        assert_eq!(block.nominal_speed, 12.5);
+    }
+}
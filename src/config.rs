@@ -0,0 +1,331 @@
+//! Machine kinematics and per-axis motion limits.
+
+/// Number of planned axes, in X, Y, Z, E order.
+pub const AXES: usize = 4;
+/// Axis letters in planner order.
+pub const AXIS_NAMES: [char; AXES] = ['X', 'Y', 'Z', 'E'];
+
+/// Default distance in millimetres between a cornering path and the sharp corner it rounds.
+pub const DEFAULT_JUNCTION_DEVIATION: f32 = 0.01;
+/// Default largest distance in millimetres between an arc and the chords that approximate it.
+pub const DEFAULT_ARC_TOLERANCE: f32 = 0.002;
+
+/// Drive and travel limits for one axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisConfig {
+    /// Motor steps per millimetre of travel, including microstepping.
+    pub steps_per_mm: f32,
+    /// Highest speed in millimetres per minute.
+    pub max_rate: f32,
+    /// Highest acceleration in millimetres per second squared.
+    pub max_acceleration: f32,
+    /// Lowest reachable machine coordinate in millimetres.
+    pub min_travel: f32,
+    /// Highest reachable machine coordinate in millimetres.
+    pub max_travel: f32,
+}
+
+impl AxisConfig {
+    /// Creates a linear axis that travels from zero to `travel` millimetres.
+    pub const fn new(steps_per_mm: f32, max_rate: f32, max_acceleration: f32, travel: f32) -> Self {
+        Self {
+            steps_per_mm,
+            max_rate,
+            max_acceleration,
+            min_travel: 0.0,
+            max_travel: travel,
+        }
+    }
+}
+
+/// How the planner treats moves that would leave an axis's configured travel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoftLimits {
+    /// Moves are queued wherever they go.
+    Disabled,
+    /// Moves that leave the travel are refused.
+    Reject,
+    /// Moves stop where their path first reaches the edge of the travel.
+    Clip,
+}
+
+/// Kinematic configuration and travel-limit enforcement consumed by the planner.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MachineConfig {
+    /// Per-axis limits in X, Y, Z, E order.
+    pub axes: [AxisConfig; AXES],
+    /// Junction deviation in millimetres; larger values corner faster but round corners more.
+    pub junction_deviation: f32,
+    /// Largest distance in millimetres between a `G2`/`G3` arc and its chords. Must be positive;
+    /// smaller values queue more, shorter segments.
+    pub arc_tolerance: f32,
+    pub soft_limits: SoftLimits,
+    /// `true` if any endstop that triggers outside homing kills step generation and raises an
+    /// alarm.
+    pub hard_limits: bool,
+}
+
+impl Default for MachineConfig {
+    /// Conservative limits for an unconfigured machine, enforced in software and by the
+    /// endstops. The extruder axis has unlimited travel.
+    fn default() -> Self {
+        Self {
+            axes: [
+                AxisConfig::new(10.0, 1_500.0, 1_200.0, 200.0),
+                AxisConfig::new(10.0, 1_500.0, 1_200.0, 200.0),
+                AxisConfig::new(10.0, 1_500.0, 1_200.0, 200.0),
+                AxisConfig {
+                    min_travel: f32::NEG_INFINITY,
+                    max_travel: f32::INFINITY,
+                    ..AxisConfig::new(10.0, 1_500.0, 1_200.0, 0.0)
+                },
+            ],
+            junction_deviation: DEFAULT_JUNCTION_DEVIATION,
+            arc_tolerance: DEFAULT_ARC_TOLERANCE,
+            soft_limits: SoftLimits::Reject,
+            hard_limits: true,
+        }
+    }
+}
+
+impl MachineConfig {
+    /// Returns each axis's steps per millimetre.
+    pub fn steps_per_mm(&self) -> [f32; AXES] {
+        self.axes.map(|axis| axis.steps_per_mm)
+    }
+
+    /// Returns the highest speed in millimetres per minute along `unit_vector` at which no axis
+    /// exceeds its own maximum rate.
+    pub fn max_rate_along(&self, unit_vector: &[f32; AXES]) -> f32 {
+        limit_by_axis_maximum(&self.axes.map(|axis| axis.max_rate), unit_vector)
+    }
+
+    /// Returns the highest acceleration in mm/s² along `unit_vector` at which no axis exceeds
+    /// its own maximum acceleration.
+    pub fn max_acceleration_along(&self, unit_vector: &[f32; AXES]) -> f32 {
+        limit_by_axis_maximum(&self.axes.map(|axis| axis.max_acceleration), unit_vector)
+    }
+
+    /// Lowers each axis's maximum rate to the fastest that `stepper` can step it, and returns
+    /// the lowered axes as a bit mask.
+    pub fn limit_step_rates(&mut self, stepper: &StepperConfig) -> u8 {
+        let mut lowered = 0;
+        for (index, axis) in self.axes.iter_mut().enumerate() {
+            let max_rate = stepper.max_step_rate(index) as f32 * 60.0 / axis.steps_per_mm;
+            if axis.max_rate > max_rate {
+                axis.max_rate = max_rate;
+                lowered |= 1 << index;
+            }
+        }
+        lowered
+    }
+}
+
+/// Scales a path quantity so that its projection onto every axis stays within that axis's limit.
+///
+/// Axes that do not move along `unit_vector` impose no limit.
+fn limit_by_axis_maximum(limits: &[f32; AXES], unit_vector: &[f32; AXES]) -> f32 {
+    limits
+        .iter()
+        .zip(unit_vector)
+        .filter(|(_, component)| **component != 0.0)
+        .map(|(limit, component)| limit / component.abs())
+        .fold(f32::INFINITY, f32::min)
+}
+
+/// Default width of each step pulse in microseconds.
+pub const DEFAULT_STEP_PULSE_MICROSECONDS: u32 = 2;
+/// Default delay in microseconds between a direction change and the next step pulse.
+pub const DEFAULT_DIRECTION_SETUP_MICROSECONDS: u32 = 1;
+/// Default delay in microseconds between the end of a step pulse and a direction change.
+pub const DEFAULT_DIRECTION_HOLD_MICROSECONDS: u32 = 1;
+/// Conservative estimate of the step interrupt's own execution time in microseconds, which
+/// bounds the interrupt rate regardless of how pulses are generated.
+const STEP_INTERRUPT_MICROSECONDS: u32 = 8;
+
+/// Hardware that shapes an axis's step pulses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepBackend {
+    /// The step interrupt drives the pin and busy-waits for the pulse width.
+    Timer,
+    /// An RMT channel emits the pulse; the step interrupt only starts it.
+    Rmt,
+}
+
+impl StepBackend {
+    /// Returns the lowercase name used by the HTTP API.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Timer => "timer",
+            Self::Rmt => "rmt",
+        }
+    }
+}
+
+/// Step pulse generation settings for the step interrupt's outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepperConfig {
+    /// Pulse backend of each axis in X, Y, Z, E order.
+    pub backends: [StepBackend; AXES],
+    /// Width of each step pulse in microseconds.
+    pub pulse_microseconds: u32,
+    /// Delay in microseconds between a direction change and the next step pulse.
+    pub direction_setup_microseconds: u32,
+    /// Delay in microseconds between the end of a step pulse and a direction change.
+    pub direction_hold_microseconds: u32,
+}
+
+impl Default for StepperConfig {
+    /// Every axis pulses through RMT, which keeps pulse widths exact while Wi-Fi is active.
+    /// Boards choose their own backends through
+    /// [`Board::STEP_BACKENDS`](crate::devices::Board::STEP_BACKENDS).
+    fn default() -> Self {
+        Self {
+            backends: [StepBackend::Rmt; AXES],
+            pulse_microseconds: DEFAULT_STEP_PULSE_MICROSECONDS,
+            direction_setup_microseconds: DEFAULT_DIRECTION_SETUP_MICROSECONDS,
+            direction_hold_microseconds: DEFAULT_DIRECTION_HOLD_MICROSECONDS,
+        }
+    }
+}
+
+impl StepperConfig {
+    /// Returns the highest step rate in steps per second that `axis` can sustain.
+    ///
+    /// A timer-driven pulse holds the interrupt for its full width, so the pulse and the
+    /// interrupt's own work add up. An RMT pulse runs in hardware alongside the interrupt and
+    /// needs only a low time equal to its width before the next pulse.
+    pub fn max_step_rate(&self, axis: usize) -> u32 {
+        let period = match self.backends[axis] {
+            StepBackend::Timer => self.pulse_microseconds + STEP_INTERRUPT_MICROSECONDS,
+            StepBackend::Rmt => (2 * self.pulse_microseconds).max(STEP_INTERRUPT_MICROSECONDS),
+        };
+        1_000_000 / period.max(1)
+    }
+}
+
+/// Default homing seek rate in millimetres per minute.
+pub const DEFAULT_HOMING_SEEK_RATE: f32 = 500.0;
+/// Default rate in millimetres per minute of the slow second approach that sets machine zero.
+pub const DEFAULT_HOMING_LOCATE_RATE: f32 = 25.0;
+/// Default distance in millimetres an axis backs away from its switch after homing.
+pub const DEFAULT_HOMING_PULL_OFF: f32 = 1.0;
+
+/// Travel direction in which an axis searches for its homing switch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HomingDirection {
+    /// The switch marks [`AxisConfig::min_travel`].
+    Negative,
+    /// The switch marks [`AxisConfig::max_travel`].
+    Positive,
+}
+
+impl HomingDirection {
+    /// Returns the sign of a move toward the switch.
+    pub fn sign(self) -> f32 {
+        match self {
+            Self::Negative => -1.0,
+            Self::Positive => 1.0,
+        }
+    }
+}
+
+/// Homing settings for one axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisHoming {
+    /// `true` if the axis has a switch and takes part in homing.
+    pub enabled: bool,
+    pub direction: HomingDirection,
+    /// Distance in millimetres the axis backs away from its switch after homing.
+    pub pull_off: f32,
+}
+
+/// Homing cycle settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HomingConfig {
+    /// Per-axis settings in X, Y, Z, E order.
+    pub axes: [AxisHoming; AXES],
+    /// Axes homed together in each pass, as bit masks in the order they run. Zero entries are
+    /// skipped.
+    pub cycles: [u8; AXES],
+    /// Rate of the first approach and of each pull-off in millimetres per minute.
+    pub seek_rate: f32,
+    /// Rate of the second approach in millimetres per minute.
+    pub locate_rate: f32,
+}
+
+impl Default for HomingConfig {
+    /// Homes Z first to clear the work, then X and Y together, each toward its minimum.
+    fn default() -> Self {
+        let axis = AxisHoming {
+            enabled: true,
+            direction: HomingDirection::Negative,
+            pull_off: DEFAULT_HOMING_PULL_OFF,
+        };
+        Self {
+            axes: [
+                axis,
+                axis,
+                axis,
+                AxisHoming {
+                    enabled: false,
+                    ..axis
+                },
+            ],
+            cycles: [1 << 2, 1 << 0 | 1 << 1, 0, 0],
+            seek_rate: DEFAULT_HOMING_SEEK_RATE,
+            locate_rate: DEFAULT_HOMING_LOCATE_RATE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> MachineConfig {
+        let mut config = MachineConfig::default();
+        config.axes[1].max_rate = 600.0;
+        config.axes[1].max_acceleration = 300.0;
+        config
+    }
+
+    #[test]
+    fn diagonal_is_limited_by_its_slowest_axis() {
+        let diagonal = [0.6, 0.8, 0.0, 0.0];
+        // Y covers 0.8 of the path and is the slower axis: 600 / 0.8 against 1,500 / 0.6.
+        assert_eq!(config().max_rate_along(&diagonal), 750.0);
+        assert_eq!(config().max_acceleration_along(&diagonal), 375.0);
+    }
+
+    #[test]
+    fn rates_are_limited_to_what_the_backend_can_step() {
+        let mut config = config();
+        config.axes[0].steps_per_mm = 6_400.0;
+        let stepper = StepperConfig {
+            backends: [
+                StepBackend::Timer,
+                StepBackend::Rmt,
+                StepBackend::Rmt,
+                StepBackend::Rmt,
+            ],
+            ..StepperConfig::default()
+        };
+        // A 2 µs timer pulse plus the interrupt's 8 µs allows 100 kHz, or 937.5 mm/min at
+        // 6,400 steps/mm. The other axes stay well below their 125 kHz RMT limit.
+        assert_eq!(stepper.max_step_rate(0), 100_000);
+        assert_eq!(stepper.max_step_rate(1), 125_000);
+        assert_eq!(config.limit_step_rates(&stepper), 1 << 0);
+        assert_eq!(config.axes[0].max_rate, 937.5);
+        assert_eq!(config.axes[1].max_rate, 600.0);
+        assert_eq!(config.limit_step_rates(&stepper), 0);
+    }
+
+    #[test]
+    fn stationary_axis_imposes_no_limit() {
+        let along_x = [1.0, 0.0, 0.0, 0.0];
+        assert_eq!(config().max_rate_along(&along_x), 1_500.0);
+        assert_eq!(config().max_acceleration_along(&along_x), 1_200.0);
+        assert_eq!(config().max_rate_along(&[0.0; AXES]), f32::INFINITY);
+    }
+}
@@ -1,6 +1,6 @@
 //! AI-Thinker ESP32-CAM pin map selected by `device_esp32cam`.
 
-use crate::devices::Device;
+use crate::devices::{Board, StatusOutput};
 
 pub mod pins {
     // Camera signals.
@@ -39,11 +39,32 @@ pub mod pins {
     pub const GPIO14: i32 = 14;
     pub const GPIO15: i32 = 15;
     pub const GPIO16: i32 = 16;
+
+    // Onboard LEDs. The flash LED shares GPIO4; the red status LED is active low.
+    pub const FLASH_LED: i32 = 4;
+    pub const STATUS_LED: i32 = 33;
 }
 
-impl Device {
-    pub const NAME: &'static str = "esp32cam";
-    pub const DISPLAY_NAME: &'static str = "ESP32-CAM";
-    pub const IMAGE_BYTES: &'static [u8] = include_bytes!("../../docs/device_images/esp32cam.jpg");
-    pub const IMAGE_MIME: &'static str = "image/jpeg";
+/// AI-Thinker ESP32-CAM module. It has no motion hardware; its onboard LEDs are exposed as
+/// status outputs.
+pub struct Esp32Cam;
+
+impl Board for Esp32Cam {
+    const NAME: &'static str = "esp32cam";
+    const DISPLAY_NAME: &'static str = "ESP32-CAM";
+    const IMAGE_BYTES: &'static [u8] = include_bytes!("../../docs/device_images/esp32cam.jpg");
+    const IMAGE_MIME: &'static str = "image/jpeg";
+
+    const STATUS_OUTPUTS: &'static [StatusOutput] = &[
+        StatusOutput {
+            name: "status",
+            pin: pins::STATUS_LED,
+            active_low: true,
+        },
+        StatusOutput {
+            name: "flash",
+            pin: pins::FLASH_LED,
+            active_low: false,
+        },
+    ];
 }
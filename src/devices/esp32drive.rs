@@ -1,6 +1,7 @@
 //! ESP32Drive pin map selected by `device_esp32drive`.
 
-use crate::devices::Device;
+use crate::devices::{Board, StatusOutput};
+
 pub mod pins {
     // General-purpose and UART pins.
     pub const TX0: i32 = 1;
@@ -16,10 +17,31 @@ pub mod pins {
     pub const GPIO23: i32 = 23;
 }
 
-impl Device {
-    pub const NAME: &'static str = "esp32drive";
-    pub const DISPLAY_NAME: &'static str = "ESP32Drive";
-    pub const IMAGE_BYTES: &'static [u8] =
-        include_bytes!("../../docs/device_images/esp32drive.png");
-    pub const IMAGE_MIME: &'static str = "image/png";
+/// ESP32Drive controller. Only its free general-purpose pins are described so far, and they are
+/// exposed as plain on/off outputs.
+pub struct Esp32Drive;
+
+impl Board for Esp32Drive {
+    const NAME: &'static str = "esp32drive";
+    const DISPLAY_NAME: &'static str = "ESP32Drive";
+    const IMAGE_BYTES: &'static [u8] = include_bytes!("../../docs/device_images/esp32drive.png");
+    const IMAGE_MIME: &'static str = "image/png";
+
+    const STATUS_OUTPUTS: &'static [StatusOutput] = &[
+        StatusOutput {
+            name: "gpio17",
+            pin: pins::GPIO17,
+            active_low: false,
+        },
+        StatusOutput {
+            name: "gpio21",
+            pin: pins::GPIO21,
+            active_low: false,
+        },
+        StatusOutput {
+            name: "gpio22",
+            pin: pins::GPIO22,
+            active_low: false,
+        },
+    ];
 }
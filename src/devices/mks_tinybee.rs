@@ -1,9 +1,18 @@
 //! MKS TinyBee pin map selected by `device_mks_tinybee`.
+//!
+//! Every stepper output sits on the shift-register chain, which the step interrupt cannot pulse,
+//! so the planner refuses motion on this board until step bits are streamed through the chain.
 
-use crate::devices::Device;
+use crate::devices::{Board, Endstop, ExpanderBus, Fan, Heater, StatusOutput, StepperChannel};
 
 pub mod pins {
-    // PCF8575-backed outputs use the firmware's virtual-pin range 128–149.
+    // Outputs on the board's 74HC595 shift-register chain use the firmware's virtual-pin range
+    // 128–149. The chain is clocked from the I2S header pins.
+    pub const EXPANDER_DATA: i32 = 27;
+    pub const EXPANDER_CLOCK: i32 = 25;
+    pub const EXPANDER_LATCH: i32 = 26;
+    pub const EXPANDER_OUTPUTS: u8 = 24;
+
     // Heater outputs.
     pub const H_BED: i32 = 144;
     pub const H_E0: i32 = 145;
@@ -80,10 +89,99 @@ pub mod pins {
     pub const UART2_RXD: i32 = 16;
 }
 
-impl Device {
-    pub const NAME: &'static str = "mks_tinybee";
-    pub const DISPLAY_NAME: &'static str = "MKS TinyBee";
-    pub const IMAGE_BYTES: &'static [u8] =
-        include_bytes!("../../docs/device_images/mks_tinybee.png");
-    pub const IMAGE_MIME: &'static str = "image/png";
+/// MKS TinyBee 3D-printer controller. Steppers, heaters, fans, and the beeper sit behind the
+/// board's shift-register chain; the second extruder driver has no planner axis yet.
+pub struct MksTinyBee;
+
+impl Board for MksTinyBee {
+    const NAME: &'static str = "mks_tinybee";
+    const DISPLAY_NAME: &'static str = "MKS TinyBee";
+    const IMAGE_BYTES: &'static [u8] = include_bytes!("../../docs/device_images/mks_tinybee.png");
+    const IMAGE_MIME: &'static str = "image/png";
+
+    const EXPANDER: Option<ExpanderBus> = Some(ExpanderBus::ShiftRegister {
+        data: pins::EXPANDER_DATA,
+        clock: pins::EXPANDER_CLOCK,
+        latch: pins::EXPANDER_LATCH,
+        outputs: pins::EXPANDER_OUTPUTS,
+    });
+    const STEPPERS: &'static [StepperChannel] = &[
+        StepperChannel {
+            axis: 0,
+            step: pins::X_STEP,
+            direction: pins::X_DIR,
+            enable: Some(pins::X_ENABLE),
+        },
+        StepperChannel {
+            axis: 1,
+            step: pins::Y_STEP,
+            direction: pins::Y_DIR,
+            enable: Some(pins::Y_ENABLE),
+        },
+        StepperChannel {
+            axis: 2,
+            step: pins::Z_STEP,
+            direction: pins::Z_DIR,
+            enable: Some(pins::Z_ENABLE),
+        },
+        StepperChannel {
+            axis: 3,
+            step: pins::E0_STEP,
+            direction: pins::E0_DIR,
+            enable: Some(pins::E0_ENABLE),
+        },
+    ];
+    const ENDSTOPS: &'static [Endstop] = &[
+        Endstop {
+            axis: 0,
+            pin: pins::X_STOP,
+            active_low: false,
+        },
+        Endstop {
+            axis: 1,
+            pin: pins::Y_STOP,
+            active_low: false,
+        },
+        Endstop {
+            axis: 2,
+            pin: pins::Z_STOP,
+            active_low: false,
+        },
+    ];
+    const HEATERS: &'static [Heater] = &[
+        Heater {
+            name: "bed",
+            output: pins::H_BED,
+            thermistor: Some(pins::TB),
+        },
+        Heater {
+            name: "e0",
+            output: pins::H_E0,
+            thermistor: Some(pins::TH1),
+        },
+        Heater {
+            name: "e1",
+            output: pins::H_E1,
+            thermistor: Some(pins::TH2),
+        },
+    ];
+    const FANS: &'static [Fan] = &[
+        // Part cooling.
+        Fan {
+            name: "fan1",
+            output: pins::FAN1,
+            heater: None,
+        },
+        // Hotend heat-break cooling.
+        Fan {
+            name: "fan2",
+            output: pins::FAN2,
+            heater: Some("e0"),
+        },
+    ];
+    const STATUS_OUTPUTS: &'static [StatusOutput] = &[StatusOutput {
+        name: "beeper",
+        pin: pins::BEEPER,
+        active_low: false,
+    }];
 }
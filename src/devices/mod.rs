@@ -1,22 +1,194 @@
-//! Compile-time device selection and metadata.
+//! Compile-time board selection and pin roles.
 //!
-//! Enable exactly one `device_*` Cargo feature. The selected module supplies its pin map, while
-//! [`Device`] exposes metadata embedded in the HTTP diagnostic interface.
+//! Enable exactly one `device_*` Cargo feature. The selected module implements [`Board`] for its
+//! controller, and [`SelectedBoard`] names that implementation so startup can build its drivers
+//! from the board's description instead of from fixed GPIO numbers.
 
-/// Namespace for metadata associated with the selected controller board.
-pub struct Device;
+use crate::config::{AXES, StepBackend};
+
+/// First pin number of the firmware's virtual-pin range, which addresses outputs on an I/O
+/// expander rather than native GPIO.
+pub const VIRTUAL_PIN_BASE: i32 = 128;
+
+/// Returns `true` if `pin` addresses an I/O expander output rather than a native GPIO.
+pub const fn is_virtual_pin(pin: i32) -> bool {
+    pin >= VIRTUAL_PIN_BASE
+}
+
+/// One stepper driver's STEP/DIR/ENABLE signals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepperChannel {
+    /// Planner axis the driver follows, in X, Y, Z, E order. Several channels may share an axis.
+    pub axis: usize,
+    pub step: i32,
+    pub direction: i32,
+    /// Active-low driver enable, if the board wires one.
+    pub enable: Option<i32>,
+}
+
+impl StepperChannel {
+    /// Returns `true` if none of the channel's pins is on an I/O expander.
+    pub fn is_native(&self) -> bool {
+        [self.step, self.direction]
+            .into_iter()
+            .chain(self.enable)
+            .all(|pin| !is_virtual_pin(pin))
+    }
+}
+
+/// Returns the planner axes, as a bit mask, that the step interrupt cannot drive through
+/// `channels`: axes with no channel, and axes with any channel on the I/O expander, whose
+/// ganged motors would otherwise part ways.
+pub fn undriven_axes(channels: &[StepperChannel]) -> u8 {
+    (0..AXES)
+        .filter(|axis| {
+            let mut axis_channels = channels.iter().filter(|channel| channel.axis == *axis);
+            axis_channels.clone().next().is_none() || !axis_channels.all(StepperChannel::is_native)
+        })
+        .fold(0, |mask, axis| mask | 1 << axis)
+}
+
+/// Bus that drives the board's virtual pins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpanderBus {
+    /// Daisy-chained 74HC595 shift registers; `latch` rises once all `outputs` bits are shifted.
+    ShiftRegister {
+        data: i32,
+        clock: i32,
+        latch: i32,
+        outputs: u8,
+    },
+    /// PCF8575 I2C expanders at consecutive addresses from `address`, 16 virtual pins each.
+    Pcf8575 {
+        sda: i32,
+        scl: i32,
+        address: u8,
+        chips: u8,
+    },
+}
+
+/// A limit or homing switch input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Endstop {
+    /// Planner axis the switch limits, in X, Y, Z, E order. Several switches may share an axis.
+    pub axis: usize,
+    pub pin: i32,
+    /// `true` if the pin reads low while the switch is triggered.
+    pub active_low: bool,
+}
+
+/// A safety-door switch input on native GPIO.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SafetyDoor {
+    pub pin: i32,
+    /// `true` if the pin reads low while the door is open.
+    pub active_low: bool,
+}
+
+/// A heater output and the thermistor input that measures it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Heater {
+    pub name: &'static str,
+    pub output: i32,
+    pub thermistor: Option<i32>,
+}
+
+/// A fan output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fan {
+    pub name: &'static str,
+    pub output: i32,
+    /// Name of the heater whose temperature switches the fan on, as for a hotend's heat-break
+    /// fan, or `None` for a fan controlled only by `M106` and `M107`.
+    pub heater: Option<&'static str>,
+}
+
+/// Spindle control outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Spindle {
+    pub enable: Option<i32>,
+    pub pwm: Option<i32>,
+}
+
+/// A named on/off output controlled from the HTTP interface, such as an LED or relay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StatusOutput {
+    pub name: &'static str,
+    pub pin: i32,
+    /// `true` if driving the pin low turns the output on.
+    pub active_low: bool,
+}
+
+/// Description of a controller board by pin role.
+///
+/// Pins at or above [`VIRTUAL_PIN_BASE`] live on the board's [`ExpanderBus`]. Roles a board lacks
+/// keep their empty defaults.
+pub trait Board {
+    /// Stable identifier used by the HTTP interface.
+    const NAME: &'static str;
+    const DISPLAY_NAME: &'static str;
+    const IMAGE_BYTES: &'static [u8];
+    const IMAGE_MIME: &'static str;
+
+    const EXPANDER: Option<ExpanderBus> = None;
+    const STEPPERS: &'static [StepperChannel] = &[];
+    /// Pulse backend of each axis in X, Y, Z, E order. The ESP32 has eight RMT channels, so a
+    /// board with more step channels than that puts some axes on the timer.
+    const STEP_BACKENDS: [StepBackend; AXES] = [StepBackend::Rmt; AXES];
+    const ENDSTOPS: &'static [Endstop] = &[];
+    const SAFETY_DOOR: Option<SafetyDoor> = None;
+    const HEATERS: &'static [Heater] = &[];
+    const FANS: &'static [Fan] = &[];
+    const SPINDLE: Option<Spindle> = None;
+    const STATUS_OUTPUTS: &'static [StatusOutput] = &[];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(axis: usize, step: i32) -> StepperChannel {
+        StepperChannel {
+            axis,
+            step,
+            direction: step + 1,
+            enable: None,
+        }
+    }
+
+    #[test]
+    fn axes_without_native_channels_are_undriven() {
+        let channels = [
+            channel(0, 12),
+            channel(1, 26),
+            // Y is ganged with a motor on the expander, and Z is only on the expander.
+            channel(1, 130),
+            channel(2, 133),
+        ];
+        assert_eq!(undriven_axes(&channels), 1 << 1 | 1 << 2 | 1 << 3);
+        assert_eq!(undriven_axes(&[]), 0b1111);
+    }
+}
 
 #[cfg(feature = "device_mks_tinybee")]
 pub mod mks_tinybee;
+#[cfg(feature = "device_mks_tinybee")]
+pub use mks_tinybee::MksTinyBee as SelectedBoard;
 
 #[cfg(feature = "device_esp32drive")]
 pub mod esp32drive;
+#[cfg(feature = "device_esp32drive")]
+pub use esp32drive::Esp32Drive as SelectedBoard;
 
 #[cfg(feature = "device_esp32cam")]
 pub mod esp32cam;
+#[cfg(feature = "device_esp32cam")]
+pub use esp32cam::Esp32Cam as SelectedBoard;
 
 #[cfg(feature = "device_xprov5")]
 pub mod xprov5;
+#[cfg(feature = "device_xprov5")]
+pub use xprov5::XproV5 as SelectedBoard;
 
 #[cfg(not(any(
     feature = "device_mks_tinybee",
@@ -1,6 +1,6 @@
 //! CNC xPro V5 pin map selected by `device_xprov5`.
 
-use crate::devices::Device;
+use crate::devices::{Board, Endstop, SafetyDoor, Spindle, StatusOutput, StepperChannel};
 
 pub mod pins {
     // General-purpose inputs and outputs.
@@ -23,6 +23,16 @@ pub mod pins {
     pub const TF_MOSI: i32 = 23;
     pub const TF_DET: i32 = 34;
 
+    // Stepper-driver STEP/DIR outputs. AY2 is a second Y motor.
+    pub const X_STEP: i32 = 12;
+    pub const X_DIR: i32 = 14;
+    pub const Y_STEP: i32 = 27;
+    pub const Y_DIR: i32 = 26;
+    pub const Z_STEP: i32 = 15;
+    pub const Z_DIR: i32 = 2;
+    pub const AY2_STEP: i32 = 33;
+    pub const AY2_DIR: i32 = 32;
+
     pub const MOTOR_DRIVER_CS: i32 = 17;
     // Positions in the motor-driver SPI daisy chain.
     pub const MOTOR_X: i32 = 1;
@@ -39,9 +49,77 @@ pub mod pins {
     pub const UART2_RXD: i32 = 16;
 }
 
-impl Device {
-    pub const NAME: &'static str = "xprov5";
-    pub const DISPLAY_NAME: &'static str = "CNC xPro V5";
-    pub const IMAGE_BYTES: &'static [u8] = include_bytes!("../../docs/device_images/xprov5.png");
-    pub const IMAGE_MIME: &'static str = "image/png";
+/// CNC xPro V5 controller. The AY2 driver is ganged to the Y axis, and the DOOR input is the
+/// safety-door switch. The TMC5160 drivers take their current and enable settings over SPI,
+/// which the firmware does not configure yet.
+pub struct XproV5;
+
+impl Board for XproV5 {
+    const NAME: &'static str = "xprov5";
+    const DISPLAY_NAME: &'static str = "CNC xPro V5";
+    const IMAGE_BYTES: &'static [u8] = include_bytes!("../../docs/device_images/xprov5.png");
+    const IMAGE_MIME: &'static str = "image/png";
+
+    const STEPPERS: &'static [StepperChannel] = &[
+        StepperChannel {
+            axis: 0,
+            step: pins::X_STEP,
+            direction: pins::X_DIR,
+            enable: None,
+        },
+        StepperChannel {
+            axis: 1,
+            step: pins::Y_STEP,
+            direction: pins::Y_DIR,
+            enable: None,
+        },
+        StepperChannel {
+            axis: 2,
+            step: pins::Z_STEP,
+            direction: pins::Z_DIR,
+            enable: None,
+        },
+        StepperChannel {
+            axis: 1,
+            step: pins::AY2_STEP,
+            direction: pins::AY2_DIR,
+            enable: None,
+        },
+    ];
+    const ENDSTOPS: &'static [Endstop] = &[
+        Endstop {
+            axis: 0,
+            pin: pins::X_STOP,
+            active_low: true,
+        },
+        Endstop {
+            axis: 1,
+            pin: pins::Y_STOP,
+            active_low: true,
+        },
+        Endstop {
+            axis: 1,
+            pin: pins::AY2_STOP,
+            active_low: true,
+        },
+        Endstop {
+            axis: 2,
+            pin: pins::Z_STOP,
+            active_low: true,
+        },
+    ];
+    // The door input is pulled up and a closed door's switch holds it low.
+    const SAFETY_DOOR: Option<SafetyDoor> = Some(SafetyDoor {
+        pin: pins::DOOR,
+        active_low: false,
+    });
+    const SPINDLE: Option<Spindle> = Some(Spindle {
+        enable: Some(pins::SPINDLE_EN),
+        pwm: Some(pins::SPINDLE_PWM),
+    });
+    const STATUS_OUTPUTS: &'static [StatusOutput] = &[StatusOutput {
+        name: "mist",
+        pin: pins::MIST,
+        active_low: false,
+    }];
 }
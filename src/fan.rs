@@ -0,0 +1,200 @@
+//! Fan speed control.
+//!
+//! A [`Fan`] turns a requested speed into a duty cycle and spreads it over a software PWM period
+//! through [`Fan::output_level`], like a heater output, so fans behind an I/O expander can be
+//! throttled. Non-zero speeds are scaled above a minimum duty that keeps the fan turning, and a
+//! fan starting from rest gets a short full-duty kick. A fan bound to a heater also runs at full
+//! speed whenever that heater is hot, whatever speed was requested.
+
+use core::fmt;
+use std::time::Duration;
+
+use crate::temperature::SensorFault;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FanConfig {
+    /// Duty from 0 to 1 that the lowest non-zero speed maps to, below which the fan would stall.
+    pub min_duty: f32,
+    /// How long a fan starting from rest runs at full duty before settling to its speed.
+    pub kick_start: Duration,
+    /// Period over which [`Fan::output_level`] spreads the duty.
+    pub pwm_period: Duration,
+    /// Temperature in °C of a bound heater above which the fan runs at full speed.
+    pub auto_celsius: f32,
+    /// Distance in °C below `auto_celsius` at which the fan returns to its requested speed.
+    pub auto_hysteresis: f32,
+}
+
+impl Default for FanConfig {
+    /// A brushless 12 or 24 V fan switched by a MOSFET, with Marlin's default hotend fan
+    /// threshold of 50 °C.
+    fn default() -> Self {
+        Self {
+            min_duty: 0.2,
+            kick_start: Duration::from_millis(200),
+            pwm_period: Duration::from_millis(100),
+            auto_celsius: 50.0,
+            auto_hysteresis: 2.0,
+        }
+    }
+}
+
+/// Why a fan M-code was not applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FanError {
+    /// The board has no fan with this `P` number, or cannot drive its output.
+    NotFitted(u8),
+}
+
+impl fmt::Display for FanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFitted(fan) => write!(f, "no fan {fan}"),
+        }
+    }
+}
+
+impl std::error::Error for FanError {}
+
+/// A fan's requested speed and output state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fan {
+    config: FanConfig,
+    /// Speed from 0 to 1 set by `M106` or `M107`.
+    speed: f32,
+    /// The bound heater is hot, so the fan runs at full speed.
+    auto: bool,
+    /// Duty applied by the last call to `output_level`.
+    duty: f32,
+    /// Seconds of kick-start left.
+    kick: f32,
+    /// Position in the PWM period, in seconds.
+    phase: f32,
+}
+
+impl Fan {
+    pub fn new(config: FanConfig) -> Self {
+        Self {
+            config,
+            speed: 0.0,
+            auto: false,
+            duty: 0.0,
+            kick: 0.0,
+            phase: 0.0,
+        }
+    }
+
+    /// Returns the requested speed from 0 to 1.
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Sets the speed from 0 to 1, where zero stops the fan unless its heater is hot.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(0.0, 1.0);
+    }
+
+    /// Returns `true` while a hot bound heater holds the fan at full speed.
+    pub fn is_auto(&self) -> bool {
+        self.auto
+    }
+
+    /// Returns the duty applied since the last output update, from 0 to 1.
+    pub fn duty(&self) -> f32 {
+        self.duty
+    }
+
+    /// Returns `true` while a kick-start is running the fan at full duty.
+    pub fn is_kicking(&self) -> bool {
+        self.kick > 0.0
+    }
+
+    /// Follows the bound heater's latest reading. A faulty or missing reading counts as hot, so
+    /// that a fan cooling a hotend keeps running when its sensor fails.
+    pub fn update_heater(&mut self, reading: Option<Result<f32, SensorFault>>) {
+        let on = self.config.auto_celsius;
+        let off = on - self.config.auto_hysteresis;
+        self.auto = match reading {
+            Some(Ok(celsius)) if celsius > on => true,
+            Some(Ok(celsius)) if celsius < off => false,
+            Some(Ok(_)) => self.auto,
+            Some(Err(_)) | None => true,
+        };
+    }
+
+    /// Advances the PWM by `elapsed` and returns whether the output should be on.
+    pub fn output_level(&mut self, elapsed: Duration) -> bool {
+        let speed = match self.auto {
+            true => 1.0,
+            false => self.speed,
+        };
+        let duty = match speed > 0.0 {
+            true => self.config.min_duty + (1.0 - self.config.min_duty) * speed,
+            false => 0.0,
+        };
+        if self.duty <= 0.0 && duty > 0.0 && duty < 1.0 {
+            self.kick = self.config.kick_start.as_secs_f32();
+        } else if duty <= 0.0 {
+            self.kick = 0.0;
+        }
+        self.duty = duty;
+
+        let seconds = elapsed.as_secs_f32();
+        if self.kick > 0.0 {
+            self.kick = (self.kick - seconds).max(0.0);
+            return true;
+        }
+        let period = self.config.pwm_period.as_secs_f32();
+        if period <= 0.0 {
+            return duty > 0.0;
+        }
+        self.phase = (self.phase + seconds) % period;
+        self.phase < duty * period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn slow_start_kicks_then_scales_above_the_minimum_duty() {
+        let mut fan = Fan::new(FanConfig::default());
+        assert!(!fan.output_level(TICK));
+        fan.set_speed(0.25);
+        assert_eq!((0..20).filter(|_| fan.output_level(TICK)).count(), 20);
+        assert!(!fan.is_kicking());
+        let on = (0..100).filter(|_| fan.output_level(TICK)).count();
+        assert!((39..=41).contains(&on), "on for {on} of 100 ticks");
+        assert!((fan.duty() - 0.4).abs() < 1e-6);
+
+        fan.set_speed(0.0);
+        assert!(!fan.output_level(TICK));
+        // Full speed needs no kick.
+        fan.set_speed(1.0);
+        assert!(fan.output_level(TICK));
+        assert!(!fan.is_kicking());
+        fan.set_speed(2.0);
+        assert_eq!(fan.speed(), 1.0);
+    }
+
+    #[test]
+    fn hot_or_failed_heater_runs_the_fan() {
+        let mut fan = Fan::new(FanConfig::default());
+        fan.update_heater(Some(Ok(60.0)));
+        assert!(fan.is_auto());
+        assert!((0..10).all(|_| fan.output_level(TICK)));
+        // Hysteresis keeps it on until the heater is 2 °C below the threshold.
+        fan.update_heater(Some(Ok(49.0)));
+        assert!(fan.is_auto());
+        fan.update_heater(Some(Ok(47.0)));
+        assert!(!fan.is_auto());
+        assert!(!fan.output_level(TICK));
+        fan.update_heater(Some(Err(SensorFault::Open)));
+        assert!(fan.is_auto());
+        fan.update_heater(None);
+        assert!(fan.is_auto());
+    }
+}
@@ -0,0 +1,409 @@
+//! Circular and helical arc interpolation for `G2` and `G3`.
+//!
+//! [`ArcPath`] locates an arc's centre from either `I`/`J`/`K` offsets or an `R` radius, rejects
+//! inconsistent geometry with the same checks and messages as LinuxCNC, and splits the arc into
+//! chords that stay within a tolerance of the true path. Axes outside the arc plane, including the
+//! helical axis normal to it, move linearly in proportion to the angle swept.
+
+use core::{
+    f32::consts::{PI, TAU},
+    fmt,
+};
+
+use crate::{
+    config::{AXES, AXIS_NAMES},
+    gcode::command::{ArcWords, Plane},
+};
+
+/// Letters of the centre-offset words in X, Y, Z order.
+const OFFSET_NAMES: [char; 3] = ['I', 'J', 'K'];
+/// Absolute radius tolerance in millimetres: smaller radii are zero, and start and end radii may
+/// differ by this much before the relative check applies.
+const RADIUS_TOLERANCE: f32 = 0.005;
+/// Relative difference allowed between start and end radii of a centre-format arc, which is then
+/// interpolated as a spiral.
+const SPIRAL_RELATIVE_TOLERANCE: f32 = 0.001;
+/// Multiple of [`RADIUS_TOLERANCE`] above which a radius difference is an error regardless of the
+/// arc's size.
+const SPIRAL_ABSOLUTE_LIMIT: f32 = 100.0;
+/// Smallest angle in radians one chord spans, which bounds a full circle at 3600 chords however
+/// small the tolerance.
+const MIN_CHORD_ANGLE: f32 = TAU / 3_600.0;
+
+/// Start, centre, and end of a rejected arc in program units, for error messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArcGeometry {
+    pub plane: Plane,
+    pub start: [f32; 2],
+    pub center: [f32; 2],
+    pub end: [f32; 2],
+    pub start_radius: f32,
+    pub end_radius: f32,
+}
+
+impl fmt::Display for ArcGeometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (first, second, _) = self.plane.axes();
+        let [a, b] = [AXIS_NAMES[first], AXIS_NAMES[second]];
+        let point = |[u, v]: [f32; 2]| format!("({a}{u:.4},{b}{v:.4})");
+        write!(
+            f,
+            "start={} center={} end={} r1={:.4} r2={:.4}",
+            point(self.start),
+            point(self.center),
+            point(self.end),
+            self.start_radius,
+            self.end_radius,
+        )
+    }
+}
+
+/// An arc that cannot be executed as programmed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArcError {
+    /// `I`, `J`, `K`, or `R` appeared without an active `G2` or `G3`.
+    WordWithoutArc(char),
+    /// Both `R` and centre offsets were programmed.
+    MixedRadiusAndOffsets,
+    /// Neither axis word of the arc plane was programmed.
+    PlaneAxesMissing(Plane),
+    /// A centre offset along the axis normal to the arc plane.
+    OffsetOutsidePlane(char, Plane),
+    /// Neither centre offset of the arc plane was programmed.
+    OffsetsMissing(Plane),
+    /// An `R` arc ends where it starts, which leaves its centre undefined.
+    SameStartAndEnd,
+    /// `R` is shorter than half the distance to the end point.
+    RadiusTooSmall,
+    /// The start or end point lies on the centre.
+    ZeroRadius(ArcGeometry),
+    /// The end point is not on the circle through the start point.
+    RadiusMismatch(ArcGeometry),
+}
+
+impl fmt::Display for ArcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WordWithoutArc(letter) => write!(f, "{letter} word with no G2 or G3 to use it"),
+            Self::MixedRadiusAndOffsets => f.write_str("Mixed radius-ijk format for arc"),
+            Self::PlaneAxesMissing(plane) => {
+                let (first, second, _) = plane.axes();
+                let [a, b] = [AXIS_NAMES[first], AXIS_NAMES[second]];
+                write!(f, "{a} and {b} words missing for arc in {a}{b} plane")
+            }
+            Self::OffsetOutsidePlane(letter, plane) => {
+                let (first, second, _) = plane.axes();
+                let [a, b] = [AXIS_NAMES[first], AXIS_NAMES[second]];
+                write!(f, "{letter} word given for arc in {a}{b} plane")
+            }
+            Self::OffsetsMissing(plane) => {
+                let (first, second, _) = plane.axes();
+                let [i, j] = [OFFSET_NAMES[first], OFFSET_NAMES[second]];
+                let [a, b] = [AXIS_NAMES[first], AXIS_NAMES[second]];
+                write!(f, "{i} and {j} words missing for arc in {a}{b} plane")
+            }
+            Self::SameStartAndEnd => f.write_str("Current point same as end point of arc"),
+            Self::RadiusTooSmall => f.write_str("Arc radius too small to reach end point"),
+            Self::ZeroRadius(geometry) => write!(f, "Zero-radius arc: {geometry}"),
+            Self::RadiusMismatch(geometry) => {
+                write!(
+                    f,
+                    "Radius to end of arc differs from radius to start: {geometry}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArcError {}
+
+/// A validated arc, split into chords.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArcPath {
+    start: [f32; AXES],
+    end: [f32; AXES],
+    plane: Plane,
+    center: [f32; 2],
+    start_angle: f32,
+    /// Signed angle swept in radians; negative sweeps are clockwise.
+    sweep: f32,
+    start_radius: f32,
+    end_radius: f32,
+    segments: u32,
+}
+
+impl ArcPath {
+    /// Validates an arc from `start` to `end`, both in millimetres.
+    ///
+    /// `words` are the line's centre words in program units, which `scale` converts to
+    /// millimetres. A centre-format arc whose end point equals its start point is a full circle.
+    /// Chords deviate from the true arc by at most `tolerance` millimetres.
+    pub fn new(
+        start: [f32; AXES],
+        end: [f32; AXES],
+        plane: Plane,
+        clockwise: bool,
+        words: &ArcWords,
+        scale: f32,
+        tolerance: f32,
+    ) -> Result<Self, ArcError> {
+        let (first, second, normal) = plane.axes();
+        let from = [start[first], start[second]];
+        let to = [end[first], end[second]];
+        let chord = (to[0] - from[0]).hypot(to[1] - from[1]);
+        let offsets = words.offsets();
+
+        let (center, start_radius, end_radius) = match words.r {
+            Some(_) if offsets.iter().any(Option::is_some) => {
+                return Err(ArcError::MixedRadiusAndOffsets);
+            }
+            Some(radius) => {
+                if chord < RADIUS_TOLERANCE {
+                    return Err(ArcError::SameStartAndEnd);
+                }
+                let radius = radius * scale;
+                let half_chord = chord / 2.0;
+                if half_chord - radius.abs() > RADIUS_TOLERANCE {
+                    return Err(ArcError::RadiusTooSmall);
+                }
+                // The centre lies on the chord's perpendicular bisector: left of the chord for a
+                // short counterclockwise arc, right for a short clockwise one. A negative radius
+                // selects the long way round.
+                let offset = (radius * radius - half_chord * half_chord).max(0.0).sqrt();
+                let side = if clockwise == (radius < 0.0) {
+                    1.0
+                } else {
+                    -1.0
+                };
+                let normal_x = -(to[1] - from[1]) / chord;
+                let normal_y = (to[0] - from[0]) / chord;
+                let center = [
+                    (from[0] + to[0]) / 2.0 + side * offset * normal_x,
+                    (from[1] + to[1]) / 2.0 + side * offset * normal_y,
+                ];
+                (center, radius.abs(), radius.abs())
+            }
+            None => {
+                if offsets[normal].is_some() {
+                    return Err(ArcError::OffsetOutsidePlane(OFFSET_NAMES[normal], plane));
+                }
+                let (i, j) = (offsets[first], offsets[second]);
+                if i.is_none() && j.is_none() {
+                    return Err(ArcError::OffsetsMissing(plane));
+                }
+                let center = [
+                    from[0] + i.unwrap_or(0.0) * scale,
+                    from[1] + j.unwrap_or(0.0) * scale,
+                ];
+                let start_radius = (from[0] - center[0]).hypot(from[1] - center[1]);
+                let end_radius = (to[0] - center[0]).hypot(to[1] - center[1]);
+
+                let geometry = ArcGeometry {
+                    plane,
+                    start: from.map(|value| value / scale),
+                    center: center.map(|value| value / scale),
+                    end: to.map(|value| value / scale),
+                    start_radius: start_radius / scale,
+                    end_radius: end_radius / scale,
+                };
+                if start_radius < RADIUS_TOLERANCE || end_radius < RADIUS_TOLERANCE {
+                    return Err(ArcError::ZeroRadius(geometry));
+                }
+                let absolute_error = (start_radius - end_radius).abs();
+                let relative_error = absolute_error / start_radius.max(end_radius);
+                if absolute_error > RADIUS_TOLERANCE * SPIRAL_ABSOLUTE_LIMIT
+                    || (relative_error > SPIRAL_RELATIVE_TOLERANCE
+                        && absolute_error > RADIUS_TOLERANCE)
+                {
+                    return Err(ArcError::RadiusMismatch(geometry));
+                }
+                (center, start_radius, end_radius)
+            }
+        };
+
+        let start_angle = (from[1] - center[1]).atan2(from[0] - center[0]);
+        let end_angle = (to[1] - center[1]).atan2(to[0] - center[0]);
+        let counterclockwise = (end_angle - start_angle).rem_euclid(TAU);
+        let sweep = match (chord < RADIUS_TOLERANCE, clockwise) {
+            (true, true) => -TAU,
+            (true, false) => TAU,
+            (false, true) => counterclockwise - TAU,
+            (false, false) => counterclockwise,
+        };
+
+        // A chord spanning angle θ on radius r deviates from the arc by r·(1 − cos(θ/2)).
+        let radius = start_radius.max(end_radius);
+        let chord_angle = if tolerance < radius {
+            (2.0 * (1.0 - tolerance / radius).acos()).max(MIN_CHORD_ANGLE)
+        } else {
+            PI
+        };
+        let segments = (sweep.abs() / chord_angle).ceil().max(1.0) as u32;
+
+        Ok(Self {
+            start,
+            end,
+            plane,
+            center,
+            start_angle,
+            sweep,
+            start_radius,
+            end_radius,
+            segments,
+        })
+    }
+
+    /// Returns the number of chords.
+    pub fn segments(&self) -> u32 {
+        self.segments
+    }
+
+    /// Returns the end point of chord `segment`, counting from 1. The last chord ends exactly at
+    /// the programmed end point.
+    pub fn point(&self, segment: u32) -> [f32; AXES] {
+        if segment >= self.segments {
+            return self.end;
+        }
+        let fraction = segment as f32 / self.segments as f32;
+        let mut point = [0.0; AXES];
+        for (axis, value) in point.iter_mut().enumerate() {
+            *value = self.start[axis] + (self.end[axis] - self.start[axis]) * fraction;
+        }
+        let (first, second, _) = self.plane.axes();
+        let angle = self.start_angle + self.sweep * fraction;
+        let radius = self.start_radius + (self.end_radius - self.start_radius) * fraction;
+        point[first] = self.center[0] + radius * angle.cos();
+        point[second] = self.center[1] + radius * angle.sin();
+        point
+    }
+
+    /// Returns the path length in millimetres, combining the arc with the linear motion of the
+    /// other axes.
+    pub fn length(&self) -> f32 {
+        let (first, second, _) = self.plane.axes();
+        let planar = (self.start_radius + self.end_radius) / 2.0 * self.sweep.abs();
+        let linear = (0..AXES)
+            .filter(|axis| *axis != first && *axis != second)
+            .map(|axis| (self.end[axis] - self.start[axis]).powi(2))
+            .sum::<f32>();
+        (planar * planar + linear).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: [f32; AXES] = [0.0; AXES];
+
+    fn offsets(i: f32, j: f32) -> ArcWords {
+        ArcWords {
+            i: Some(i),
+            j: Some(j),
+            ..ArcWords::default()
+        }
+    }
+
+    fn radius(r: f32) -> ArcWords {
+        ArcWords {
+            r: Some(r),
+            ..ArcWords::default()
+        }
+    }
+
+    fn close(a: [f32; AXES], b: [f32; AXES]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3)
+    }
+
+    #[test]
+    fn chords_stay_within_tolerance() {
+        let end = [20.0, 0.0, 0.0, 0.0];
+        let arc = ArcPath::new(
+            ORIGIN,
+            end,
+            Plane::Xy,
+            false,
+            &offsets(10.0, 0.0),
+            1.0,
+            0.01,
+        )
+        .unwrap();
+        // A half circle of radius 10 needs chords of at most 2·acos(0.999) ≈ 0.0894 rad.
+        assert_eq!(arc.segments(), 36);
+        assert!((arc.length() - 10.0 * PI).abs() < 1e-3);
+        assert!(close(arc.point(18), [10.0, -10.0, 0.0, 0.0]));
+        assert_eq!(arc.point(arc.segments()), end);
+    }
+
+    #[test]
+    fn tiny_tolerance_is_bounded() {
+        let full_circle = |tolerance| {
+            ArcPath::new(
+                ORIGIN,
+                ORIGIN,
+                Plane::Xy,
+                true,
+                &offsets(5.0, 0.0),
+                1.0,
+                tolerance,
+            )
+        };
+        assert_eq!(full_circle(0.0).unwrap().segments(), 3_600);
+        assert_eq!(full_circle(-1.0).unwrap().segments(), 3_600);
+        assert_eq!(full_circle(1e-9).unwrap().segments(), 3_600);
+        assert_eq!(full_circle(50.0).unwrap().segments(), 2);
+    }
+
+    #[test]
+    fn radius_format_picks_the_centre_side() {
+        let end = [10.0, 0.0, 0.0, 0.0];
+        let highest = |arc: &ArcPath| {
+            (1..=arc.segments())
+                .map(|segment| arc.point(segment)[1])
+                .fold(f32::NEG_INFINITY, f32::max)
+        };
+        // Clockwise from left to right, the short arc's centre lies below the chord and the long
+        // arc's above it; both bulge upward.
+        let short = ArcPath::new(ORIGIN, end, Plane::Xy, true, &radius(8.0), 1.0, 0.01).unwrap();
+        let long = ArcPath::new(ORIGIN, end, Plane::Xy, true, &radius(-8.0), 1.0, 0.01).unwrap();
+        let sagitta = 8.0 - 39.0_f32.sqrt();
+        assert!((highest(&short) - sagitta).abs() < 0.01);
+        assert!((highest(&long) - (16.0 - sagitta)).abs() < 0.01);
+        assert!(short.length() < 8.0 * PI);
+        assert!(long.length() > 8.0 * PI);
+    }
+
+    #[test]
+    fn helical_axis_moves_linearly() {
+        let end = [0.0, 0.0, 4.0, 0.0];
+        let arc =
+            ArcPath::new(ORIGIN, end, Plane::Xy, false, &offsets(2.0, 0.0), 1.0, 0.01).unwrap();
+        let quarter = arc.segments() / 4;
+        assert!(
+            (arc.point(quarter)[2] - 4.0 * quarter as f32 / arc.segments() as f32).abs() < 1e-4
+        );
+    }
+
+    #[test]
+    fn inconsistent_arcs_are_rejected() {
+        let end = [10.0, 0.0, 0.0, 0.0];
+        let error = |words: &ArcWords, end| {
+            ArcPath::new(ORIGIN, end, Plane::Xy, true, words, 1.0, 0.01).unwrap_err()
+        };
+        assert_eq!(error(&radius(4.0), end), ArcError::RadiusTooSmall);
+        assert_eq!(error(&radius(4.0), ORIGIN), ArcError::SameStartAndEnd);
+        assert_eq!(
+            error(&ArcWords::default(), end),
+            ArcError::OffsetsMissing(Plane::Xy)
+        );
+        assert!(matches!(
+            error(&offsets(3.0, 0.0), end),
+            ArcError::RadiusMismatch(_)
+        ));
+        let mixed = ArcWords {
+            i: Some(5.0),
+            ..radius(5.0)
+        };
+        assert_eq!(error(&mixed, end), ArcError::MixedRadiusAndOffsets);
+    }
+}
@@ -0,0 +1,472 @@
+//! Decoding of tokenized lines into NIST RS274/NGC modal groups.
+
+use crate::gcode::parser::{Line, ParseError, ParseErrorKind};
+
+/// Modal group 0 codes that take effect only on the line where they appear.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NonModal {
+    /// `G28`, run the homing cycle for the axes named by axis words, or for every axis.
+    Home,
+    /// `G92`, redefine the current position without moving.
+    SetPosition,
+    /// `G53`, the axis words of this line's `G0` or `G1` are absolute machine coordinates.
+    MachineCoordinates,
+}
+
+/// Modal group 1 motion modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MotionMode {
+    /// `G0`, traverse at the machine's rapid rate.
+    Rapid,
+    /// `G1`, interpolate at the programmed feed rate.
+    Linear,
+    /// `G2`, clockwise arc at the programmed feed rate.
+    ArcClockwise,
+    /// `G3`, counterclockwise arc at the programmed feed rate.
+    ArcCounterclockwise,
+    /// `G80`, no motion mode is active and axis words are an error.
+    Cancel,
+}
+
+/// Modal group 2 arc planes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Plane {
+    /// `G17`
+    Xy,
+    /// `G18`
+    Zx,
+    /// `G19`
+    Yz,
+}
+
+impl Plane {
+    /// Returns the indices of the plane's first and second axes and of the axis normal to it.
+    ///
+    /// The axes are ordered so that a counterclockwise arc turns from the first axis toward the
+    /// second when viewed from the positive end of the normal axis.
+    pub fn axes(self) -> (usize, usize, usize) {
+        match self {
+            Self::Xy => (0, 1, 2),
+            Self::Zx => (2, 0, 1),
+            Self::Yz => (1, 2, 0),
+        }
+    }
+}
+
+/// Modal group 3 distance modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DistanceMode {
+    /// `G90`, axis words are coordinates.
+    Absolute,
+    /// `G91`, axis words are offsets from the current position.
+    Incremental,
+}
+
+/// Modal group 5 feed-rate modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedRateMode {
+    /// `G93`, `F` is the reciprocal of the move's duration in minutes.
+    InverseTime,
+    /// `G94`, `F` is a speed in units per minute.
+    UnitsPerMinute,
+}
+
+/// Modal group 6 length units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Units {
+    /// `G20`
+    Inches,
+    /// `G21`
+    Millimetres,
+}
+
+impl Units {
+    /// Returns the number of millimetres in one unit.
+    pub fn millimetres(self) -> f32 {
+        match self {
+            Self::Inches => 25.4,
+            Self::Millimetres => 1.0,
+        }
+    }
+}
+
+/// M-codes outside the RS274/NGC modal groups. At most one may appear on a line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MCode {
+    /// `M220`, set the feed override to `S` percent.
+    FeedOverride,
+    /// `M221`, set the spindle override to `S` percent.
+    SpindleOverride,
+    /// `M104`, set the hotend selected by `T`, or hotend 0, to `S` °C.
+    HotendTemperature,
+    /// `M109`, set a hotend like `M104` and hold later lines until it has heated to `S` °C, or
+    /// until it has heated or cooled to `R` °C.
+    WaitForHotend,
+    /// `M140`, set the bed to `S` °C.
+    BedTemperature,
+    /// `M190`, set the bed like `M140` and wait like `M109`.
+    WaitForBed,
+    /// `M303`, tune the PID gains of heater `E`, hotend 0 by default or -1 for the bed, by
+    /// oscillating it around `S` °C for `C` cycles, then apply them if `U1` is given.
+    Autotune,
+    /// `M106`, run fan `P`, fan 0 by default, at speed `S` from 0 to 255, full speed by default.
+    FanSpeed,
+    /// `M107`, stop fan `P`, fan 0 by default.
+    FanOff,
+}
+
+impl MCode {
+    /// Returns `true` for the codes that wait for a temperature, which accept `R` in place of `S`.
+    pub fn waits_for_temperature(self) -> bool {
+        matches!(self, Self::WaitForHotend | Self::WaitForBed)
+    }
+
+    /// Returns `true` for the codes that address a fan by `P`.
+    pub fn controls_fan(self) -> bool {
+        matches!(self, Self::FanSpeed | Self::FanOff)
+    }
+}
+
+/// Optional coordinates supplied by a line's axis words.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AxisWords {
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub z: Option<f32>,
+    pub e: Option<f32>,
+}
+
+impl AxisWords {
+    /// Returns the words in X, Y, Z, E order.
+    pub fn to_array(self) -> [Option<f32>; 4] {
+        [self.x, self.y, self.z, self.e]
+    }
+
+    /// Returns `true` when the line contained no axis words.
+    pub fn is_empty(&self) -> bool {
+        self.to_array().iter().all(Option::is_none)
+    }
+}
+
+/// Arc centre words. `I`, `J`, and `K` are offsets from the start point along X, Y, and Z.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ArcWords {
+    pub i: Option<f32>,
+    pub j: Option<f32>,
+    pub k: Option<f32>,
+    pub r: Option<f32>,
+}
+
+impl ArcWords {
+    /// Returns the centre offsets in X, Y, Z order.
+    pub fn offsets(self) -> [Option<f32>; 3] {
+        [self.i, self.j, self.k]
+    }
+
+    /// Returns `true` when the line contained no arc words.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The words of one line grouped by modal group, before any modal state is applied.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Command {
+    pub non_modal: Option<NonModal>,
+    pub motion: Option<MotionMode>,
+    pub plane: Option<Plane>,
+    pub distance_mode: Option<DistanceMode>,
+    pub feed_rate_mode: Option<FeedRateMode>,
+    pub units: Option<Units>,
+    pub axes: AxisWords,
+    /// Column of the first axis word, for errors raised while executing the command.
+    pub axes_column: Option<usize>,
+    pub arc: ArcWords,
+    /// `F` value in the line's units or inverse minutes.
+    pub feed_rate: Option<f32>,
+    pub m_code: Option<MCode>,
+    /// `S` value, whose meaning depends on the M-code.
+    pub s: Option<f32>,
+    /// `T` value, the hotend addressed by a temperature M-code.
+    pub t: Option<f32>,
+    /// The temperature of `M109` or `M190` was given by `R`, so the wait also covers cooling.
+    pub wait_for_cooling: bool,
+    /// `E` value of `M303`, which names a heater instead of moving the extruder.
+    pub heater: Option<f32>,
+    /// `C` value, the cycle count of `M303`.
+    pub c: Option<f32>,
+    /// `U` value, whether `M303` applies its result.
+    pub u: Option<f32>,
+    /// `P` value, the fan addressed by `M106` or `M107`.
+    pub p: Option<f32>,
+}
+
+impl Command {
+    /// Returns `true` when the line contained no words to execute.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Line {
+    /// Groups the line's words by modal group.
+    ///
+    /// Two G-codes from the same modal group, a repeated non-G word, or axis words claimed by
+    /// both a non-modal code and a motion code are errors as required by the NIST RS274/NGC
+    /// specification.
+    pub fn command(&self) -> Result<Command, ParseError> {
+        let mut command = Command::default();
+        let mut non_modal_column = None;
+        let mut m_code_column = None;
+        let mut s_column = None;
+        let mut t_column = None;
+        let mut r_column = None;
+        let mut p_column = None;
+        let mut autotune_word = None;
+
+        for word in &self.words {
+            let duplicate =
+                ParseError::new(ParseErrorKind::DuplicateWord(word.letter), word.column);
+            let slot = match word.letter {
+                'G' => {
+                    let non_modal = command.non_modal;
+                    decode_g_code(&mut command, word.value, word.column)?;
+                    if command.non_modal != non_modal {
+                        non_modal_column = Some(word.column);
+                    }
+                    continue;
+                }
+                'M' => {
+                    if command.m_code.is_some() {
+                        return Err(duplicate);
+                    }
+                    command.m_code = Some(decode_m_code(word.value, word.column)?);
+                    m_code_column = Some(word.column);
+                    continue;
+                }
+                'S' => {
+                    s_column = Some(word.column);
+                    &mut command.s
+                }
+                'T' => {
+                    t_column = Some(word.column);
+                    &mut command.t
+                }
+                'C' => {
+                    autotune_word.get_or_insert((word.letter, word.column));
+                    &mut command.c
+                }
+                'U' => {
+                    autotune_word.get_or_insert((word.letter, word.column));
+                    &mut command.u
+                }
+                'P' => {
+                    p_column = Some(word.column);
+                    &mut command.p
+                }
+                'F' => &mut command.feed_rate,
+                'X' => &mut command.axes.x,
+                'Y' => &mut command.axes.y,
+                'Z' => &mut command.axes.z,
+                'E' => &mut command.axes.e,
+                'I' => &mut command.arc.i,
+                'J' => &mut command.arc.j,
+                'K' => &mut command.arc.k,
+                'R' => {
+                    r_column = Some(word.column);
+                    &mut command.arc.r
+                }
+                letter => {
+                    return Err(ParseError::new(
+                        ParseErrorKind::UnsupportedWord(letter),
+                        word.column,
+                    ));
+                }
+            };
+            if slot.replace(word.value).is_some() {
+                return Err(duplicate);
+            }
+            if matches!(word.letter, 'X' | 'Y' | 'Z' | 'E') {
+                command.axes_column.get_or_insert(word.column);
+            }
+        }
+
+        if let Some(column) = non_modal_column {
+            if command.non_modal == Some(NonModal::SetPosition) && command.axes.is_empty() {
+                return Err(ParseError::new(ParseErrorKind::MissingAxisWords, column));
+            }
+            if !command.axes.is_empty()
+                && command.non_modal != Some(NonModal::MachineCoordinates)
+                && !matches!(command.motion, None | Some(MotionMode::Cancel))
+            {
+                return Err(ParseError::new(ParseErrorKind::AxisWordConflict, column));
+            }
+        }
+
+        // Temperature waits take R in place of S for a target that may be reached by cooling.
+        if command.m_code.is_some_and(MCode::waits_for_temperature) {
+            match (command.arc.r.take(), r_column) {
+                (Some(_), Some(column)) if command.s.is_some() => {
+                    return Err(ParseError::new(
+                        ParseErrorKind::ExclusiveWords('S', 'R'),
+                        column,
+                    ));
+                }
+                (Some(target), _) => {
+                    command.s = Some(target);
+                    command.wait_for_cooling = true;
+                    s_column = r_column;
+                }
+                (None, _) => {}
+            }
+        }
+        // M303 takes E as a heater number rather than an extruder move.
+        if command.m_code == Some(MCode::Autotune) {
+            command.heater = command.axes.e.take();
+            if command.axes.is_empty() {
+                command.axes_column = None;
+            }
+        } else if let Some((letter, column)) = autotune_word {
+            return Err(ParseError::new(
+                ParseErrorKind::UnsupportedWord(letter),
+                column,
+            ));
+        }
+        let hotend = matches!(
+            command.m_code,
+            Some(MCode::HotendTemperature | MCode::WaitForHotend)
+        );
+        if let (false, Some(column)) = (hotend, t_column) {
+            return Err(ParseError::new(
+                ParseErrorKind::UnsupportedWord('T'),
+                column,
+            ));
+        }
+        let fan = command.m_code.is_some_and(MCode::controls_fan);
+        if let (false, Some(column)) = (fan, p_column) {
+            return Err(ParseError::new(
+                ParseErrorKind::UnsupportedWord('P'),
+                column,
+            ));
+        }
+
+        // `M106` defaults to full speed, and `M107` has no speed.
+        match (command.m_code, m_code_column, s_column) {
+            (Some(MCode::FanSpeed | MCode::FanOff), _, None) => {}
+            (Some(_), Some(column), None) => {
+                return Err(ParseError::new(ParseErrorKind::MissingWord('S'), column));
+            }
+            (None | Some(MCode::FanOff), _, Some(column)) => {
+                return Err(ParseError::new(
+                    ParseErrorKind::UnsupportedWord('S'),
+                    column,
+                ));
+            }
+            _ => {}
+        }
+
+        Ok(command)
+    }
+}
+
+/// Decodes one of the supported M-codes.
+fn decode_m_code(value: f32, column: usize) -> Result<MCode, ParseError> {
+    let unsupported = ParseError::new(ParseErrorKind::UnsupportedCode('M', value), column);
+    if value.fract() != 0.0 || value < 0.0 {
+        return Err(unsupported);
+    }
+    match value as u16 {
+        104 => Ok(MCode::HotendTemperature),
+        109 => Ok(MCode::WaitForHotend),
+        140 => Ok(MCode::BedTemperature),
+        190 => Ok(MCode::WaitForBed),
+        303 => Ok(MCode::Autotune),
+        106 => Ok(MCode::FanSpeed),
+        107 => Ok(MCode::FanOff),
+        220 => Ok(MCode::FeedOverride),
+        221 => Ok(MCode::SpindleOverride),
+        _ => Err(unsupported),
+    }
+}
+
+/// Stores one G-code in its modal group, rejecting a second code from the same group.
+fn decode_g_code(modal: &mut Command, value: f32, column: usize) -> Result<(), ParseError> {
+    /// Fills a modal-group slot, returning the group number if it was already occupied.
+    fn set<T>(slot: &mut Option<T>, value: T, group: u8) -> Result<(), u8> {
+        match slot.replace(value) {
+            Some(_) => Err(group),
+            None => Ok(()),
+        }
+    }
+
+    let unsupported = ParseError::new(ParseErrorKind::UnsupportedCode('G', value), column);
+    if value.fract() != 0.0 || value < 0.0 {
+        return Err(unsupported);
+    }
+    let result = match value as u16 {
+        28 => set(&mut modal.non_modal, NonModal::Home, 0),
+        53 => set(&mut modal.non_modal, NonModal::MachineCoordinates, 0),
+        92 => set(&mut modal.non_modal, NonModal::SetPosition, 0),
+        0 => set(&mut modal.motion, MotionMode::Rapid, 1),
+        1 => set(&mut modal.motion, MotionMode::Linear, 1),
+        2 => set(&mut modal.motion, MotionMode::ArcClockwise, 1),
+        3 => set(&mut modal.motion, MotionMode::ArcCounterclockwise, 1),
+        80 => set(&mut modal.motion, MotionMode::Cancel, 1),
+        17 => set(&mut modal.plane, Plane::Xy, 2),
+        18 => set(&mut modal.plane, Plane::Zx, 2),
+        19 => set(&mut modal.plane, Plane::Yz, 2),
+        90 => set(&mut modal.distance_mode, DistanceMode::Absolute, 3),
+        91 => set(&mut modal.distance_mode, DistanceMode::Incremental, 3),
+        93 => set(&mut modal.feed_rate_mode, FeedRateMode::InverseTime, 5),
+        94 => set(&mut modal.feed_rate_mode, FeedRateMode::UnitsPerMinute, 5),
+        20 => set(&mut modal.units, Units::Inches, 6),
+        21 => set(&mut modal.units, Units::Millimetres, 6),
+        _ => return Err(unsupported),
+    };
+    result.map_err(|group| ParseError::new(ParseErrorKind::ModalGroupConflict(group), column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::parse_line;
+
+    fn command(source: &str) -> Result<Command, ParseError> {
+        parse_line(source).unwrap().command()
+    }
+
+    #[test]
+    fn words_grouped_by_modal_group() {
+        let command = command("G91 G1 G21 X1 Y-2 F100").unwrap();
+        assert_eq!(command.motion, Some(MotionMode::Linear));
+        assert_eq!(command.distance_mode, Some(DistanceMode::Incremental));
+        assert_eq!(command.units, Some(Units::Millimetres));
+        assert_eq!(command.axes.to_array(), [Some(1.0), Some(-2.0), None, None]);
+        assert_eq!(command.axes_column, Some(12));
+        assert_eq!(command.feed_rate, Some(100.0));
+    }
+
+    #[test]
+    fn conflicting_words() {
+        let kind = |source| command(source).unwrap_err().kind;
+        assert_eq!(kind("G0 G1 X1"), ParseErrorKind::ModalGroupConflict(1));
+        assert_eq!(kind("G1 X1 X2"), ParseErrorKind::DuplicateWord('X'));
+        assert_eq!(kind("G92"), ParseErrorKind::MissingAxisWords);
+        assert_eq!(kind("G1 G92 X1"), ParseErrorKind::AxisWordConflict);
+        assert_eq!(kind("G0 G28 Z0"), ParseErrorKind::AxisWordConflict);
+        assert_eq!(kind("G53 G92 X1"), ParseErrorKind::ModalGroupConflict(0));
+    }
+
+    #[test]
+    fn non_modal_code_without_axis_words_keeps_motion_mode() {
+        let command = command("G1 G28").unwrap();
+        assert_eq!(command.non_modal, Some(NonModal::Home));
+        assert_eq!(command.motion, Some(MotionMode::Linear));
+        assert!(command.axes.is_empty());
+
+        // G53 qualifies the motion's axis words rather than competing for them.
+        let machine = self::command("G53 G0 X1").unwrap();
+        assert_eq!(machine.non_modal, Some(NonModal::MachineCoordinates));
+        assert_eq!(machine.motion, Some(MotionMode::Rapid));
+    }
+}
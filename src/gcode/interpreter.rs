@@ -0,0 +1,922 @@
+//! Modal G-code interpreter that turns decoded commands into planner moves.
+
+use core::{fmt, ops::RangeInclusive};
+
+use crate::{
+    gcode::{
+        arc::{ArcError, ArcPath},
+        command::{Command, DistanceMode, FeedRateMode, MCode, MotionMode, NonModal, Plane, Units},
+        parser::{ParseError, parse_line},
+    },
+    planner::{Overrides, Planner, PlannerError},
+};
+
+/// An error raised while parsing or executing a line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InterpreterError {
+    Parse(ParseError),
+    /// Axis words were programmed while `G80` was active.
+    AxisWordsWithoutMotion {
+        column: usize,
+    },
+    /// A `G2`/`G3` arc or its words were programmed inconsistently.
+    Arc(ArcError),
+    /// A feed move was programmed before any feed rate was set.
+    FeedRateUndefined,
+    /// `F` was zero or negative.
+    InvalidFeedRate,
+    /// A `$J=` jog line lacked axis words or `F`, or contained other words.
+    InvalidJog,
+    /// `G53` was programmed while neither `G0` nor `G1` was active.
+    MachineCoordinatesWithoutLine,
+    /// An `M220` or `M221` percentage outside the override's range.
+    OverrideOutOfRange {
+        min: u16,
+        max: u16,
+    },
+    /// A temperature M-code's `S` or `R` was negative.
+    InvalidTemperature,
+    /// `T` was not a hotend number.
+    InvalidTool,
+    /// The `E` of `M303` was neither a hotend number nor -1 for the bed.
+    InvalidHeater,
+    /// The `C` of `M303` was outside the supported cycle counts.
+    InvalidCycles {
+        min: u32,
+        max: u32,
+    },
+    /// The `P` of `M106` or `M107` was not a fan number.
+    InvalidFan,
+    /// The `S` of `M106` was outside 0 to 255.
+    InvalidFanSpeed,
+    /// The move would leave the travel of the axis.
+    SoftLimit(usize),
+    /// The move would drive an axis that has no stepper output.
+    NoStepper(usize),
+    /// The planner has no free slot; the line was not applied.
+    QueueFull,
+}
+
+impl fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(error) => error.fmt(f),
+            Self::AxisWordsWithoutMotion { column } => {
+                write!(
+                    f,
+                    "column {column}: axis words require an active motion mode"
+                )
+            }
+            Self::Arc(error) => error.fmt(f),
+            Self::FeedRateUndefined => f.write_str("feed move without a feed rate"),
+            Self::InvalidFeedRate => f.write_str("feed rate must be positive"),
+            Self::InvalidJog => f.write_str(
+                "jog needs axis words and F, and accepts only G20, G21, G53, G90, and G91 besides",
+            ),
+            Self::MachineCoordinatesWithoutLine => f.write_str("G53 requires G0 or G1"),
+            Self::OverrideOutOfRange { min, max } => {
+                write!(f, "override must be from {min}% to {max}%")
+            }
+            Self::InvalidTemperature => f.write_str("temperature must not be negative"),
+            Self::InvalidTool => f.write_str("T must be a hotend number"),
+            Self::InvalidHeater => f.write_str("E must be a hotend number, or -1 for the bed"),
+            Self::InvalidCycles { min, max } => {
+                write!(f, "C must be from {min} to {max} cycles")
+            }
+            Self::InvalidFan => f.write_str("P must be a fan number"),
+            Self::InvalidFanSpeed => write!(f, "S must be from 0 to {}", FanRequest::FULL_SPEED),
+            Self::SoftLimit(axis) => PlannerError::SoftLimit(*axis).fmt(f),
+            Self::NoStepper(axis) => PlannerError::NoStepper(*axis).fmt(f),
+            Self::QueueFull => f.write_str("motion queue full"),
+        }
+    }
+}
+
+impl std::error::Error for InterpreterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Parse(error) => Some(error),
+            Self::Arc(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseError> for InterpreterError {
+    fn from(error: ParseError) -> Self {
+        Self::Parse(error)
+    }
+}
+
+impl From<PlannerError> for InterpreterError {
+    fn from(error: PlannerError) -> Self {
+        match error {
+            PlannerError::QueueFull => Self::QueueFull,
+            PlannerError::SoftLimit(axis) => Self::SoftLimit(axis),
+            PlannerError::NoStepper(axis) => Self::NoStepper(axis),
+        }
+    }
+}
+
+impl From<ArcError> for InterpreterError {
+    fn from(error: ArcError) -> Self {
+        Self::Arc(error)
+    }
+}
+
+/// The heater addressed by a temperature M-code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaterSelect {
+    /// A hotend by its `T` number.
+    Hotend(u8),
+    Bed,
+}
+
+/// How long `M109` or `M190` holds back later lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TemperatureWait {
+    /// Until the heater is at or above the target, given by `S`. A heater that is already hotter
+    /// does not wait.
+    UntilHeated,
+    /// Until the heater is at the target from either side, given by `R`.
+    UntilReached,
+}
+
+/// A target set by `M104`, `M109`, `M140`, or `M190`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureRequest {
+    pub heater: HeaterSelect,
+    /// Target in °C, where zero turns the heater off.
+    pub celsius: f32,
+    pub wait: Option<TemperatureWait>,
+}
+
+/// A tuning run requested by `M303`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutotuneRequest {
+    pub heater: HeaterSelect,
+    /// Temperature in °C to oscillate around.
+    pub celsius: f32,
+    pub cycles: u32,
+    /// Apply the resulting gains once tuning completes.
+    pub apply: bool,
+}
+
+impl AutotuneRequest {
+    /// Cycles run when `C` is omitted.
+    pub const DEFAULT_CYCLES: u32 = 5;
+    /// Accepted values of `C`.
+    pub const CYCLE_RANGE: RangeInclusive<u32> = 3..=20;
+}
+
+/// A fan speed set by `M106` or `M107`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FanRequest {
+    /// The fan's `P` number.
+    pub fan: u8,
+    /// Speed from 0 to 1.
+    pub speed: f32,
+}
+
+impl FanRequest {
+    /// `S` value of full speed, as in Marlin.
+    pub const FULL_SPEED: u8 = 255;
+}
+
+/// Chords of an accepted arc that have not fit into the planner yet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PendingArc {
+    arc: ArcPath,
+    /// Next chord to queue, counting from 1.
+    next_segment: u32,
+    /// Feed rate of every chord in millimetres per minute.
+    feed_rate: f32,
+}
+
+/// Modal state carried from one line to the next.
+///
+/// Lengths are stored in millimetres regardless of the active [`Units`]; words are converted as
+/// each line is executed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interpreter {
+    pub motion_mode: MotionMode,
+    pub plane: Plane,
+    pub distance_mode: DistanceMode,
+    pub feed_rate_mode: FeedRateMode,
+    pub units: Units,
+    /// Last programmed units-per-minute feed rate in millimetres per minute, or zero if unset.
+    pub feed_rate: f32,
+    /// Machine position in X, Y, Z, E order, in millimetres.
+    pub position: [f32; 4],
+    /// Work offset set by `G92` in X, Y, Z, E order, in millimetres. Absolute axis words are
+    /// measured from it, while the planner and soft limits keep to machine coordinates.
+    pub offset: [f32; 4],
+    pending_arc: Option<PendingArc>,
+    /// Axes named by a `G28` that has not been taken by the caller yet.
+    homing_request: Option<u8>,
+    /// Target set by the last temperature M-code that has not been taken by the caller yet.
+    temperature_request: Option<TemperatureRequest>,
+    /// Tuning run requested by the last `M303` that has not been taken by the caller yet.
+    autotune_request: Option<AutotuneRequest>,
+    /// Speed set by the last `M106` or `M107` that has not been taken by the caller yet.
+    fan_request: Option<FanRequest>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self {
+            motion_mode: MotionMode::Cancel,
+            plane: Plane::Xy,
+            distance_mode: DistanceMode::Absolute,
+            feed_rate_mode: FeedRateMode::UnitsPerMinute,
+            units: Units::Millimetres,
+            feed_rate: 0.0,
+            position: [0.0; 4],
+            offset: [0.0; 4],
+            pending_arc: None,
+            homing_request: None,
+            temperature_request: None,
+            autotune_request: None,
+            fan_request: None,
+        }
+    }
+}
+
+impl Interpreter {
+    /// Creates an interpreter in the power-on modal state: `G80 G17 G90 G94 G21` at the origin.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses and executes one line of G-code.
+    pub fn execute_line(
+        &mut self,
+        source: &str,
+        planner: &mut Planner,
+    ) -> Result<(), InterpreterError> {
+        let command = parse_line(source)?.command()?;
+        self.execute(&command, planner)
+    }
+
+    /// Returns the position in work coordinates, as programmed after the last `G92`.
+    pub fn work_position(&self) -> [f32; 4] {
+        [0, 1, 2, 3].map(|axis| self.position[axis] - self.offset[axis])
+    }
+
+    /// Returns `true` while chords of an accepted arc are waiting for planner space.
+    pub fn has_pending_arc(&self) -> bool {
+        self.pending_arc.is_some()
+    }
+
+    /// Queues as many waiting arc chords as the planner accepts. Returns `true` once none remain.
+    pub fn queue_pending_arc(&mut self, planner: &mut Planner) -> bool {
+        let Some(pending) = &mut self.pending_arc else {
+            return true;
+        };
+        while pending.next_segment <= pending.arc.segments() {
+            let [x, y, z, e] = pending.arc.point(pending.next_segment);
+            if planner.buffer_line(x, y, z, e, pending.feed_rate).is_err() {
+                return false;
+            }
+            pending.next_segment += 1;
+        }
+        self.pending_arc = None;
+        true
+    }
+
+    /// Drops waiting arc chords and continues from the machine `position` in millimetres, after
+    /// queued motion was abandoned before reaching its end. The work offset is kept.
+    pub fn abandon_motion(&mut self, position: [f32; 4]) {
+        self.pending_arc = None;
+        self.position = position;
+    }
+
+    /// Returns and clears the axes requested by the last `G28`, as a bit mask in X, Y, Z, E order
+    /// where zero asks for every homing axis.
+    ///
+    /// The interpreter cannot home the machine itself; the caller runs the cycle once queued
+    /// motion has finished and then updates [`Self::position`].
+    pub fn take_homing_request(&mut self) -> Option<u8> {
+        self.homing_request.take()
+    }
+
+    /// Returns and clears the target set by the last temperature M-code.
+    ///
+    /// Heaters are outside the interpreter; the caller validates the heater and target, drives
+    /// the heater towards it, and holds back later lines while a requested wait is pending.
+    pub fn take_temperature_request(&mut self) -> Option<TemperatureRequest> {
+        self.temperature_request.take()
+    }
+
+    /// Returns and clears the tuning run requested by the last `M303`, which the caller runs in
+    /// place of the heater's regulation.
+    pub fn take_autotune_request(&mut self) -> Option<AutotuneRequest> {
+        self.autotune_request.take()
+    }
+
+    /// Returns and clears the fan speed set by the last `M106` or `M107`, which the caller
+    /// applies at once rather than in step with queued motion.
+    pub fn take_fan_request(&mut self) -> Option<FanRequest> {
+        self.fan_request.take()
+    }
+
+    /// Queues the linear jog described by the words of a `$J=` line.
+    ///
+    /// As in Grbl, `G20`/`G21`, `G53`, and `G90`/`G91` apply to the jog alone, `F` is required and
+    /// always in units per minute, and the modal state is left unchanged apart from the position,
+    /// which moves to the jog's target.
+    pub fn jog(
+        &mut self,
+        command: &Command,
+        planner: &mut Planner,
+    ) -> Result<(), InterpreterError> {
+        let machine_coordinates = command.non_modal == Some(NonModal::MachineCoordinates);
+        let allowed = Command {
+            non_modal: command.non_modal.filter(|_| machine_coordinates),
+            units: command.units,
+            distance_mode: command.distance_mode,
+            axes: command.axes,
+            axes_column: command.axes_column,
+            feed_rate: command.feed_rate,
+            ..Command::default()
+        };
+        let Some(feed_rate) = command.feed_rate else {
+            return Err(InterpreterError::InvalidJog);
+        };
+        if *command != allowed || command.axes.is_empty() {
+            return Err(InterpreterError::InvalidJog);
+        }
+        if feed_rate <= 0.0 {
+            return Err(InterpreterError::InvalidFeedRate);
+        }
+        if !self.queue_pending_arc(planner) {
+            return Err(InterpreterError::QueueFull);
+        }
+
+        let scale = command.units.unwrap_or(self.units).millimetres();
+        let distance_mode = command.distance_mode.unwrap_or(self.distance_mode);
+        let mut target = self.position;
+        for (axis, word) in command.axes.to_array().into_iter().enumerate() {
+            if let Some(value) = word {
+                target[axis] = match (machine_coordinates, distance_mode) {
+                    (true, _) => value * scale,
+                    (false, DistanceMode::Absolute) => value * scale + self.offset[axis],
+                    (false, DistanceMode::Incremental) => self.position[axis] + value * scale,
+                };
+            }
+        }
+        let [x, y, z, e] = target;
+        self.position = planner.buffer_jog(x, y, z, e, feed_rate * scale)?;
+        Ok(())
+    }
+
+    /// Applies one decoded line in NIST execution order and queues any resulting move.
+    ///
+    /// The interpreter state is left unchanged when an error is returned, so a rejected line can
+    /// be resubmitted once the cause has been resolved. An arc is accepted as soon as it is
+    /// validated; chords that do not fit into the planner wait for [`Self::queue_pending_arc`],
+    /// and later lines report [`InterpreterError::QueueFull`] until they have been queued.
+    pub fn execute(
+        &mut self,
+        command: &Command,
+        planner: &mut Planner,
+    ) -> Result<(), InterpreterError> {
+        if !self.queue_pending_arc(planner) {
+            return Err(InterpreterError::QueueFull);
+        }
+        let mut next = *self;
+
+        if let Some(feed_rate_mode) = command.feed_rate_mode {
+            next.feed_rate_mode = feed_rate_mode;
+        }
+        if let Some(units) = command.units {
+            next.units = units;
+        }
+        let scale = next.units.millimetres();
+
+        let mut inverse_time = None;
+        if let Some(feed_rate) = command.feed_rate {
+            if feed_rate <= 0.0 {
+                return Err(InterpreterError::InvalidFeedRate);
+            }
+            match next.feed_rate_mode {
+                FeedRateMode::UnitsPerMinute => next.feed_rate = feed_rate * scale,
+                FeedRateMode::InverseTime => inverse_time = Some(feed_rate),
+            }
+        }
+        // Overrides replan the whole queue, so they are applied once the line has succeeded.
+        let mut overrides = planner.overrides();
+        if let (Some(m_code), Some(value)) = (command.m_code, command.s) {
+            let override_range = match m_code {
+                MCode::FeedOverride => Some((&mut overrides.feed, Overrides::FEED_RANGE)),
+                MCode::SpindleOverride => Some((&mut overrides.spindle, Overrides::SPINDLE_RANGE)),
+                _ => None,
+            };
+            if let Some((percent, range)) = override_range {
+                let value = value.round();
+                if !(f32::from(*range.start())..=f32::from(*range.end())).contains(&value) {
+                    return Err(InterpreterError::OverrideOutOfRange {
+                        min: *range.start(),
+                        max: *range.end(),
+                    });
+                }
+                *percent = value as u16;
+            } else if m_code == MCode::Autotune {
+                next.autotune_request = Some(autotune_request(command, value)?);
+            } else if !m_code.controls_fan() {
+                next.temperature_request = Some(temperature_request(command, m_code, value)?);
+            }
+        }
+        if let Some(m_code) = command.m_code.filter(|m_code| m_code.controls_fan()) {
+            next.fan_request = Some(fan_request(command, m_code)?);
+        }
+        if let Some(plane) = command.plane {
+            next.plane = plane;
+        }
+        if let Some(distance_mode) = command.distance_mode {
+            next.distance_mode = distance_mode;
+        }
+        if let Some(motion_mode) = command.motion {
+            next.motion_mode = motion_mode;
+        }
+
+        if let Some(NonModal::Home) = command.non_modal {
+            // Only the presence of each axis word matters; its value is ignored.
+            let axes = command
+                .axes
+                .to_array()
+                .iter()
+                .enumerate()
+                .filter(|(_, word)| word.is_some())
+                .fold(0, |mask, (axis, _)| mask | 1 << axis);
+            next.homing_request = Some(axes);
+        } else if let Some(NonModal::SetPosition) = command.non_modal {
+            // G92 coordinates are absolute even in G91; omitted axes keep their offset. Only the
+            // work offset moves, so soft limits and the homed reference still hold.
+            for (axis, word) in command.axes.to_array().into_iter().enumerate() {
+                if let Some(value) = word {
+                    next.offset[axis] = next.position[axis] - value * scale;
+                }
+            }
+        } else if command.axes_column.is_some() || !command.arc.is_empty() {
+            // G53 axis words are machine coordinates, even in G91.
+            let machine_coordinates = command.non_modal == Some(NonModal::MachineCoordinates);
+            if machine_coordinates
+                && !matches!(next.motion_mode, MotionMode::Rapid | MotionMode::Linear)
+            {
+                return Err(InterpreterError::MachineCoordinatesWithoutLine);
+            }
+            let mut target = next.position;
+            for (axis, word) in command.axes.to_array().into_iter().enumerate() {
+                if let Some(value) = word {
+                    target[axis] = match (machine_coordinates, next.distance_mode) {
+                        (true, _) => value * scale,
+                        (false, DistanceMode::Absolute) => value * scale + next.offset[axis],
+                        (false, DistanceMode::Incremental) => next.position[axis] + value * scale,
+                    };
+                }
+            }
+
+            let arc = match next.motion_mode {
+                MotionMode::ArcClockwise | MotionMode::ArcCounterclockwise => {
+                    let (first, second, _) = next.plane.axes();
+                    let axes = command.axes.to_array();
+                    if axes[first].is_none() && axes[second].is_none() {
+                        return Err(ArcError::PlaneAxesMissing(next.plane).into());
+                    }
+                    let clockwise = next.motion_mode == MotionMode::ArcClockwise;
+                    let tolerance = planner.config().arc_tolerance;
+                    Some(ArcPath::new(
+                        next.position,
+                        target,
+                        next.plane,
+                        clockwise,
+                        &command.arc,
+                        scale,
+                        tolerance,
+                    )?)
+                }
+                _ => {
+                    let words = [command.arc.i, command.arc.j, command.arc.k, command.arc.r];
+                    if let Some(index) = words.iter().position(Option::is_some) {
+                        return Err(ArcError::WordWithoutArc(['I', 'J', 'K', 'R'][index]).into());
+                    }
+                    None
+                }
+            };
+
+            let feed_rate = match next.motion_mode {
+                MotionMode::Cancel => {
+                    let column = command.axes_column.unwrap_or_default();
+                    return Err(InterpreterError::AxisWordsWithoutMotion { column });
+                }
+                // Rapids traverse as fast as the slowest moving axis allows.
+                MotionMode::Rapid => f32::INFINITY,
+                MotionMode::Linear | MotionMode::ArcClockwise | MotionMode::ArcCounterclockwise => {
+                    match next.feed_rate_mode {
+                        FeedRateMode::UnitsPerMinute if next.feed_rate == 0.0 => {
+                            return Err(InterpreterError::FeedRateUndefined);
+                        }
+                        FeedRateMode::UnitsPerMinute => next.feed_rate,
+                        FeedRateMode::InverseTime => match inverse_time {
+                            None => return Err(InterpreterError::FeedRateUndefined),
+                            Some(inverse_minutes) => {
+                                let length = match &arc {
+                                    Some(arc) => arc.length(),
+                                    None => distance(&next.position, &target),
+                                };
+                                length * inverse_minutes
+                            }
+                        },
+                    }
+                }
+            };
+
+            next.position = match arc {
+                Some(arc) => {
+                    // Limit every chord up front so that a refused arc queues nothing and a
+                    // clipped one ends where its last chord will.
+                    let mut end = next.position;
+                    for segment in 1..=arc.segments() {
+                        end = planner.limit_move(&end, &arc.point(segment))?;
+                    }
+                    next.pending_arc = Some(PendingArc {
+                        arc,
+                        next_segment: 1,
+                        feed_rate,
+                    });
+                    next.queue_pending_arc(planner);
+                    end
+                }
+                None => {
+                    let [x, y, z, e] = target;
+                    planner.buffer_line(x, y, z, e, feed_rate)?
+                }
+            };
+        }
+
+        if overrides != planner.overrides() {
+            planner.set_overrides(overrides);
+        }
+        *self = next;
+        Ok(())
+    }
+}
+
+/// Returns the Euclidean length of a move across all axes.
+fn distance(from: &[f32; 4], to: &[f32; 4]) -> f32 {
+    from.iter()
+        .zip(to)
+        .map(|(from, to)| (to - from).powi(2))
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// Decodes the words of a temperature M-code.
+fn temperature_request(
+    command: &Command,
+    m_code: MCode,
+    celsius: f32,
+) -> Result<TemperatureRequest, InterpreterError> {
+    if celsius < 0.0 {
+        return Err(InterpreterError::InvalidTemperature);
+    }
+    let heater = match m_code {
+        MCode::BedTemperature | MCode::WaitForBed => HeaterSelect::Bed,
+        _ => match command.t.unwrap_or(0.0) {
+            tool if tool.fract() == 0.0 && (0.0..=f32::from(u8::MAX)).contains(&tool) => {
+                HeaterSelect::Hotend(tool as u8)
+            }
+            _ => return Err(InterpreterError::InvalidTool),
+        },
+    };
+    let wait = match (m_code.waits_for_temperature(), command.wait_for_cooling) {
+        (false, _) => None,
+        (true, false) => Some(TemperatureWait::UntilHeated),
+        (true, true) => Some(TemperatureWait::UntilReached),
+    };
+    Ok(TemperatureRequest {
+        heater,
+        celsius,
+        wait,
+    })
+}
+
+/// Decodes the words of `M303`.
+fn autotune_request(command: &Command, celsius: f32) -> Result<AutotuneRequest, InterpreterError> {
+    if celsius < 0.0 {
+        return Err(InterpreterError::InvalidTemperature);
+    }
+    let heater = match command.heater.unwrap_or(0.0) {
+        -1.0 => HeaterSelect::Bed,
+        index if index.fract() == 0.0 && (0.0..=f32::from(u8::MAX)).contains(&index) => {
+            HeaterSelect::Hotend(index as u8)
+        }
+        _ => return Err(InterpreterError::InvalidHeater),
+    };
+    let range = AutotuneRequest::CYCLE_RANGE;
+    let cycles = match command.c {
+        None => AutotuneRequest::DEFAULT_CYCLES,
+        Some(cycles)
+            if cycles.fract() == 0.0
+                && (*range.start() as f32..=*range.end() as f32).contains(&cycles) =>
+        {
+            cycles as u32
+        }
+        Some(_) => {
+            return Err(InterpreterError::InvalidCycles {
+                min: *range.start(),
+                max: *range.end(),
+            });
+        }
+    };
+    Ok(AutotuneRequest {
+        heater,
+        celsius,
+        cycles,
+        apply: command.u.is_some_and(|apply| apply != 0.0),
+    })
+}
+
+/// Decodes the words of `M106` or `M107`.
+fn fan_request(command: &Command, m_code: MCode) -> Result<FanRequest, InterpreterError> {
+    let fan = match command.p.unwrap_or(0.0) {
+        fan if fan.fract() == 0.0 && (0.0..=f32::from(u8::MAX)).contains(&fan) => fan as u8,
+        _ => return Err(InterpreterError::InvalidFan),
+    };
+    let full = f32::from(FanRequest::FULL_SPEED);
+    let speed = match (m_code, command.s) {
+        (MCode::FanOff, _) => 0.0,
+        (_, None) => full,
+        (_, Some(speed)) if (0.0..=full).contains(&speed) => speed,
+        (_, Some(_)) => return Err(InterpreterError::InvalidFanSpeed),
+    };
+    Ok(FanRequest {
+        fan,
+        speed: speed / full,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MachineConfig;
+
+    fn setup() -> (Interpreter, Planner) {
+        (
+            Interpreter::new(),
+            Planner::new(8, MachineConfig::default()),
+        )
+    }
+
+    #[test]
+    fn modal_state_persists_between_lines() {
+        let (mut interpreter, mut planner) = setup();
+        interpreter
+            .execute_line("G1 G20 G91 X1 F10", &mut planner)
+            .unwrap();
+        assert_eq!(interpreter.motion_mode, MotionMode::Linear);
+        assert_eq!(interpreter.units, Units::Inches);
+        assert_eq!(interpreter.feed_rate, 254.0);
+        assert_eq!(interpreter.position, [25.4, 0.0, 0.0, 0.0]);
+
+        interpreter.execute_line("Y1", &mut planner).unwrap();
+        assert_eq!(interpreter.position, [25.4, 25.4, 0.0, 0.0]);
+        assert_eq!(planner.position(), interpreter.position);
+    }
+
+    #[test]
+    fn feed_rate_must_be_positive() {
+        let (mut interpreter, mut planner) = setup();
+        assert_eq!(
+            interpreter.execute_line("G1 X1", &mut planner),
+            Err(InterpreterError::FeedRateUndefined)
+        );
+        for line in ["G1 X1 F0", "G1 X1 F-5", "F0", "G93 G1 X1 F0"] {
+            assert_eq!(
+                interpreter.execute_line(line, &mut planner),
+                Err(InterpreterError::InvalidFeedRate),
+                "{line}"
+            );
+        }
+        assert_eq!(interpreter.position, [0.0; 4]);
+        assert!(planner.is_empty());
+    }
+
+    #[test]
+    fn rejected_line_leaves_state_unchanged() {
+        let (mut interpreter, mut planner) = setup();
+        assert_eq!(
+            interpreter.execute_line("G91 X1", &mut planner),
+            Err(InterpreterError::AxisWordsWithoutMotion { column: 5 })
+        );
+        assert_eq!(interpreter.distance_mode, DistanceMode::Absolute);
+    }
+
+    #[test]
+    fn soft_limits_apply_to_lines_and_arcs() {
+        let (mut interpreter, mut planner) = setup();
+        interpreter.execute_line("G0 X10", &mut planner).unwrap();
+        assert_eq!(
+            interpreter.execute_line("G0 X-1", &mut planner),
+            Err(InterpreterError::SoftLimit(0))
+        );
+        assert_eq!(interpreter.position, [10.0, 0.0, 0.0, 0.0]);
+
+        // A counterclockwise semicircle from (200, 198) about (195, 198) rises above Y200.
+        interpreter
+            .execute_line("G0 X200 Y198", &mut planner)
+            .unwrap();
+        assert_eq!(
+            interpreter.execute_line("G3 X190 I-5 F100", &mut planner),
+            Err(InterpreterError::SoftLimit(1))
+        );
+        assert!(!interpreter.has_pending_arc());
+        interpreter
+            .execute_line("G2 X190 I-5 F100", &mut planner)
+            .unwrap();
+    }
+
+    #[test]
+    fn clipped_moves_update_the_position() {
+        let config = MachineConfig {
+            soft_limits: crate::config::SoftLimits::Clip,
+            ..MachineConfig::default()
+        };
+        let mut planner = Planner::new(8, config);
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .execute_line("G0 X100 Y100", &mut planner)
+            .unwrap();
+        interpreter
+            .execute_line("G0 X300 Y200", &mut planner)
+            .unwrap();
+        assert_eq!(interpreter.position, [200.0, 150.0, 0.0, 0.0]);
+        assert_eq!(planner.position(), interpreter.position);
+
+        // Incremental moves continue from the clipped end point.
+        interpreter.execute_line("G91 Y-50", &mut planner).unwrap();
+        assert_eq!(interpreter.position, [200.0, 100.0, 0.0, 0.0]);
+
+        // The arc's crown above Y200 is clipped chord by chord.
+        interpreter
+            .execute_line("G90 G0 X190 Y198", &mut planner)
+            .unwrap();
+        interpreter
+            .execute_line("G3 X180 Y198 I-5 F100", &mut planner)
+            .unwrap();
+        while !interpreter.queue_pending_arc(&mut planner) {
+            planner.discard_current_block();
+        }
+        let difference = interpreter
+            .position
+            .iter()
+            .zip(planner.position())
+            .map(|(interpreter, planner)| (interpreter - planner).abs())
+            .fold(0.0, f32::max);
+        assert!(difference < 0.1, "{difference}");
+    }
+
+    #[test]
+    fn non_modal_codes_with_motion_mode() {
+        let (mut interpreter, mut planner) = setup();
+        interpreter.execute_line("G28 G1", &mut planner).unwrap();
+        assert_eq!(interpreter.take_homing_request(), Some(0));
+        assert_eq!(interpreter.motion_mode, MotionMode::Linear);
+
+        interpreter
+            .execute_line("G0 G92 X5", &mut planner)
+            .unwrap_err();
+        interpreter.execute_line("G92 X5", &mut planner).unwrap();
+        assert_eq!(interpreter.work_position(), [5.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn g92_offsets_work_coordinates_without_moving_the_machine() {
+        let (mut interpreter, mut planner) = setup();
+        interpreter.execute_line("G0 X10", &mut planner).unwrap();
+        interpreter.execute_line("G92 X0", &mut planner).unwrap();
+        assert_eq!(interpreter.position, [10.0, 0.0, 0.0, 0.0]);
+        assert_eq!(planner.position(), interpreter.position);
+        assert_eq!(interpreter.work_position(), [0.0; 4]);
+
+        // X5 in work coordinates is X15 on the machine.
+        interpreter.execute_line("G0 X5", &mut planner).unwrap();
+        assert_eq!(planner.position(), [15.0, 0.0, 0.0, 0.0]);
+
+        // Work X-12 is machine X-2, beyond the X0 end of travel.
+        assert_eq!(
+            interpreter.execute_line("G0 X-12", &mut planner),
+            Err(InterpreterError::SoftLimit(0))
+        );
+        interpreter.execute_line("G0 X-10", &mut planner).unwrap();
+        assert_eq!(planner.position(), [0.0; 4]);
+
+        // The offset survives abandoned motion, such as after homing.
+        interpreter.abandon_motion([0.0; 4]);
+        assert_eq!(interpreter.work_position(), [-10.0, 0.0, 0.0, 0.0]);
+    }
+
+    /// Decodes the words after `$J=`.
+    fn jog_words(source: &str) -> Command {
+        parse_line(source).unwrap().command().unwrap()
+    }
+
+    #[test]
+    fn jog_words_apply_to_the_jog_only() {
+        let (mut interpreter, mut planner) = setup();
+        interpreter
+            .execute_line("G0 X10 Y10", &mut planner)
+            .unwrap();
+        interpreter.execute_line("G92 X0 Y0", &mut planner).unwrap();
+
+        interpreter
+            .jog(&jog_words("G91 X5 F300"), &mut planner)
+            .unwrap();
+        assert_eq!(interpreter.position, [15.0, 10.0, 0.0, 0.0]);
+        interpreter
+            .jog(&jog_words("G20 Y1 F10"), &mut planner)
+            .unwrap();
+        assert_eq!(interpreter.position, [15.0, 35.4, 0.0, 0.0]);
+        // G53 targets machine coordinates rather than the G92 work coordinates.
+        interpreter
+            .jog(&jog_words("G53 X2 F300"), &mut planner)
+            .unwrap();
+        assert_eq!(interpreter.position, [2.0, 35.4, 0.0, 0.0]);
+        assert_eq!(planner.position(), interpreter.position);
+        assert_eq!(interpreter.distance_mode, DistanceMode::Absolute);
+        assert_eq!(interpreter.units, Units::Millimetres);
+        assert_eq!(interpreter.motion_mode, MotionMode::Rapid);
+
+        // Jogs ignore the feed override.
+        interpreter.execute_line("M220 S50", &mut planner).unwrap();
+        interpreter
+            .jog(&jog_words("G91 X1 F600"), &mut planner)
+            .unwrap();
+        let jog = planner.blocks().last().unwrap();
+        assert_eq!(jog.nominal_speed, 10.0);
+    }
+
+    #[test]
+    fn jog_requires_axis_words_and_feed_rate() {
+        let (mut interpreter, mut planner) = setup();
+        for words in [
+            "X5",
+            "F100",
+            "G1 X5 F100",
+            "G92 X5 F100",
+            "M220 S100 X1 F100",
+        ] {
+            assert_eq!(
+                interpreter.jog(&jog_words(words), &mut planner),
+                Err(InterpreterError::InvalidJog),
+                "{words}"
+            );
+        }
+        assert_eq!(
+            interpreter.jog(&jog_words("X1 F0"), &mut planner),
+            Err(InterpreterError::InvalidFeedRate)
+        );
+        assert!(planner.is_empty());
+        assert_eq!(interpreter.position, [0.0; 4]);
+    }
+
+    #[test]
+    fn jogs_respect_soft_limits() {
+        let (mut interpreter, mut planner) = setup();
+        interpreter.execute_line("G0 X5", &mut planner).unwrap();
+        assert_eq!(
+            interpreter.jog(&jog_words("G91 X-10 F600"), &mut planner),
+            Err(InterpreterError::SoftLimit(0))
+        );
+        assert_eq!(interpreter.position, [5.0, 0.0, 0.0, 0.0]);
+
+        let config = MachineConfig {
+            soft_limits: crate::config::SoftLimits::Clip,
+            ..MachineConfig::default()
+        };
+        let mut planner = Planner::new(8, config);
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .jog(&jog_words("G91 X400 F600"), &mut planner)
+            .unwrap();
+        assert_eq!(interpreter.position, [200.0, 0.0, 0.0, 0.0]);
+        assert_eq!(planner.position(), interpreter.position);
+    }
+
+    #[test]
+    fn g53_moves_in_machine_coordinates() {
+        let (mut interpreter, mut planner) = setup();
+        interpreter.execute_line("G0 X10", &mut planner).unwrap();
+        interpreter.execute_line("G92 X0", &mut planner).unwrap();
+        interpreter
+            .execute_line("G91 G53 G0 X20", &mut planner)
+            .unwrap();
+        assert_eq!(interpreter.position, [20.0, 0.0, 0.0, 0.0]);
+        assert_eq!(interpreter.work_position(), [10.0, 0.0, 0.0, 0.0]);
+        assert_eq!(
+            interpreter.execute_line("G2 G53 X1 I1 F100", &mut planner),
+            Err(InterpreterError::MachineCoordinatesWithoutLine)
+        );
+    }
+}
@@ -0,0 +1,20 @@
+//! RS274/NGC G-code parsing and interpretation.
+//!
+//! [`parse_line`] tokenizes a line, [`Command`] groups its words by modal group, and the
+//! [`Interpreter`] applies commands to the modal state to produce machine-coordinate moves.
+
+pub mod arc;
+pub mod command;
+pub mod interpreter;
+pub mod parser;
+
+pub use arc::{ArcError, ArcPath};
+pub use command::{
+    ArcWords, AxisWords, Command, DistanceMode, FeedRateMode, MCode, MotionMode, NonModal, Plane,
+    Units,
+};
+pub use interpreter::{
+    AutotuneRequest, FanRequest, HeaterSelect, Interpreter, InterpreterError, TemperatureRequest,
+    TemperatureWait,
+};
+pub use parser::{Line, ParseError, ParseErrorKind, Word, parse_line};
@@ -0,0 +1,352 @@
+//! Tokenizer and single-line parser for RS274/NGC G-code.
+//!
+//! A line is split into letter/value [`Word`]s, parenthesized and semicolon comments, an optional
+//! leading `N` line number, and an optional trailing RepRap-style `*` checksum. Letters are
+//! case-insensitive, whitespace may also appear between a letter and its value, and every error
+//! carries the one-based column at which parsing failed.
+
+use core::fmt;
+
+/// One letter/value pair such as `G1` or `X-12.5`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Word {
+    /// Upper-case word letter.
+    pub letter: char,
+    pub value: f32,
+    /// One-based column of the letter within the source line.
+    pub column: usize,
+}
+
+/// A tokenized G-code line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Line {
+    /// Value of the leading `N` word, if present.
+    pub line_number: Option<u32>,
+    /// Words in source order, excluding the line number.
+    pub words: Vec<Word>,
+    /// Comment text without its delimiters.
+    pub comments: Vec<String>,
+    /// Verified checksum, if the line carried one.
+    pub checksum: Option<u8>,
+}
+
+/// The reason a line could not be parsed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedCharacter(char),
+    MissingValue(char),
+    InvalidNumber,
+    UnterminatedComment,
+    NestedComment,
+    MisplacedLineNumber,
+    InvalidLineNumber,
+    InvalidChecksum,
+    ChecksumMismatch { expected: u8, computed: u8 },
+    TrailingCharacters,
+    DuplicateWord(char),
+    UnsupportedWord(char),
+    UnsupportedCode(char, f32),
+    ModalGroupConflict(u8),
+    MissingAxisWords,
+    MissingWord(char),
+    AxisWordConflict,
+    ExclusiveWords(char, char),
+}
+
+/// A parse failure and the one-based column where it was detected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub column: usize,
+}
+
+impl ParseError {
+    pub(crate) fn new(kind: ParseErrorKind, column: usize) -> Self {
+        Self { kind, column }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedCharacter(character) => write!(f, "unexpected character {character:?}"),
+            Self::MissingValue(letter) => write!(f, "word {letter} has no value"),
+            Self::InvalidNumber => f.write_str("malformed number"),
+            Self::UnterminatedComment => f.write_str("comment is not closed"),
+            Self::NestedComment => f.write_str("comments cannot be nested"),
+            Self::MisplacedLineNumber => f.write_str("N word must start the line"),
+            Self::InvalidLineNumber => f.write_str("line number must be a non-negative integer"),
+            Self::InvalidChecksum => f.write_str("checksum must be an integer from 0 to 255"),
+            Self::ChecksumMismatch { expected, computed } => {
+                write!(f, "checksum {expected} does not match computed {computed}")
+            }
+            Self::TrailingCharacters => f.write_str("unexpected text after checksum"),
+            Self::DuplicateWord(letter) => write!(f, "word {letter} appears more than once"),
+            Self::UnsupportedWord(letter) => write!(f, "word {letter} is not supported"),
+            Self::UnsupportedCode(letter, value) => write!(f, "{letter}{value} is not supported"),
+            Self::ModalGroupConflict(group) => {
+                write!(f, "more than one word from modal group {group}")
+            }
+            Self::MissingAxisWords => f.write_str("command requires at least one axis word"),
+            Self::MissingWord(letter) => write!(f, "command requires a {letter} word"),
+            Self::AxisWordConflict => {
+                f.write_str("a motion code and a non-modal code both use the axis words")
+            }
+            Self::ExclusiveWords(first, second) => {
+                write!(f, "words {first} and {second} cannot be combined")
+            }
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.kind)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Tokenizes one line of G-code.
+pub fn parse_line(source: &str) -> Result<Line, ParseError> {
+    let mut line = Line::default();
+    let mut characters = source.char_indices().enumerate().peekable();
+
+    while let Some((index, (offset, character))) = characters.next() {
+        let column = index + 1;
+        match character {
+            ' ' | '\t' | '\r' | '\n' => {}
+            '(' => {
+                let mut comment = String::new();
+                loop {
+                    match characters.next() {
+                        Some((_, (_, ')'))) => break,
+                        Some((nested, (_, '('))) => {
+                            return Err(ParseError::new(ParseErrorKind::NestedComment, nested + 1));
+                        }
+                        Some((_, (_, character))) => comment.push(character),
+                        None => {
+                            return Err(ParseError::new(
+                                ParseErrorKind::UnterminatedComment,
+                                column,
+                            ));
+                        }
+                    }
+                }
+                line.comments.push(comment);
+            }
+            ';' => {
+                line.comments.push(source[offset + 1..].trim().to_owned());
+                break;
+            }
+            '*' => {
+                let digits: String = characters
+                    .by_ref()
+                    .map(|(_, (_, character))| character)
+                    .take_while(|character| !character.is_whitespace() && *character != ';')
+                    .collect();
+                let expected = digits
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|_| digits.bytes().all(|byte| byte.is_ascii_digit()))
+                    .ok_or(ParseError::new(ParseErrorKind::InvalidChecksum, column + 1))?;
+                let computed = source.as_bytes()[..offset]
+                    .iter()
+                    .fold(0_u8, |checksum, byte| checksum ^ byte);
+                if expected != computed {
+                    return Err(ParseError::new(
+                        ParseErrorKind::ChecksumMismatch { expected, computed },
+                        column,
+                    ));
+                }
+                line.checksum = Some(expected);
+
+                // Only whitespace or a trailing semicolon comment may follow a checksum.
+                let rest = source[offset + 1 + digits.len()..].trim_start();
+                if !(rest.is_empty() || rest.starts_with(';')) {
+                    let trailing = column + 1 + digits.chars().count();
+                    return Err(ParseError::new(
+                        ParseErrorKind::TrailingCharacters,
+                        trailing,
+                    ));
+                }
+                if let Some(comment) = rest.strip_prefix(';') {
+                    line.comments.push(comment.trim().to_owned());
+                }
+                break;
+            }
+            letter if letter.is_ascii_alphabetic() => {
+                let letter = letter.to_ascii_uppercase();
+                while characters
+                    .next_if(|(_, (_, character))| *character == ' ' || *character == '\t')
+                    .is_some()
+                {}
+
+                let mut number = String::new();
+                let mut number_column = None;
+                while let Some((index, (_, character))) =
+                    characters.next_if(|(_, (_, character))| {
+                        character.is_ascii_digit() || matches!(character, '.' | '+' | '-')
+                    })
+                {
+                    number_column.get_or_insert(index + 1);
+                    number.push(character);
+                }
+                let Some(number_column) = number_column else {
+                    return Err(ParseError::new(
+                        ParseErrorKind::MissingValue(letter),
+                        column,
+                    ));
+                };
+                let value = parse_number(&number).ok_or(ParseError::new(
+                    ParseErrorKind::InvalidNumber,
+                    number_column,
+                ))?;
+
+                if letter == 'N' {
+                    if line.line_number.is_some() || !line.words.is_empty() {
+                        return Err(ParseError::new(ParseErrorKind::MisplacedLineNumber, column));
+                    }
+                    let line_number = number
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|_| number.bytes().all(|byte| byte.is_ascii_digit()))
+                        .ok_or(ParseError::new(
+                            ParseErrorKind::InvalidLineNumber,
+                            number_column,
+                        ))?;
+                    line.line_number = Some(line_number);
+                } else {
+                    line.words.push(Word {
+                        letter,
+                        value,
+                        column,
+                    });
+                }
+            }
+            unexpected => {
+                return Err(ParseError::new(
+                    ParseErrorKind::UnexpectedCharacter(unexpected),
+                    column,
+                ));
+            }
+        }
+    }
+
+    Ok(line)
+}
+
+/// Parses a signed decimal without exponent notation.
+fn parse_number(number: &str) -> Option<f32> {
+    let unsigned = number.strip_prefix(['+', '-']).unwrap_or(number);
+    let mut digits = 0;
+    let mut points = 0;
+    for character in unsigned.chars() {
+        match character {
+            '0'..='9' => digits += 1,
+            '.' => points += 1,
+            _ => return None,
+        }
+    }
+    if digits == 0 || points > 1 {
+        return None;
+    }
+    number.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn letters(line: &Line) -> Vec<(char, f32)> {
+        line.words
+            .iter()
+            .map(|word| (word.letter, word.value))
+            .collect()
+    }
+
+    fn error(source: &str) -> ParseError {
+        parse_line(source).expect_err(source)
+    }
+
+    #[test]
+    fn words_comments_and_line_number() {
+        let line = parse_line("n10 g1 x-1.5 Y +2 (move) f 300 ; fast").unwrap();
+        assert_eq!(line.line_number, Some(10));
+        assert_eq!(
+            letters(&line),
+            [('G', 1.0), ('X', -1.5), ('Y', 2.0), ('F', 300.0)]
+        );
+        assert_eq!(line.words[1].column, 8);
+        assert_eq!(line.comments, ["move", "fast"]);
+        assert_eq!(line.checksum, None);
+    }
+
+    #[test]
+    fn blank_and_comment_only_lines() {
+        assert_eq!(parse_line("").unwrap(), Line::default());
+        let line = parse_line("  ; just a note").unwrap();
+        assert!(line.words.is_empty());
+        assert_eq!(line.comments, ["just a note"]);
+    }
+
+    #[test]
+    fn checksum() {
+        let body = "N3 G1 X5";
+        let checksum = body.bytes().fold(0_u8, |checksum, byte| checksum ^ byte);
+        let line = parse_line(&format!("{body}*{checksum} ; ok")).unwrap();
+        assert_eq!(line.checksum, Some(checksum));
+        assert_eq!(line.comments, ["ok"]);
+
+        let wrong = checksum.wrapping_add(1);
+        assert_eq!(
+            error(&format!("{body}*{wrong}")).kind,
+            ParseErrorKind::ChecksumMismatch {
+                expected: wrong,
+                computed: checksum,
+            }
+        );
+        assert_eq!(error("G1*x").kind, ParseErrorKind::InvalidChecksum);
+        assert_eq!(
+            error(&format!("{body}*{checksum} X1")).kind,
+            ParseErrorKind::TrailingCharacters
+        );
+    }
+
+    #[test]
+    fn errors_carry_columns() {
+        assert_eq!(
+            error("G1 X"),
+            ParseError::new(ParseErrorKind::MissingValue('X'), 4)
+        );
+        assert_eq!(
+            error("G1 X1.2.3"),
+            ParseError::new(ParseErrorKind::InvalidNumber, 5)
+        );
+        assert_eq!(
+            error("G1 (a (b))"),
+            ParseError::new(ParseErrorKind::NestedComment, 7)
+        );
+        assert_eq!(
+            error("G1 (open"),
+            ParseError::new(ParseErrorKind::UnterminatedComment, 4)
+        );
+        assert_eq!(
+            error("G1 N5"),
+            ParseError::new(ParseErrorKind::MisplacedLineNumber, 4)
+        );
+        assert_eq!(
+            error("N-1"),
+            ParseError::new(ParseErrorKind::InvalidLineNumber, 2)
+        );
+        assert_eq!(
+            error("G1 #1"),
+            ParseError::new(ParseErrorKind::UnexpectedCharacter('#'), 4)
+        );
+        // Exponents are not numbers in G-code, so `E` starts a word of its own.
+        assert_eq!(
+            letters(&parse_line("X1e3").unwrap()),
+            [('X', 1.0), ('E', 3.0)]
+        );
+    }
+}
@@ -0,0 +1,543 @@
+//! Heater regulation.
+//!
+//! A [`Heater`] turns its sensor's readings into an output power between zero and its configured
+//! maximum, either by PID control or by bang-bang switching. Heater outputs are switched on and
+//! off, so [`Heater::output_level`] spreads the power over a slow software PWM period, which suits
+//! MOSFETs and solid-state relays behind an I/O expander. While an [`Autotune`] runs, it replaces
+//! the heater's regulation. A [`RunawayMonitor`] checks every reading, and a heater that trips it
+//! stays off until its fault is cleared.
+
+use core::fmt;
+use std::time::Duration;
+
+use crate::{
+    autotune::{Autotune, AutotuneStatus, TuningRule},
+    gcode::{HeaterSelect, TemperatureWait},
+    runaway::{RunawayConfig, RunawayMonitor, ThermalFault},
+    temperature::SensorFault,
+};
+
+/// Weight of each new rate of change in the filtered derivative, from 0 to 1.
+const DERIVATIVE_SMOOTHING: f32 = 0.25;
+
+/// PID gains acting on the error in °C and producing power as a fraction of full output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PidGains {
+    /// Power per °C of error.
+    pub kp: f32,
+    /// Power per °C of error sustained for one second.
+    pub ki: f32,
+    /// Power per °C/s of temperature change, opposing it.
+    pub kd: f32,
+}
+
+/// How a heater's power follows its temperature.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Regulation {
+    Pid(PidGains),
+    /// Full power below the target by more than `hysteresis` °C and none above it by more than
+    /// `hysteresis`, keeping the previous state in between. Suits relays and beds with too much
+    /// thermal mass to benefit from PID.
+    BangBang {
+        hysteresis: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeaterConfig {
+    pub regulation: Regulation,
+    /// Largest power applied, from 0 to 1, for heaters that are oversized for their supply.
+    pub max_power: f32,
+    /// Period over which [`Heater::output_level`] spreads the power.
+    pub pwm_period: Duration,
+    /// Highest accepted target in °C.
+    pub max_celsius: f32,
+    /// Distance in °C from the target within which a wait counts as reached.
+    pub window: f32,
+    pub runaway: RunawayConfig,
+}
+
+impl HeaterConfig {
+    /// A typical 40 W cartridge hotend, with Marlin's default gains scaled from 0–255 to 0–1.
+    pub const HOTEND: Self = Self {
+        regulation: Regulation::Pid(PidGains {
+            kp: 0.087,
+            ki: 0.004_2,
+            kd: 0.45,
+        }),
+        max_power: 1.0,
+        pwm_period: Duration::from_millis(500),
+        max_celsius: 275.0,
+        window: 1.0,
+        runaway: RunawayConfig::HOTEND,
+    };
+
+    /// A heated bed switched by a MOSFET or solid-state relay.
+    pub const BED: Self = Self {
+        regulation: Regulation::BangBang { hysteresis: 2.0 },
+        max_power: 1.0,
+        pwm_period: Duration::from_secs(2),
+        max_celsius: 120.0,
+        window: 1.0,
+        runaway: RunawayConfig::BED,
+    };
+}
+
+/// Why a temperature M-code was not applied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeaterError {
+    /// The board has no such heater, or it has no sensor to regulate it by.
+    NotFitted(HeaterSelect),
+    TargetTooHigh {
+        max: f32,
+    },
+    /// The heater was shut down by runaway protection and its fault has not been cleared.
+    Shutdown(ThermalFault),
+}
+
+impl fmt::Display for HeaterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFitted(HeaterSelect::Hotend(tool)) => write!(f, "no hotend {tool}"),
+            Self::NotFitted(HeaterSelect::Bed) => f.write_str("no heated bed"),
+            Self::TargetTooHigh { max } => write!(f, "target must not exceed {max} °C"),
+            Self::Shutdown(fault) => write!(f, "heater is shut down: {fault}"),
+        }
+    }
+}
+
+impl std::error::Error for HeaterError {}
+
+/// A heater's target and regulation state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Heater {
+    config: HeaterConfig,
+    /// Target in °C, where zero means off.
+    target: f32,
+    wait: Option<TemperatureWait>,
+    /// Integral term, kept within the output range.
+    integral: f32,
+    /// Last valid temperature, from which the rate of change is taken.
+    previous: Option<f32>,
+    /// Filtered rate of change in °C/s.
+    rate: f32,
+    /// Bang-bang output state.
+    heating: bool,
+    power: f32,
+    /// Position in the PWM period, in seconds.
+    phase: f32,
+    /// The running tuning run, or the last one for reporting.
+    autotune: Option<Autotune>,
+    /// Rule whose gains are applied when the running tuning run completes.
+    autotune_rule: Option<TuningRule>,
+    monitor: RunawayMonitor,
+    /// Runaway fault that keeps the heater off until cleared.
+    fault: Option<ThermalFault>,
+}
+
+impl Heater {
+    pub fn new(config: HeaterConfig) -> Self {
+        Self {
+            config,
+            target: 0.0,
+            wait: None,
+            integral: 0.0,
+            previous: None,
+            rate: 0.0,
+            heating: false,
+            power: 0.0,
+            phase: 0.0,
+            autotune: None,
+            autotune_rule: None,
+            monitor: RunawayMonitor::new(config.runaway),
+            fault: None,
+        }
+    }
+
+    pub fn config(&self) -> &HeaterConfig {
+        &self.config
+    }
+
+    /// Returns the target in °C, or zero when off. A tuning run's target counts while it runs.
+    pub fn target(&self) -> f32 {
+        match self.autotune.filter(Autotune::is_running) {
+            Some(autotune) => autotune.target(),
+            None => self.target,
+        }
+    }
+
+    /// Returns the PID gains, or `None` for bang-bang regulation.
+    pub fn gains(&self) -> Option<PidGains> {
+        match self.config.regulation {
+            Regulation::Pid(gains) => Some(gains),
+            Regulation::BangBang { .. } => None,
+        }
+    }
+
+    /// Switches to PID regulation with `gains`, as after tuning.
+    pub fn set_gains(&mut self, gains: PidGains) {
+        self.config.regulation = Regulation::Pid(gains);
+        self.integral = 0.0;
+    }
+
+    /// Returns the running or last tuning run.
+    pub fn autotune(&self) -> Option<&Autotune> {
+        self.autotune.as_ref()
+    }
+
+    /// Returns `true` while a wait or a tuning run should hold back later G-code.
+    pub fn is_busy(&self) -> bool {
+        self.wait.is_some() || self.autotune.is_some_and(|autotune| autotune.is_running())
+    }
+
+    /// Returns the power applied since the last update, from 0 to the configured maximum.
+    pub fn power(&self) -> f32 {
+        self.power
+    }
+
+    /// Returns the wait requested with the target until the heater reaches it.
+    pub fn wait(&self) -> Option<TemperatureWait> {
+        self.wait
+    }
+
+    /// Returns the runaway fault that shut the heater down, if any.
+    pub fn fault(&self) -> Option<ThermalFault> {
+        self.fault
+    }
+
+    /// Lets the heater be turned on again after a runaway fault. The heater stays off until it
+    /// is given a new target.
+    pub fn clear_fault(&mut self) {
+        self.fault = None;
+    }
+
+    /// Sets the target in °C, where zero turns the heater off, and optionally waits for it.
+    ///
+    /// The integral term is kept, so a small change of target does not restart the approach.
+    pub fn set_target(
+        &mut self,
+        celsius: f32,
+        wait: Option<TemperatureWait>,
+    ) -> Result<(), HeaterError> {
+        self.check_target(celsius)?;
+        self.target = celsius;
+        self.wait = wait;
+        Ok(())
+    }
+
+    /// Replaces regulation with a tuning run around `celsius` for `cycles` oscillations, after
+    /// which the heater is off. `rule` selects gains to apply if the run completes.
+    pub fn start_autotune(
+        &mut self,
+        celsius: f32,
+        cycles: u32,
+        rule: Option<TuningRule>,
+    ) -> Result<(), HeaterError> {
+        self.check_target(celsius)?;
+        self.turn_off();
+        self.autotune = Some(Autotune::new(celsius, cycles, self.config.max_power));
+        self.autotune_rule = rule;
+        Ok(())
+    }
+
+    /// Stops waiting and ends a tuning run without changing the target.
+    pub fn cancel_wait(&mut self) {
+        self.wait = None;
+        if let Some(autotune) = &mut self.autotune {
+            autotune.abort();
+        }
+    }
+
+    /// Turns the heater off and cancels any wait or tuning run.
+    pub fn turn_off(&mut self) {
+        self.cancel_wait();
+        self.target = 0.0;
+        self.power = 0.0;
+        self.integral = 0.0;
+        self.heating = false;
+    }
+
+    /// Recomputes the power from a sensor reading taken `interval` after the previous one, and
+    /// ends the wait once the reading satisfies it.
+    ///
+    /// A missing or faulty reading turns the power off until valid readings return. A reading
+    /// that trips runaway protection turns the heater off and latches the fault. While a tuning
+    /// run swings the temperature around its target, falling below the target is not a fault.
+    pub fn update(&mut self, reading: Option<Result<f32, SensorFault>>, interval: Duration) {
+        let target = self.target();
+        if self.fault.is_none() {
+            let checked = match self.autotune.is_some_and(|autotune| autotune.is_running()) {
+                true => self.monitor.check_tuning(target, reading, interval),
+                false => self.monitor.check(target, reading, interval),
+            };
+            self.fault = checked.err();
+        }
+        if self.fault.is_some() {
+            self.turn_off();
+            return;
+        }
+        if let Some(autotune) = self
+            .autotune
+            .as_mut()
+            .filter(|autotune| autotune.is_running())
+        {
+            self.power = autotune.update(reading, interval);
+            if let (AutotuneStatus::Completed(oscillation), Some(rule)) =
+                (autotune.status(), self.autotune_rule)
+            {
+                self.set_gains(rule.gains(&oscillation));
+            }
+            self.previous = None;
+            self.rate = 0.0;
+            return;
+        }
+        let Some(Ok(celsius)) = reading else {
+            self.previous = None;
+            self.rate = 0.0;
+            self.integral = 0.0;
+            self.heating = false;
+            self.power = 0.0;
+            return;
+        };
+        let seconds = interval.as_secs_f32();
+        if let Some(previous) = self.previous.filter(|_| seconds > 0.0) {
+            let rate = (celsius - previous) / seconds;
+            self.rate += (rate - self.rate) * DERIVATIVE_SMOOTHING;
+        }
+        self.previous = Some(celsius);
+
+        let reached = match self.wait {
+            Some(TemperatureWait::UntilHeated) => celsius >= self.target - self.config.window,
+            Some(TemperatureWait::UntilReached) => {
+                (celsius - self.target).abs() <= self.config.window
+            }
+            None => false,
+        };
+        if reached {
+            self.wait = None;
+        }
+
+        if self.target <= 0.0 {
+            self.integral = 0.0;
+            self.heating = false;
+            self.power = 0.0;
+            return;
+        }
+        let max = self.config.max_power;
+        let error = self.target - celsius;
+        self.power = match self.config.regulation {
+            Regulation::Pid(gains) => {
+                // Derivative on measurement, so a change of target does not kick the output.
+                let proportional = gains.kp * error - gains.kd * self.rate;
+                let integral = self.integral + gains.ki * error * seconds;
+                // Anti-windup: the integral only grows while the output is not saturated in the
+                // same direction, and never beyond the output range on its own.
+                let output = proportional + integral;
+                let saturated = (output > max && error > 0.0) || (output < 0.0 && error < 0.0);
+                if !saturated {
+                    self.integral = integral.clamp(0.0, max);
+                }
+                (proportional + self.integral).clamp(0.0, max)
+            }
+            Regulation::BangBang { hysteresis } => {
+                if error > hysteresis {
+                    self.heating = true;
+                } else if error < -hysteresis {
+                    self.heating = false;
+                }
+                if self.heating { max } else { 0.0 }
+            }
+        };
+    }
+
+    fn check_target(&self, celsius: f32) -> Result<(), HeaterError> {
+        match self.fault {
+            Some(fault) if celsius > 0.0 => Err(HeaterError::Shutdown(fault)),
+            _ if celsius > self.config.max_celsius => Err(HeaterError::TargetTooHigh {
+                max: self.config.max_celsius,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Advances the PWM by `elapsed` and returns whether the output should be on.
+    pub fn output_level(&mut self, elapsed: Duration) -> bool {
+        let period = self.config.pwm_period.as_secs_f32();
+        if period <= 0.0 {
+            return self.power > 0.0;
+        }
+        self.phase = (self.phase + elapsed.as_secs_f32()) % period;
+        self.phase < self.power * period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(100);
+
+    /// A 40 W heater block whose sensor lags it, losing heat to a 25 °C room.
+    struct Plant {
+        sensor: f32,
+        block: f32,
+    }
+
+    impl Plant {
+        fn new() -> Self {
+            Self {
+                sensor: 25.0,
+                block: 25.0,
+            }
+        }
+
+        /// Runs `heater` against the plant for `seconds` and returns the highest temperature and
+        /// the largest error from the target over the last quarter.
+        fn run(&mut self, heater: &mut Heater, seconds: f32) -> (f32, f32) {
+            let steps = (seconds / TICK.as_secs_f32()) as usize;
+            let (mut highest, mut error) = (self.sensor, 0.0_f32);
+            for step in 0..steps {
+                heater.update(Some(Ok(self.sensor)), TICK);
+                let on = (0..10).filter(|_| heater.output_level(TICK / 10)).count() as f32 / 10.0;
+                let dt = TICK.as_secs_f32();
+                self.block += (40.0 * on - (self.block - self.sensor) * 2.0) * dt / 8.0;
+                self.sensor +=
+                    ((self.block - self.sensor) * 2.0 - (self.sensor - 25.0) * 0.15) * dt / 12.0;
+                highest = highest.max(self.sensor);
+                if step > steps * 3 / 4 {
+                    error = error.max((self.sensor - heater.target()).abs());
+                }
+            }
+            (highest, error)
+        }
+    }
+
+    #[test]
+    fn pid_settles_on_the_target() {
+        let mut heater = Heater::new(HeaterConfig::HOTEND);
+        heater.set_target(200.0, None).unwrap();
+        let (highest, error) = Plant::new().run(&mut heater, 900.0);
+        assert!(error < 2.0, "settled {error} °C off the target");
+        assert!(highest < 215.0, "overshot to {highest} °C");
+        assert_eq!(heater.fault(), None);
+    }
+
+    #[test]
+    fn bang_bang_stays_near_the_target() {
+        let mut heater = Heater::new(HeaterConfig::BED);
+        heater.set_target(60.0, None).unwrap();
+        let (_, error) = Plant::new().run(&mut heater, 900.0);
+        assert!(error < 6.0, "swung {error} °C off the target");
+        assert_eq!(heater.fault(), None);
+    }
+
+    #[test]
+    fn wait_ends_once_heated_and_faults_cut_power() {
+        let mut heater = Heater::new(HeaterConfig::HOTEND);
+        assert_eq!(
+            heater.set_target(300.0, None),
+            Err(HeaterError::TargetTooHigh { max: 275.0 })
+        );
+        heater
+            .set_target(100.0, Some(TemperatureWait::UntilHeated))
+            .unwrap();
+        heater.update(Some(Ok(50.0)), TICK);
+        assert_eq!(heater.power(), 1.0);
+        assert!(heater.is_busy());
+        heater.update(Some(Err(SensorFault::Open)), TICK);
+        assert_eq!(heater.power(), 0.0);
+        assert_eq!(
+            heater.fault(),
+            Some(ThermalFault::Sensor(SensorFault::Open))
+        );
+        assert_eq!(
+            heater.set_target(100.0, None),
+            Err(HeaterError::Shutdown(ThermalFault::Sensor(
+                SensorFault::Open
+            )))
+        );
+
+        let mut heater = Heater::new(HeaterConfig::HOTEND);
+        heater
+            .set_target(100.0, Some(TemperatureWait::UntilHeated))
+            .unwrap();
+        heater.update(Some(Ok(99.5)), TICK);
+        assert!(!heater.is_busy());
+    }
+
+    #[test]
+    fn autotune_applies_gains_that_hold_the_target() {
+        let mut heater = Heater::new(HeaterConfig::HOTEND);
+        heater
+            .start_autotune(200.0, 6, Some(TuningRule::TyreusLuyben))
+            .unwrap();
+        assert!(heater.is_busy());
+        let mut plant = Plant::new();
+        for _ in 0..720 {
+            if !heater.is_busy() {
+                break;
+            }
+            plant.run(&mut heater, 10.0);
+        }
+        let AutotuneStatus::Completed(oscillation) = heater.autotune().unwrap().status() else {
+            panic!("tuning ended {:?}", heater.autotune().unwrap().status());
+        };
+        assert_eq!(heater.fault(), None);
+        assert_eq!(heater.target(), 0.0);
+        assert_eq!(
+            heater.gains(),
+            Some(TuningRule::TyreusLuyben.gains(&oscillation))
+        );
+
+        for rule in TuningRule::ALL {
+            let mut heater = Heater::new(HeaterConfig::HOTEND);
+            heater.set_gains(rule.gains(&oscillation));
+            heater.set_target(200.0, None).unwrap();
+            let (highest, error) = Plant::new().run(&mut heater, 1_200.0);
+            assert!(error < 1.0, "{} settled {error} °C off", rule.name());
+            assert!(highest < 225.0, "{} overshot to {highest} °C", rule.name());
+        }
+    }
+
+    #[test]
+    fn autotune_swing_below_the_target_is_not_a_fault() {
+        let mut heater = Heater::new(HeaterConfig::HOTEND);
+        heater.start_autotune(200.0, 5, None).unwrap();
+        heater.update(Some(Ok(197.0)), TICK);
+        // Longer under the holding band than the hotend's 40 s allowance.
+        for _ in 0..600 {
+            heater.update(Some(Ok(190.0)), TICK);
+        }
+        assert_eq!(heater.fault(), None);
+        assert!(heater.is_busy());
+    }
+
+    #[test]
+    fn autotune_stops_on_a_sensor_fault() {
+        let mut heater = Heater::new(HeaterConfig::HOTEND);
+        heater.start_autotune(200.0, 5, None).unwrap();
+        heater.update(Some(Ok(25.0)), TICK);
+        assert_eq!(heater.power(), 1.0);
+        heater.update(Some(Err(SensorFault::Open)), TICK);
+        assert_eq!(heater.power(), 0.0);
+        assert!(!heater.is_busy());
+        assert_eq!(
+            heater.fault(),
+            Some(ThermalFault::Sensor(SensorFault::Open))
+        );
+    }
+
+    #[test]
+    fn output_spreads_limited_power_over_the_period() {
+        let mut config = HeaterConfig::HOTEND;
+        config.max_power = 0.5;
+        let mut heater = Heater::new(config);
+        heater.set_target(200.0, None).unwrap();
+        heater.update(Some(Ok(20.0)), TICK);
+        assert_eq!(heater.power(), 0.5);
+        let on = (0..100)
+            .filter(|_| heater.output_level(Duration::from_millis(10)))
+            .count();
+        assert!((49..=51).contains(&on), "on for {on} of 100 ticks");
+    }
+}
@@ -0,0 +1,449 @@
+//! Homing cycle that finds each axis's switch and sets machine zero.
+//!
+//! [`HomingCycle`] runs the configured passes in order, each one homing a group of axes together
+//! as Grbl does: a fast seek toward the switches, a back-off, a slow second approach that fixes
+//! the switch position, and a final pull-off. The cycle queues its moves on the [`Planner`] and
+//! tells the caller which axes the step generator must stop at their endstops.
+
+use core::fmt;
+
+use crate::{
+    config::{AXES, AXIS_NAMES, HomingConfig, HomingDirection, MachineConfig},
+    planner::{Planner, PlannerError},
+};
+
+/// Multiple of an axis's pull-off distance covered by the slow second approach.
+const LOCATE_DISTANCE_SCALE: f32 = 5.0;
+
+/// A homing request that cannot be run, or a cycle that failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HomingError {
+    /// No axis is enabled for homing.
+    NoAxes,
+    /// The axis is disabled, belongs to no pass, or has unbounded travel.
+    NotConfigured(usize),
+    /// The axis moved its full travel without reaching its switch.
+    SwitchNotFound(usize),
+    /// The axis's switch was still triggered after backing away from it.
+    SwitchNotReleased(usize),
+    /// The axis has no stepper output to move it.
+    NoStepper(usize),
+}
+
+impl fmt::Display for HomingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoAxes => f.write_str("no axis is configured for homing"),
+            Self::NotConfigured(axis) => {
+                write!(f, "{} axis is not configured for homing", AXIS_NAMES[*axis])
+            }
+            Self::SwitchNotFound(axis) => {
+                write!(f, "{} endstop not found within travel", AXIS_NAMES[*axis])
+            }
+            Self::SwitchNotReleased(axis) => {
+                write!(
+                    f,
+                    "{} endstop still triggered after pull-off",
+                    AXIS_NAMES[*axis]
+                )
+            }
+            Self::NoStepper(axis) => PlannerError::NoStepper(*axis).fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for HomingError {}
+
+/// What the caller has to do after [`HomingCycle::advance`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HomingStep {
+    /// Run the queued move, stopping each axis in `watch` as soon as its endstop triggers. Once
+    /// every watched axis has stopped, the rest of the move is abandoned.
+    Move { watch: u8 },
+    /// The planner position of `axes` has been redefined at their switches. Copy it to the step
+    /// generator before advancing again.
+    Homed(u8),
+    /// Every pass has finished.
+    Done,
+}
+
+/// Stage of the current pass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    /// The pass has not moved yet.
+    Start,
+    Seek,
+    BackOff,
+    Locate,
+    /// The switch position has been recorded; the pull-off has not been queued yet.
+    Located,
+    PullOff,
+}
+
+/// A homing cycle in progress.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HomingCycle {
+    config: HomingConfig,
+    /// Axes homed in each pass, in order.
+    passes: [u8; AXES],
+    pass: usize,
+    phase: Phase,
+}
+
+impl HomingCycle {
+    /// Plans a cycle for the axes in the `axes` bit mask, or every enabled axis if it is zero.
+    ///
+    /// Passes keep the order of [`HomingConfig::cycles`] and skip axes that were not requested.
+    pub fn new(
+        config: HomingConfig,
+        machine: &MachineConfig,
+        axes: u8,
+    ) -> Result<Self, HomingError> {
+        let enabled = (0..AXES)
+            .filter(|axis| {
+                let travel = &machine.axes[*axis];
+                config.axes[*axis].enabled
+                    && travel.min_travel.is_finite()
+                    && travel.max_travel.is_finite()
+            })
+            .fold(0_u8, |mask, axis| mask | 1 << axis);
+        let scheduled = config.cycles.iter().fold(0, |mask, pass| mask | pass) & enabled;
+        let requested = if axes == 0 { scheduled } else { axes };
+        if requested == 0 {
+            return Err(HomingError::NoAxes);
+        }
+        if let Some(axis) = (0..AXES).find(|axis| requested & !scheduled & 1 << axis != 0) {
+            return Err(HomingError::NotConfigured(axis));
+        }
+
+        let mut passes = [0; AXES];
+        let mut count = 0;
+        for pass in config.cycles {
+            if pass & requested != 0 {
+                passes[count] = pass & requested;
+                count += 1;
+            }
+        }
+        Ok(Self {
+            config,
+            passes,
+            pass: 0,
+            phase: Phase::Start,
+        })
+    }
+
+    /// Returns the axes homed by the pass in progress.
+    pub fn axes(&self) -> u8 {
+        self.passes.get(self.pass).copied().unwrap_or(0)
+    }
+
+    /// Finishes the previous step and queues the next move.
+    ///
+    /// Call this once the machine has stopped after the previous [`HomingStep::Move`], with the
+    /// planner empty and its position matching the step generator's. `triggered` has bit `n` set
+    /// if axis `n`'s switch is triggered or was hit during that move.
+    pub fn advance(
+        &mut self,
+        planner: &mut Planner,
+        triggered: u8,
+    ) -> Result<HomingStep, HomingError> {
+        let axes = self.axes();
+        match self.phase {
+            Phase::Start => {
+                if axes == 0 {
+                    return Ok(HomingStep::Done);
+                }
+                let config = planner.config();
+                let travel = each_axis(axes)
+                    .map(|axis| config.axes[axis].max_travel - config.axes[axis].min_travel)
+                    .fold(0.0, f32::max);
+                self.queue_move(planner, |_| travel, self.config.seek_rate)?;
+                self.phase = Phase::Seek;
+                Ok(HomingStep::Move { watch: axes })
+            }
+            Phase::Seek => {
+                self.check_found(triggered)?;
+                let pull_off = self.config.axes;
+                self.queue_move(
+                    planner,
+                    |axis| -pull_off[axis].pull_off,
+                    self.config.seek_rate,
+                )?;
+                self.phase = Phase::BackOff;
+                Ok(HomingStep::Move { watch: 0 })
+            }
+            Phase::BackOff => {
+                self.check_released(triggered)?;
+                let distance = each_axis(axes)
+                    .map(|axis| self.config.axes[axis].pull_off * LOCATE_DISTANCE_SCALE)
+                    .fold(0.0, f32::max);
+                self.queue_move(planner, |_| distance, self.config.locate_rate)?;
+                self.phase = Phase::Locate;
+                Ok(HomingStep::Move { watch: axes })
+            }
+            Phase::Locate => {
+                self.check_found(triggered)?;
+                let mut position = planner.position();
+                for axis in each_axis(axes) {
+                    let travel = &planner.config().axes[axis];
+                    position[axis] = match self.config.axes[axis].direction {
+                        HomingDirection::Negative => travel.min_travel,
+                        HomingDirection::Positive => travel.max_travel,
+                    };
+                }
+                let [x, y, z, e] = position;
+                planner.set_position(x, y, z, e);
+                self.phase = Phase::Located;
+                Ok(HomingStep::Homed(axes))
+            }
+            Phase::Located => {
+                let pull_off = self.config.axes;
+                self.queue_move(
+                    planner,
+                    |axis| -pull_off[axis].pull_off,
+                    self.config.seek_rate,
+                )?;
+                self.phase = Phase::PullOff;
+                Ok(HomingStep::Move { watch: 0 })
+            }
+            Phase::PullOff => {
+                self.check_released(triggered)?;
+                self.pass += 1;
+                self.phase = Phase::Start;
+                self.advance(planner, triggered)
+            }
+        }
+    }
+
+    /// Queues a move of each axis in the pass by `distance(axis)` millimetres toward its switch.
+    ///
+    /// The feed rate is scaled so that each axis moves at `rate` when several move together.
+    fn queue_move(
+        &self,
+        planner: &mut Planner,
+        distance: impl Fn(usize) -> f32,
+        rate: f32,
+    ) -> Result<(), HomingError> {
+        let axes = self.axes();
+        let mut target = planner.position();
+        for axis in each_axis(axes) {
+            target[axis] += self.config.axes[axis].direction.sign() * distance(axis);
+        }
+        let moving = axes.count_ones() as f32;
+        let [x, y, z, e] = target;
+        match planner.buffer_line_without_limits(x, y, z, e, rate * moving.sqrt()) {
+            Err(PlannerError::NoStepper(axis)) => Err(HomingError::NoStepper(axis)),
+            // The planner is empty between homing moves, so there is always room, and homing
+            // moves ignore soft limits.
+            Err(PlannerError::QueueFull | PlannerError::SoftLimit(_)) | Ok(()) => Ok(()),
+        }
+    }
+
+    /// Fails with the first axis of the pass whose switch is not in `triggered`.
+    fn check_found(&self, triggered: u8) -> Result<(), HomingError> {
+        match each_axis(self.axes() & !triggered).next() {
+            Some(axis) => Err(HomingError::SwitchNotFound(axis)),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails with the first axis of the pass whose switch is still in `triggered`.
+    fn check_released(&self, triggered: u8) -> Result<(), HomingError> {
+        match each_axis(self.axes() & triggered).next() {
+            Some(axis) => Err(HomingError::SwitchNotReleased(axis)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the axis indices whose bits are set in `axes`.
+fn each_axis(axes: u8) -> impl Iterator<Item = usize> {
+    (0..AXES).filter(move |axis| axes & 1 << axis != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commandbuffer::Target,
+        interrupts::{EndstopInputs, StepOutput, Stepper},
+        segments::{SegmentPreparer, segment_queue},
+    };
+
+    struct NoOutput;
+
+    impl StepOutput for NoOutput {
+        fn set_directions(&mut self, _direction_bits: u8) {}
+        fn step(&mut self, _step_bits: u8) {}
+    }
+
+    /// Switches fixed to the machine frame, seen through the stepper's position.
+    struct Machine {
+        /// Stepper position in steps, as of the last interrupt.
+        position: [i32; AXES],
+        /// Real position minus the stepper's, which changes whenever homing redefines it.
+        offset: [i32; AXES],
+        /// Real step position of each axis's switch and the direction it is approached from.
+        switches: [Option<(i32, HomingDirection)>; AXES],
+    }
+
+    impl Machine {
+        /// A machine standing at real X120 Y50 Z80 with switches at zero on X, Y, and Z.
+        fn new() -> Self {
+            let switch = Some((0, HomingDirection::Negative));
+            Self {
+                position: [0; AXES],
+                offset: [1_200, 500, 800, 0],
+                switches: [switch, switch, switch, None],
+            }
+        }
+
+        fn real(&self) -> [i32; AXES] {
+            [0, 1, 2, 3].map(|axis| self.position[axis] + self.offset[axis])
+        }
+    }
+
+    impl EndstopInputs for Machine {
+        fn triggered(&mut self) -> u8 {
+            let real = self.real();
+            (0..AXES)
+                .filter(|axis| match self.switches[*axis] {
+                    Some((at, HomingDirection::Negative)) => real[*axis] <= at,
+                    Some((at, HomingDirection::Positive)) => real[*axis] >= at,
+                    None => false,
+                })
+                .fold(0, |mask, axis| mask | 1 << axis)
+        }
+    }
+
+    /// Runs a cycle the way the firmware's segment preparation thread does, simulating the step
+    /// interrupt 5 ms at a time. Returns the result, the planner, and the real position.
+    fn home(
+        config: HomingConfig,
+        mut machine: Machine,
+        axes: u8,
+    ) -> (Result<(), HomingError>, Planner, [i32; AXES]) {
+        let mut planner = Planner::new(20, MachineConfig::default());
+        let (producer, consumer) = segment_queue();
+        let mut stepper = Stepper::new(consumer);
+        let mut preparer = SegmentPreparer::new(producer);
+        let mut cycle = HomingCycle::new(config, planner.config(), axes).unwrap();
+        let mut time = 0_u64;
+        loop {
+            let reached = stepper.endstops_reached();
+            let hits = stepper.endstop_hits();
+            let busy = stepper.is_busy() || preparer.is_busy() || !planner.is_empty();
+            if reached {
+                stepper.abort();
+                let [x, y, z, e] = stepper.position();
+                preparer.reset();
+                planner.clear(Target { x, y, z, e });
+            }
+            if reached || !busy {
+                loop {
+                    let Target { x, y, z, e } = planner.position_steps();
+                    let position = [x, y, z, e];
+                    // Redefining the stepper's position does not move the machine.
+                    let real = machine.real();
+                    machine.offset = [0, 1, 2, 3].map(|axis| real[axis] - position[axis]);
+                    stepper.set_position(position);
+                    machine.position = position;
+                    match cycle.advance(&mut planner, hits | machine.triggered()) {
+                        Ok(HomingStep::Move { watch }) => {
+                            stepper.watch_endstops(watch);
+                            planner.recalculate_trapezoids();
+                            break;
+                        }
+                        Ok(HomingStep::Homed(_)) => {}
+                        Ok(HomingStep::Done) => return (Ok(()), planner, machine.real()),
+                        Err(error) => return (Err(error), planner, machine.real()),
+                    }
+                }
+            }
+
+            preparer.fill(&mut planner);
+            let end = time + 5_000;
+            while time < end {
+                let interval = stepper.step_interrupt_handler(&mut NoOutput, &mut machine);
+                machine.position = stepper.position();
+                time += u64::from(interval.unwrap_or(1_000));
+            }
+            assert!(time < 600_000_000, "homing did not finish");
+        }
+    }
+
+    #[test]
+    fn homes_to_the_switches_and_pulls_off() {
+        let (result, planner, real) = home(HomingConfig::default(), Machine::new(), 0);
+        result.unwrap();
+        assert_eq!(planner.position(), [1.0, 1.0, 1.0, 0.0]);
+        assert_eq!(real, [10, 10, 10, 0]);
+    }
+
+    #[test]
+    fn positive_switch_marks_maximum_travel() {
+        let mut config = HomingConfig::default();
+        config.axes[2].direction = HomingDirection::Positive;
+        config.axes[2].pull_off = 2.0;
+        let machine = Machine {
+            switches: [None, None, Some((2_000, HomingDirection::Positive)), None],
+            ..Machine::new()
+        };
+        let (result, planner, real) = home(config, machine, 1 << 2);
+        result.unwrap();
+        assert_eq!(planner.position()[2], 198.0);
+        assert_eq!(real[2], 1_980);
+    }
+
+    #[test]
+    fn missing_switch_fails_the_cycle() {
+        let mut machine = Machine::new();
+        machine.switches[1] = None;
+        let (result, ..) = home(HomingConfig::default(), machine, 0);
+        assert_eq!(result, Err(HomingError::SwitchNotFound(1)));
+    }
+
+    #[test]
+    fn stuck_switch_fails_the_cycle() {
+        let mut machine = Machine::new();
+        machine.switches[0] = Some((100_000, HomingDirection::Negative));
+        let (result, ..) = home(HomingConfig::default(), machine, 1 << 0);
+        assert_eq!(result, Err(HomingError::SwitchNotReleased(0)));
+    }
+
+    #[test]
+    fn undriven_axis_fails_before_moving() {
+        let mut planner = Planner::new(20, MachineConfig::default());
+        planner.set_undriven_axes(1 << 0);
+        let mut cycle = HomingCycle::new(HomingConfig::default(), planner.config(), 0).unwrap();
+        // Z homes first and moves; the X and Y pass is refused before anything is queued.
+        assert_eq!(
+            cycle.advance(&mut planner, 0),
+            Ok(HomingStep::Move { watch: 1 << 2 })
+        );
+        planner.clear(planner.position_steps());
+        cycle.phase = Phase::PullOff;
+        assert_eq!(
+            cycle.advance(&mut planner, 0),
+            Err(HomingError::NoStepper(0))
+        );
+        assert!(planner.is_empty());
+    }
+
+    #[test]
+    fn unconfigured_axes_are_refused() {
+        let machine = MachineConfig::default();
+        assert_eq!(
+            HomingCycle::new(HomingConfig::default(), &machine, 1 << 3).unwrap_err(),
+            HomingError::NotConfigured(3)
+        );
+        let mut config = HomingConfig::default();
+        for axis in &mut config.axes {
+            axis.enabled = false;
+        }
+        assert_eq!(
+            HomingCycle::new(config, &machine, 0).unwrap_err(),
+            HomingError::NoAxes
+        );
+    }
+}
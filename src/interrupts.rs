@@ -1,35 +1,386 @@
-//! Prototype step executor intended to be driven by a hardware timer interrupt.
+//! Multi-axis step generation driven by a hardware timer interrupt.
+//!
+//! [`Stepper`] is a Bresenham digital differential analyser: every step event advances the
+//! block's dominant axis and steps each other axis whose accumulated error crosses zero. It
+//! consumes constant-rate segments prepared by [`crate::segments::SegmentPreparer`], so the
+//! interrupt handler uses integer arithmetic only; Xtensa interrupts do not preserve the
+//! floating-point unit.
+//!
+//! Pulses leave through the [`StepOutput`] trait and the handler returns the delay to the next
+//! event in timer ticks, which the firmware's `StepTimer` programs into a gptimer alarm and the
+//! tests below add to a simulated clock.
+//!
+//! During homing moves the handler also samples [`EndstopInputs`] and stops stepping each watched
+//! axis as soon as its switch triggers, while the remaining axes finish the move. Outside homing,
+//! [`Stepper::endstop_event`] serves as the hard-limit kill switch.
 
-use crate::commandbuffer::Block;
+use crate::{
+    config::AXES,
+    segments::{Segment, SegmentConsumer},
+};
 
-/// Tracks the block currently being emitted by the step generator.
-#[derive(Default)]
+/// Step timer resolution in ticks per second.
+pub const STEP_TIMER_FREQUENCY: u32 = 1_000_000;
+
+/// Step and direction outputs driven by [`Stepper`].
+///
+/// Bit `n` of each mask refers to axis `n` in X, Y, Z, E order. Implementations are called from
+/// interrupt context and must not block or use floating point.
+pub trait StepOutput {
+    /// Latches direction outputs; a set bit drives its axis toward negative coordinates.
+    fn set_directions(&mut self, direction_bits: u8);
+    /// Emits one step pulse on every axis whose bit is set.
+    fn step(&mut self, step_bits: u8);
+}
+
+/// Endstop switches sampled by [`Stepper`] while it watches for them.
+///
+/// Implementations are called from interrupt context and must not block or use floating point.
+pub trait EndstopInputs {
+    /// Returns a mask with bit `n` set while a switch on axis `n` is triggered.
+    fn triggered(&mut self) -> u8;
+}
+
+/// Executes queued segments one interrupt tick at a time.
 pub struct Stepper {
-    current_block: Option<Block>,
-    step_count: i32,
+    segments: SegmentConsumer,
+    current_segment: Option<Segment>,
+    /// Interrupt ticks left in the current segment.
+    ticks_remaining: u32,
+    /// Per-axis Bresenham increments for the current segment's AMASS level.
+    steps: [u32; AXES],
+    /// Bresenham error accumulators in X, Y, Z, E order.
+    counters: [u32; AXES],
+    /// Direction bits currently latched on the outputs.
+    direction_bits: u8,
+    /// Machine position in steps, updated as pulses are emitted.
+    position: [i32; AXES],
+    /// Axes that stop at their endstop during the current move.
+    endstop_watch: u8,
+    /// Watched axes whose endstop has triggered; they take no further steps.
+    endstop_hits: u8,
+    /// `true` while an endstop event kills step generation.
+    hard_limits_armed: bool,
+    /// Axes whose endstop killed step generation; no segment runs while this is nonzero.
+    limit_alarm: u8,
 }
 
 impl Stepper {
-    /// Creates an idle step executor.
-    pub fn new() -> Self {
-        Self::default()
+    /// Creates an idle step executor that reads from `segments`.
+    pub fn new(segments: SegmentConsumer) -> Self {
+        Self {
+            segments,
+            current_segment: None,
+            ticks_remaining: 0,
+            steps: [0; AXES],
+            counters: [0; AXES],
+            direction_bits: 0,
+            position: [0; AXES],
+            endstop_watch: 0,
+            endstop_hits: 0,
+            hard_limits_armed: false,
+            limit_alarm: 0,
+        }
+    }
+
+    /// Returns `true` while a segment is executing or waiting in the queue.
+    pub fn is_busy(&self) -> bool {
+        self.current_segment.is_some() || !self.segments.is_empty()
+    }
+
+    /// Returns the machine position in steps as of the last emitted pulse.
+    pub fn position(&self) -> [i32; AXES] {
+        self.position
     }
 
-    /// Replaces the active block and resets its emitted-step count.
-    pub fn execute_block(&mut self, block: Block) {
-        self.current_block = Some(block);
-        self.step_count = 0;
+    /// Redefines the machine position without moving.
+    pub fn set_position(&mut self, position: [i32; AXES]) {
+        self.position = position;
     }
 
-    /// Advances the software execution state by one step event.
+    /// Stops each axis in `axes` when its endstop triggers, until the next call. Pass zero to
+    /// step every axis unconditionally again.
+    pub fn watch_endstops(&mut self, axes: u8) {
+        self.endstop_watch = axes;
+        self.endstop_hits = 0;
+    }
+
+    /// Returns the watched axes whose endstop has triggered since [`Self::watch_endstops`].
+    pub fn endstop_hits(&self) -> u8 {
+        self.endstop_hits
+    }
+
+    /// Returns `true` once every watched axis has reached its endstop, so the rest of the move
+    /// would only run out its time without stepping.
+    pub fn endstops_reached(&self) -> bool {
+        self.endstop_watch != 0 && self.endstop_hits == self.endstop_watch
+    }
+
+    /// Enables or disables the hard-limit kill, which must be off while homing drives axes onto
+    /// their switches.
+    pub fn arm_hard_limits(&mut self, armed: bool) {
+        self.hard_limits_armed = armed;
+    }
+
+    /// Handles an endstop edge; `triggered` has bit `n` set while a switch on axis `n` is
+    /// triggered. When hard limits are armed, any triggered switch abandons all queued segments
+    /// and latches an alarm. Safe to call from interrupt context.
+    pub fn endstop_event(&mut self, triggered: u8) {
+        if self.hard_limits_armed && triggered != 0 {
+            self.limit_alarm |= triggered;
+            self.abort();
+        }
+    }
+
+    /// Returns and clears the axes whose endstop killed step generation. Segments queued in the
+    /// meantime are discarded until this is called.
+    pub fn take_limit_alarm(&mut self) -> u8 {
+        let axes = self.limit_alarm;
+        if axes != 0 {
+            self.abort();
+            self.limit_alarm = 0;
+        }
+        axes
+    }
+
+    /// Abandons the current segment and every queued one. The position keeps the last emitted
+    /// pulse.
+    pub fn abort(&mut self) {
+        self.current_segment = None;
+        self.ticks_remaining = 0;
+        while self.segments.pop().is_some() {}
+    }
+
+    /// Runs one interrupt tick.
     ///
-    /// Hardware pulse generation and timer scheduling are not implemented yet.
-    pub fn step_interrupt_handler(&mut self) {
-        if let Some(block) = &self.current_block {
-            self.step_count += 1;
-            if self.step_count >= block.steps.step_event_count {
-                self.current_block = None;
+    /// A tick that loads a block with new directions only latches the direction outputs, which
+    /// gives the drivers one full interval of setup time before the first pulse. Returns the
+    /// number of [`STEP_TIMER_FREQUENCY`] ticks until the handler should run again, or `None`
+    /// when the segment queue is empty or a hard-limit alarm holds the stepper idle.
+    pub fn step_interrupt_handler(
+        &mut self,
+        output: &mut impl StepOutput,
+        endstops: &mut impl EndstopInputs,
+    ) -> Option<u32> {
+        if self.limit_alarm != 0 {
+            self.abort();
+            return None;
+        }
+        let segment = match self.current_segment {
+            Some(segment) => segment,
+            None => {
+                let segment = self.segments.pop()?;
+                self.current_segment = Some(segment);
+                self.ticks_remaining = segment.n_step;
+                self.steps = segment
+                    .block_steps
+                    .map(|steps| steps >> segment.amass_level);
+                if segment.new_block {
+                    self.counters = [segment.block_step_event_count / 2; AXES];
+                    if segment.direction_bits != self.direction_bits {
+                        self.direction_bits = segment.direction_bits;
+                        output.set_directions(segment.direction_bits);
+                        return Some(segment.interval);
+                    }
+                }
+                segment
             }
+        };
+
+        if self.endstop_hits != self.endstop_watch {
+            self.endstop_hits |= self.endstop_watch & endstops.triggered();
         }
+
+        let mut step_bits = 0;
+        for axis in 0..AXES {
+            self.counters[axis] += self.steps[axis];
+            if self.counters[axis] > segment.block_step_event_count {
+                self.counters[axis] -= segment.block_step_event_count;
+                if self.endstop_hits & 1 << axis != 0 {
+                    continue;
+                }
+                step_bits |= 1 << axis;
+                if self.direction_bits & 1 << axis == 0 {
+                    self.position[axis] += 1;
+                } else {
+                    self.position[axis] -= 1;
+                }
+            }
+        }
+        if step_bits != 0 {
+            output.step(step_bits);
+        }
+
+        self.ticks_remaining = self.ticks_remaining.saturating_sub(1);
+        if self.ticks_remaining == 0 {
+            self.current_segment = None;
+        }
+        Some(segment.interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segments::{MAX_AMASS_LEVEL, SegmentProducer, segment_queue};
+
+    /// Records outputs against a simulated timer clock.
+    #[derive(Default)]
+    struct Recorder {
+        now: u64,
+        directions: Vec<(u64, u8)>,
+        steps: Vec<(u64, u8)>,
+    }
+
+    impl Recorder {
+        /// Returns the times at which `axis` stepped.
+        fn step_times(&self, axis: usize) -> Vec<u64> {
+            self.steps
+                .iter()
+                .filter(|(_, bits)| bits & 1 << axis != 0)
+                .map(|(time, _)| *time)
+                .collect()
+        }
+    }
+
+    impl StepOutput for Recorder {
+        fn set_directions(&mut self, direction_bits: u8) {
+            self.directions.push((self.now, direction_bits));
+        }
+
+        fn step(&mut self, step_bits: u8) {
+            self.steps.push((self.now, step_bits));
+        }
+    }
+
+    /// Switches held in a fixed state.
+    struct Switches(u8);
+
+    impl EndstopInputs for Switches {
+        fn triggered(&mut self) -> u8 {
+            self.0
+        }
+    }
+
+    /// A block's first segment covering all of its `steps` at `interval` ticks per step event.
+    fn segment(steps: [u32; AXES], direction_bits: u8, interval: u32, amass_level: u8) -> Segment {
+        let scale = 1 << MAX_AMASS_LEVEL;
+        let events = steps.into_iter().max().unwrap_or(0);
+        Segment {
+            block_steps: steps.map(|steps| steps * scale),
+            block_step_event_count: events * scale,
+            direction_bits,
+            new_block: true,
+            n_step: events << amass_level,
+            interval,
+            amass_level,
+        }
+    }
+
+    fn stepper(segments: &[Segment]) -> (Stepper, SegmentProducer) {
+        let (mut producer, consumer) = segment_queue();
+        for segment in segments {
+            producer.push(*segment).unwrap();
+        }
+        (Stepper::new(consumer), producer)
+    }
+
+    /// Runs the handler until the queue drains, advancing the clock by each returned interval.
+    fn run(stepper: &mut Stepper, output: &mut Recorder, endstops: &mut impl EndstopInputs) {
+        while let Some(interval) = stepper.step_interrupt_handler(output, endstops) {
+            output.now += u64::from(interval);
+        }
+    }
+
+    #[test]
+    fn bresenham_spreads_minor_axes() {
+        let (mut stepper, _producer) = stepper(&[segment([8, 4, 2, 0], 0, 100, 0)]);
+        let mut output = Recorder::default();
+        run(&mut stepper, &mut output, &mut Switches(0));
+
+        assert_eq!(output.now, 800);
+        assert!(output.directions.is_empty());
+        assert_eq!(output.step_times(0), [0, 100, 200, 300, 400, 500, 600, 700]);
+        assert_eq!(output.step_times(1), [100, 300, 500, 700]);
+        assert_eq!(output.step_times(2), [200, 600]);
+        assert!(output.step_times(3).is_empty());
+        assert_eq!(stepper.position(), [8, 4, 2, 0]);
+        assert!(!stepper.is_busy());
+    }
+
+    #[test]
+    fn direction_change_precedes_first_step() {
+        let (mut stepper, _producer) = stepper(&[
+            segment([3, 0, 0, 2], 0b1001, 50, 0),
+            segment([2, 0, 0, 0], 0, 50, 0),
+        ]);
+        let mut output = Recorder::default();
+        run(&mut stepper, &mut output, &mut Switches(0));
+
+        // Each direction change takes one interval of driver setup time before the next pulse.
+        assert_eq!(output.directions, [(0, 0b1001), (200, 0)]);
+        assert_eq!(output.step_times(0), [50, 100, 150, 250, 300]);
+        assert_eq!(stepper.position(), [-1, 0, 0, -2]);
+    }
+
+    #[test]
+    fn amass_runs_more_ticks_for_the_same_steps() {
+        let (mut stepper, _producer) = stepper(&[segment([4, 1, 0, 0], 0, 50, 2)]);
+        let mut output = Recorder::default();
+        run(&mut stepper, &mut output, &mut Switches(0));
+
+        assert_eq!(output.now, 16 * 50);
+        assert_eq!(output.step_times(0), [100, 300, 500, 700]);
+        assert_eq!(output.step_times(1).len(), 1);
+        assert_eq!(stepper.position(), [4, 1, 0, 0]);
+    }
+
+    #[test]
+    fn watched_endstop_stops_its_axis() {
+        let (mut stepper, _producer) = stepper(&[segment([4, 4, 0, 0], 0, 10, 0)]);
+        stepper.watch_endstops(0b01);
+        let mut output = Recorder::default();
+        run(&mut stepper, &mut output, &mut Switches(0b01));
+
+        assert_eq!(stepper.position(), [0, 4, 0, 0]);
+        assert_eq!(stepper.endstop_hits(), 0b01);
+        assert!(stepper.endstops_reached());
+
+        stepper.watch_endstops(0);
+        assert_eq!(stepper.endstop_hits(), 0);
+        assert!(!stepper.endstops_reached());
+    }
+
+    #[test]
+    fn hard_limit_kills_queued_segments() {
+        let (mut stepper, mut producer) = stepper(&[
+            segment([4, 0, 0, 0], 0, 10, 0),
+            segment([4, 0, 0, 0], 0, 10, 0),
+        ]);
+        let mut output = Recorder::default();
+        let mut switches = Switches(0);
+        stepper.arm_hard_limits(true);
+        for _ in 0..2 {
+            stepper.step_interrupt_handler(&mut output, &mut switches);
+        }
+        stepper.endstop_event(0b10);
+        assert!(!stepper.is_busy());
+        assert_eq!(stepper.position(), [2, 0, 0, 0]);
+
+        // Segments queued before the alarm is taken are discarded rather than run.
+        producer.push(segment([4, 0, 0, 0], 0, 10, 0)).unwrap();
+        assert_eq!(
+            stepper.step_interrupt_handler(&mut output, &mut switches),
+            None
+        );
+        assert_eq!(stepper.take_limit_alarm(), 0b10);
+        assert_eq!(stepper.take_limit_alarm(), 0);
+        assert!(!stepper.is_busy());
+
+        stepper.arm_hard_limits(false);
+        stepper.endstop_event(0b10);
+        producer.push(segment([4, 0, 0, 0], 0, 10, 0)).unwrap();
+        run(&mut stepper, &mut output, &mut switches);
+        assert_eq!(stepper.position(), [6, 0, 0, 0]);
     }
 }
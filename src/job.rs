@@ -0,0 +1,403 @@
+//! Stored G-code files executed as jobs.
+//!
+//! A [`Job`] reads its file one line at a time and hands parsed commands to the caller, which
+//! feeds them to the interpreter as planner space frees up. It tracks the position in the file
+//! and the time spent running, from which progress and an estimated time remaining are derived.
+
+use core::fmt;
+use std::{
+    io::{self, BufRead},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    alarm::Alarm,
+    fan::FanError,
+    gcode::{self, Command, InterpreterError, ParseError},
+    heater::HeaterError,
+    homing::HomingError,
+};
+
+/// Where a job is in its life.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JobStatus {
+    Running,
+    /// The machine is held; no further lines are fed until it resumes.
+    Paused,
+    /// Every line was executed and the machine has stopped moving.
+    Completed,
+    Aborted,
+    Failed(JobError),
+}
+
+impl JobStatus {
+    /// Returns the status's name as reported by `/status`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Paused => "paused",
+            Self::Completed => "completed",
+            Self::Aborted => "aborted",
+            Self::Failed(_) => "failed",
+        }
+    }
+}
+
+/// Why a job stopped before reaching the end of its file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JobError {
+    /// The file could not be read or is not UTF-8.
+    Read(io::ErrorKind),
+    Parse {
+        line: usize,
+        error: ParseError,
+    },
+    Interpreter {
+        line: usize,
+        error: InterpreterError,
+    },
+    Homing {
+        line: usize,
+        error: HomingError,
+    },
+    Heater {
+        line: usize,
+        error: HeaterError,
+    },
+    Fan {
+        line: usize,
+        error: FanError,
+    },
+    /// An alarm latched while the job was running.
+    Alarm(Alarm),
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(kind) => write!(f, "reading the file failed: {kind}"),
+            Self::Parse { line, error } => write!(f, "line {line}: {error}"),
+            Self::Interpreter { line, error } => write!(f, "line {line}: {error}"),
+            Self::Homing { line, error } => write!(f, "line {line}: {error}"),
+            Self::Heater { line, error } => write!(f, "line {line}: {error}"),
+            Self::Fan { line, error } => write!(f, "line {line}: {error}"),
+            Self::Alarm(alarm) => write!(f, "alarm: {alarm}"),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+/// A stored file being executed.
+pub struct Job<R> {
+    name: String,
+    reader: R,
+    /// File size in bytes.
+    size: u64,
+    /// Bytes read so far, including the line in `pending`.
+    offset: u64,
+    /// Number of the last line read, starting at 1.
+    line: usize,
+    /// The next command to execute, read ahead by [`Self::peek`].
+    pending: Option<(usize, Command)>,
+    end_of_file: bool,
+    status: JobStatus,
+    started: Instant,
+    /// When the job stopped being active, which freezes its times.
+    ended: Option<Instant>,
+    /// When the current pause began.
+    paused_since: Option<Instant>,
+    /// Total length of earlier pauses.
+    paused_for: Duration,
+}
+
+impl<R: BufRead> Job<R> {
+    /// Starts a job reading `size` bytes of G-code from `reader`.
+    pub fn new(name: impl Into<String>, reader: R, size: u64, now: Instant) -> Self {
+        Self {
+            name: name.into(),
+            reader,
+            size,
+            offset: 0,
+            line: 0,
+            pending: None,
+            end_of_file: false,
+            status: JobStatus::Running,
+            started: now,
+            ended: None,
+            paused_since: None,
+            paused_for: Duration::ZERO,
+        }
+    }
+
+    /// Returns the next command and its line number without consuming it, skipping lines
+    /// without words. Returns `None` at the end of the file.
+    pub fn peek(&mut self) -> Result<Option<(usize, Command)>, JobError> {
+        let mut source = String::new();
+        while self.pending.is_none() && !self.end_of_file {
+            source.clear();
+            let bytes_read = self
+                .reader
+                .read_line(&mut source)
+                .map_err(|error| JobError::Read(error.kind()))?;
+            if bytes_read == 0 {
+                self.end_of_file = true;
+                break;
+            }
+            self.offset += bytes_read as u64;
+            self.line += 1;
+            let line = self.line;
+            let command = gcode::parse_line(&source)
+                .and_then(|parsed| parsed.command())
+                .map_err(|error| JobError::Parse { line, error })?;
+            if !command.is_empty() {
+                self.pending = Some((line, command));
+            }
+        }
+        Ok(self.pending)
+    }
+
+    /// Consumes the command returned by [`Self::peek`] once it has been executed.
+    pub fn advance(&mut self) {
+        self.pending = None;
+    }
+}
+
+impl<R> Job<R> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.status
+    }
+
+    /// Returns `true` while the job is running or paused.
+    pub fn is_active(&self) -> bool {
+        matches!(self.status, JobStatus::Running | JobStatus::Paused)
+    }
+
+    /// Returns the number of the last line read.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the number of bytes read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the share of the file read so far, in percent.
+    pub fn progress(&self) -> f32 {
+        match self.size {
+            0 => 100.0,
+            size => (self.offset as f64 / size as f64 * 100.0) as f32,
+        }
+    }
+
+    /// Returns the wall-clock time since the job started, up to when it ended.
+    pub fn elapsed(&self, now: Instant) -> Duration {
+        self.ended
+            .unwrap_or(now)
+            .saturating_duration_since(self.started)
+    }
+
+    /// Returns the time spent running, excluding pauses.
+    pub fn running_time(&self, now: Instant) -> Duration {
+        let now = self.ended.unwrap_or(now);
+        let paused = self.paused_for
+            + self
+                .paused_since
+                .map_or(Duration::ZERO, |since| now.saturating_duration_since(since));
+        self.elapsed(now).saturating_sub(paused)
+    }
+
+    /// Estimates the time remaining by extrapolating the running time per byte read so far.
+    ///
+    /// The planner buffers moves ahead of the machine, so the estimate runs slightly early and
+    /// reaches zero while the last buffered moves are still executing.
+    pub fn eta(&self, now: Instant) -> Option<Duration> {
+        if !self.is_active() || self.offset == 0 {
+            return None;
+        }
+        let remaining = self.size.saturating_sub(self.offset) as f64 / self.offset as f64;
+        Some(self.running_time(now).mul_f64(remaining))
+    }
+
+    /// Follows the machine into or out of a hold. Returns `true` if the status changed.
+    pub fn set_paused(&mut self, paused: bool, now: Instant) -> bool {
+        match (self.status, paused) {
+            (JobStatus::Running, true) => {
+                self.status = JobStatus::Paused;
+                self.paused_since = Some(now);
+                true
+            }
+            (JobStatus::Paused, false) => {
+                self.status = JobStatus::Running;
+                self.end_pause(now);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn complete(&mut self, now: Instant) {
+        self.end(JobStatus::Completed, now);
+    }
+
+    pub fn abort(&mut self, now: Instant) {
+        self.end(JobStatus::Aborted, now);
+    }
+
+    pub fn fail(&mut self, error: JobError, now: Instant) {
+        self.end(JobStatus::Failed(error), now);
+    }
+
+    fn end(&mut self, status: JobStatus, now: Instant) {
+        if self.is_active() {
+            self.end_pause(now);
+            self.status = status;
+            self.ended = Some(now);
+        }
+    }
+
+    fn end_pause(&mut self, now: Instant) {
+        if let Some(since) = self.paused_since.take() {
+            self.paused_for += now.saturating_duration_since(since);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const PROGRAM: &str = "G21 G90\n\n; comment\n(setup)\nG1 X1 F600\nG1 X2\n";
+
+    type TestJob = Job<Cursor<&'static [u8]>>;
+
+    fn job(program: &'static str, started: Instant) -> TestJob {
+        Job::new(
+            "test.gcode",
+            Cursor::new(program.as_bytes()),
+            program.len() as u64,
+            started,
+        )
+    }
+
+    /// Returns the line numbers of every command in `job`.
+    fn lines(job: &mut TestJob) -> Vec<usize> {
+        let mut lines = Vec::new();
+        while let Some((line, _)) = job.peek().unwrap() {
+            lines.push(line);
+            job.advance();
+        }
+        lines
+    }
+
+    #[test]
+    fn peek_skips_lines_without_words() {
+        let mut job = job(PROGRAM, Instant::now());
+        let (line, first) = job.peek().unwrap().unwrap();
+        assert_eq!(line, 1);
+        // Peeking again returns the same command until it is consumed.
+        assert_eq!(job.peek().unwrap(), Some((1, first)));
+        job.advance();
+        let (line, command) = job.peek().unwrap().unwrap();
+        assert_eq!(line, 5);
+        assert_eq!(command.axes.to_array()[0], Some(1.0));
+        assert_eq!(job.line(), 5);
+        job.advance();
+        assert_eq!(lines(&mut job), [6]);
+        assert_eq!(job.peek().unwrap(), None);
+    }
+
+    #[test]
+    fn parse_error_reports_its_line() {
+        let mut job = job("G1 X1 F600\n\nG1 X\nG1 X2\n", Instant::now());
+        job.peek().unwrap();
+        job.advance();
+        let error = job.peek().unwrap_err();
+        assert!(
+            matches!(error, JobError::Parse { line: 3, .. }),
+            "{error:?}"
+        );
+        assert!(error.to_string().starts_with("line 3: "), "{error}");
+    }
+
+    #[test]
+    fn progress_and_eta_follow_the_bytes_read() {
+        let started = Instant::now();
+        let later = started + Duration::from_secs(10);
+        let mut job = job(PROGRAM, started);
+        assert_eq!(job.progress(), 0.0);
+        assert_eq!(job.eta(later), None);
+
+        // The first line is 8 of 44 bytes, read in 10 s.
+        job.peek().unwrap();
+        assert_eq!(job.offset(), 8);
+        assert!((job.progress() - 8.0 / 44.0 * 100.0).abs() < 1e-3);
+        assert_eq!(
+            job.eta(later),
+            Some(Duration::from_secs(10).mul_f64(36.0 / 8.0))
+        );
+
+        lines(&mut job);
+        assert_eq!(job.offset(), job.size());
+        assert_eq!(job.progress(), 100.0);
+        assert_eq!(job.eta(later), Some(Duration::ZERO));
+        job.complete(later);
+        assert_eq!(job.eta(later), None);
+
+        assert_eq!(self::job("", started).progress(), 100.0);
+    }
+
+    #[test]
+    fn running_time_excludes_pauses() {
+        let started = Instant::now();
+        let at = |seconds| started + Duration::from_secs(seconds);
+        let mut job = job(PROGRAM, started);
+        assert!(job.set_paused(true, at(2)));
+        assert!(!job.set_paused(true, at(3)));
+        assert_eq!(job.status(), JobStatus::Paused);
+        assert_eq!(job.running_time(at(5)), Duration::from_secs(2));
+        assert!(job.set_paused(false, at(5)));
+        assert_eq!(job.running_time(at(6)), Duration::from_secs(3));
+        assert_eq!(job.elapsed(at(6)), Duration::from_secs(6));
+    }
+
+    #[test]
+    fn times_freeze_when_the_job_ends() {
+        let started = Instant::now();
+        let at = |seconds| started + Duration::from_secs(seconds);
+        let error = JobError::Read(io::ErrorKind::InvalidData);
+        for status in [
+            JobStatus::Completed,
+            JobStatus::Aborted,
+            JobStatus::Failed(error),
+        ] {
+            let mut job = job(PROGRAM, started);
+            job.set_paused(true, at(1));
+            // Ending a paused job closes the pause.
+            match status {
+                JobStatus::Completed => job.complete(at(3)),
+                JobStatus::Aborted => job.abort(at(3)),
+                JobStatus::Failed(error) => job.fail(error, at(3)),
+                JobStatus::Running | JobStatus::Paused => unreachable!(),
+            }
+            assert_eq!(job.status(), status);
+            assert!(!job.is_active());
+            assert_eq!(job.running_time(at(100)), Duration::from_secs(1));
+            assert_eq!(job.elapsed(at(100)), Duration::from_secs(3));
+            assert!(!job.set_paused(false, at(4)));
+            // Only the first end counts.
+            job.abort(at(5));
+            assert_eq!(job.status(), status);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Hardware-independent firmware logic.
+//!
+//! G-code interpretation, motion planning, homing, the machine state machine, and temperature
+//! control live here, apart from the ESP-IDF drivers and HTTP server in the firmware binary. None
+//! of these modules touch ESP-IDF, so the library also builds for the development host, where its
+//! tests drive them through simulated clocks, switches, transports, and thermal plants:
+//!
+//! ```sh
+//! cargo +stable test --lib --no-default-features --features device_mks_tinybee \
+//!     --target x86_64-unknown-linux-gnu
+//! ```
+
+pub mod alarm;
+pub mod autotune;
+pub mod commandbuffer;
+pub mod config;
+pub mod devices;
+pub mod fan;
+pub mod gcode;
+pub mod heater;
+pub mod homing;
+pub mod interrupts;
+pub mod job;
+pub mod machine;
+pub mod planner;
+pub mod runaway;
+pub mod segments;
+pub mod temperature;
+
+pub mod peripherals {
+    //! Peripheral logic that needs no ESP-IDF driver.
+
+    pub mod expander;
+}
@@ -0,0 +1,450 @@
+//! Global machine state and the transitions between states.
+//!
+//! [`MachineState`] decides which [`Request`]s the firmware accepts and how [`Event`]s raised by
+//! motion, homing, limit switches, the safety door, and heaters move the machine from one state
+//! to the next.
+
+use core::fmt;
+
+use crate::{alarm::Alarm, homing::HomingError, planner::OverrideCommand, runaway::ThermalFault};
+
+/// What the machine is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MachineState {
+    /// Nothing is queued or moving.
+    Idle,
+    /// Queued motion is executing.
+    Run,
+    /// Motion is paused by a feed hold and waits for a cycle resume.
+    Hold,
+    /// A jog is executing.
+    Jog,
+    /// The homing cycle is running.
+    Homing,
+    /// Motion is locked out until the alarm is unlocked or homing succeeds.
+    Alarm(Alarm),
+    /// The safety door opened, so motion braked as in a feed hold and waits for the door to close
+    /// and a cycle resume.
+    Door {
+        /// `true` until the door closes again.
+        open: bool,
+    },
+}
+
+/// Something that happened to the machine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Blocks were added to the planner.
+    MotionQueued,
+    /// Jog blocks were added to the planner.
+    JogQueued,
+    /// The planner and step generator ran dry.
+    MotionComplete,
+    /// The operator asked motion to pause.
+    FeedHold,
+    /// The operator asked paused motion to continue.
+    CycleResume,
+    /// A stop or jog cancel discarded the queued motion after braking to a halt.
+    QueueFlushed,
+    HomingStarted,
+    HomingComplete,
+    HomingFailed(HomingError),
+    /// Endstops on the axes in this bit mask triggered while hard limits were armed.
+    HardLimit(u8),
+    /// The safety door input reported the door open.
+    DoorOpened,
+    /// The safety door input reported the door closed.
+    DoorClosed,
+    /// Runaway protection shut a heater down.
+    ThermalRunaway {
+        heater: &'static str,
+        fault: ThermalFault,
+    },
+    /// The operator cleared the alarm.
+    Unlock,
+}
+
+/// A command that takes effect immediately instead of waiting behind queued G-code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RealtimeCommand {
+    /// Decelerate to a stop and wait for a cycle start.
+    FeedHold,
+    /// Resume after a feed hold.
+    CycleStart,
+    /// Decelerate to a stop, then discard the queued motion.
+    Stop,
+    /// Decelerate a jog to a stop, then discard the queued jog moves.
+    JogCancel,
+    /// Change a feed, rapid, or spindle override.
+    Override(OverrideCommand),
+}
+
+impl RealtimeCommand {
+    /// Recognises Grbl's single-byte real-time commands: `!`, `~`, Ctrl-X, jog cancel `0x85`,
+    /// and the overrides from `0x90` to `0x9D`.
+    ///
+    /// Transports check every received byte before assembling lines, so these commands are never
+    /// queued behind G-code.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            b'!' => Some(Self::FeedHold),
+            b'~' => Some(Self::CycleStart),
+            0x18 => Some(Self::Stop),
+            0x85 => Some(Self::JogCancel),
+            0x90 => Some(Self::Override(OverrideCommand::Feed(None))),
+            0x91 => Some(Self::Override(OverrideCommand::Feed(Some(10)))),
+            0x92 => Some(Self::Override(OverrideCommand::Feed(Some(-10)))),
+            0x93 => Some(Self::Override(OverrideCommand::Feed(Some(1)))),
+            0x94 => Some(Self::Override(OverrideCommand::Feed(Some(-1)))),
+            0x95 => Some(Self::Override(OverrideCommand::Rapid(100))),
+            0x96 => Some(Self::Override(OverrideCommand::Rapid(50))),
+            0x97 => Some(Self::Override(OverrideCommand::Rapid(25))),
+            0x99 => Some(Self::Override(OverrideCommand::Spindle(None))),
+            0x9A => Some(Self::Override(OverrideCommand::Spindle(Some(10)))),
+            0x9B => Some(Self::Override(OverrideCommand::Spindle(Some(-10)))),
+            0x9C => Some(Self::Override(OverrideCommand::Spindle(Some(1)))),
+            0x9D => Some(Self::Override(OverrideCommand::Spindle(Some(-1)))),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RealtimeCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::FeedHold => "feed hold",
+            Self::CycleStart => "cycle start",
+            Self::Stop => "stop",
+            Self::JogCancel => "jog cancel",
+            Self::Override(_) => "override",
+        })
+    }
+}
+
+/// A command whose acceptance depends on the machine state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// One or more G-code lines.
+    Gcode,
+    /// `G28` or `$H`.
+    Home,
+    /// `$J=`.
+    Jog,
+    /// `$X`.
+    Unlock,
+    /// Starting a stored file as a job.
+    Job,
+}
+
+impl Request {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Gcode => "G-code",
+            Self::Home => "homing",
+            Self::Jog => "jogging",
+            Self::Unlock => "unlock",
+            Self::Job => "a job",
+        }
+    }
+}
+
+/// A request that the current state does not accept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StateError {
+    pub request: Request,
+    pub state: MachineState,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is not accepted in the {} state",
+            self.request.name(),
+            self.state.name()
+        )?;
+        match self.state {
+            MachineState::Alarm(alarm) if alarm.clears_by_homing() => {
+                write!(f, " ({alarm}); unlock with $X or home with $H")
+            }
+            MachineState::Alarm(alarm) => write!(f, " ({alarm}); unlock with $X"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl MachineState {
+    /// Returns the state's name as reported by `/status`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Idle => "Idle",
+            Self::Run => "Run",
+            Self::Hold => "Hold",
+            Self::Jog => "Jog",
+            Self::Homing => "Homing",
+            Self::Alarm(_) => "Alarm",
+            Self::Door { .. } => "Door",
+        }
+    }
+
+    /// Returns the latched alarm, if any.
+    pub fn alarm(&self) -> Option<Alarm> {
+        match self {
+            Self::Alarm(alarm) => Some(*alarm),
+            _ => None,
+        }
+    }
+
+    /// Fails unless `request` may run in this state.
+    ///
+    /// G-code streams in behind running, held, or door-stopped motion. Homing needs a stopped machine and is a way
+    /// out of an alarm other than a thermal one, and jogs may only follow other jogs. A job only
+    /// starts on a machine with nothing queued.
+    pub fn accepts(&self, request: Request) -> Result<(), StateError> {
+        let accepted = match request {
+            Request::Gcode => matches!(
+                self,
+                Self::Idle | Self::Run | Self::Hold | Self::Door { .. }
+            ),
+            Request::Home => match self {
+                Self::Idle => true,
+                Self::Alarm(alarm) => alarm.clears_by_homing(),
+                _ => false,
+            },
+            Request::Unlock => matches!(self, Self::Idle | Self::Alarm(_)),
+            Request::Jog => matches!(self, Self::Idle | Self::Jog),
+            Request::Job => matches!(self, Self::Idle),
+        };
+        match accepted {
+            true => Ok(()),
+            false => Err(StateError {
+                request,
+                state: *self,
+            }),
+        }
+    }
+
+    /// Returns the state `event` leads to, or `None` if the event has no effect in this state.
+    pub fn transition(&self, event: Event) -> Option<Self> {
+        use Event as E;
+        use MachineState as S;
+
+        let next = match (*self, event) {
+            // A thermal alarm replaces any other, since the heaters need attention first.
+            (S::Alarm(Alarm::Thermal { .. }), E::Unlock) => S::Idle,
+            (S::Alarm(Alarm::Thermal { .. }), _) => return None,
+            (_, E::ThermalRunaway { heater, fault }) => S::Alarm(Alarm::Thermal { heater, fault }),
+            // Other alarms keep their first cause until they are cleared.
+            (S::Alarm(_), E::Unlock | E::HomingComplete) => S::Idle,
+            (S::Alarm(_), E::HomingStarted) => S::Homing,
+            (S::Alarm(_), _) => return None,
+
+            // Hard limits are disarmed while homing drives axes onto their switches.
+            (S::Homing, E::HardLimit(_)) => return None,
+            (_, E::HardLimit(axes)) => S::Alarm(Alarm::HardLimit(axes)),
+            (S::Homing, E::HomingComplete) => S::Idle,
+            (S::Homing, E::HomingFailed(error)) => S::Alarm(Alarm::Homing(error)),
+            (S::Homing, E::DoorOpened) => S::Alarm(Alarm::DoorDuringHoming),
+            (S::Homing, _) => return None,
+
+            // An open door holds motion until it closes and the cycle is resumed. Queued motion
+            // stays put, and a stop flushes it without leaving the state.
+            (S::Door { .. }, E::DoorOpened) => S::Door { open: true },
+            (S::Door { open: true }, E::DoorClosed) => S::Door { open: false },
+            (S::Door { open: false }, E::CycleResume) => S::Run,
+            (S::Door { .. }, _) => return None,
+            (_, E::DoorOpened) => S::Door { open: true },
+
+            (S::Idle, E::MotionQueued) => S::Run,
+            (S::Idle, E::JogQueued) => S::Jog,
+            (S::Idle, E::HomingStarted) => S::Homing,
+            (S::Run | S::Jog, E::MotionComplete) => S::Idle,
+            (S::Run, E::FeedHold) => S::Hold,
+            (S::Hold, E::CycleResume) => S::Run,
+            (S::Hold | S::Jog, E::QueueFlushed) => S::Idle,
+            _ => return None,
+        };
+        Some(next)
+    }
+
+    /// Applies `event`. Returns `true` if the state changed.
+    pub fn handle(&mut self, event: Event) -> bool {
+        match self.transition(event) {
+            Some(next) if next != *self => {
+                *self = next;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temperature::SensorFault;
+
+    use Event as E;
+    use MachineState as S;
+
+    const RUNAWAY: Event = E::ThermalRunaway {
+        heater: "bed",
+        fault: ThermalFault::Sensor(SensorFault::Open),
+    };
+
+    /// Applies `events` in order from `state` and returns where the machine ends up.
+    fn after(state: MachineState, events: &[Event]) -> MachineState {
+        let mut state = state;
+        for event in events {
+            state.handle(*event);
+        }
+        state
+    }
+
+    #[test]
+    fn motion_cycle() {
+        assert_eq!(after(S::Idle, &[E::MotionQueued]), S::Run);
+        assert_eq!(after(S::Idle, &[E::MotionQueued, E::FeedHold]), S::Hold);
+        assert_eq!(
+            after(S::Idle, &[E::MotionQueued, E::FeedHold, E::CycleResume]),
+            S::Run
+        );
+        assert_eq!(
+            after(S::Idle, &[E::MotionQueued, E::MotionComplete]),
+            S::Idle
+        );
+        assert_eq!(after(S::Hold, &[E::QueueFlushed]), S::Idle);
+        assert_eq!(after(S::Idle, &[E::JogQueued, E::MotionComplete]), S::Idle);
+        assert_eq!(after(S::Jog, &[E::QueueFlushed]), S::Idle);
+        // A jog cannot be held, and a feed hold outside motion does nothing.
+        assert_eq!(S::Jog.transition(E::FeedHold), None);
+        assert_eq!(S::Idle.transition(E::FeedHold), None);
+    }
+
+    #[test]
+    fn homing_cycle() {
+        assert_eq!(after(S::Idle, &[E::HomingStarted]), S::Homing);
+        assert_eq!(after(S::Homing, &[E::HomingComplete]), S::Idle);
+        assert_eq!(
+            after(
+                S::Homing,
+                &[E::HomingFailed(HomingError::SwitchNotFound(0))]
+            ),
+            S::Alarm(Alarm::Homing(HomingError::SwitchNotFound(0)))
+        );
+        // Homing drives axes onto their switches, so hard limits do not apply.
+        assert_eq!(S::Homing.transition(E::HardLimit(1)), None);
+        assert_eq!(S::Homing.transition(E::MotionQueued), None);
+    }
+
+    #[test]
+    fn door_holds_until_closed_and_resumed() {
+        let open = S::Door { open: true };
+        let closed = S::Door { open: false };
+        for state in [S::Idle, S::Run, S::Hold, S::Jog] {
+            assert_eq!(after(state, &[E::DoorOpened]), open, "{state:?}");
+        }
+        // Resuming, finishing, or flushing motion does not leave the state while the door is open.
+        for event in [
+            E::CycleResume,
+            E::MotionComplete,
+            E::QueueFlushed,
+            E::FeedHold,
+        ] {
+            assert_eq!(open.transition(event), None, "{event:?}");
+        }
+        assert_eq!(after(open, &[E::DoorClosed]), closed);
+        assert_eq!(after(closed, &[E::DoorOpened]), open);
+        assert_eq!(after(closed, &[E::CycleResume]), S::Run);
+        assert_eq!(
+            after(open, &[E::HardLimit(1)]),
+            S::Alarm(Alarm::HardLimit(1))
+        );
+
+        assert_eq!(
+            after(S::Homing, &[E::DoorOpened]),
+            S::Alarm(Alarm::DoorDuringHoming)
+        );
+        // An alarm keeps its cause when the door opens.
+        let limit = S::Alarm(Alarm::HardLimit(1));
+        assert_eq!(limit.transition(E::DoorOpened), None);
+    }
+
+    #[test]
+    fn alarms_latch_their_first_cause() {
+        let limit = after(S::Run, &[E::HardLimit(0b10)]);
+        assert_eq!(limit, S::Alarm(Alarm::HardLimit(0b10)));
+        assert_eq!(after(limit, &[E::HardLimit(0b01), E::MotionQueued]), limit);
+        assert_eq!(after(limit, &[E::Unlock]), S::Idle);
+        assert_eq!(
+            after(limit, &[E::HomingStarted, E::HomingComplete]),
+            S::Idle
+        );
+    }
+
+    #[test]
+    fn thermal_alarm_replaces_others_and_needs_unlock() {
+        let thermal = after(S::Alarm(Alarm::HardLimit(1)), &[RUNAWAY]);
+        assert!(matches!(thermal, S::Alarm(Alarm::Thermal { .. })));
+        assert_eq!(after(S::Homing, &[RUNAWAY]), thermal);
+        assert_eq!(
+            after(thermal, &[E::HomingStarted, E::HardLimit(1)]),
+            thermal
+        );
+        assert_eq!(after(thermal, &[E::Unlock]), S::Idle);
+        assert!(thermal.accepts(Request::Home).is_err());
+        assert!(thermal.accepts(Request::Unlock).is_ok());
+    }
+
+    #[test]
+    fn requests_gated_by_state() {
+        let alarm = S::Alarm(Alarm::HardLimit(1));
+        let accepted = |state: MachineState| {
+            [
+                Request::Gcode,
+                Request::Home,
+                Request::Jog,
+                Request::Unlock,
+                Request::Job,
+            ]
+            .map(|request| state.accepts(request).is_ok())
+        };
+        assert_eq!(accepted(S::Idle), [true, true, true, true, true]);
+        assert_eq!(accepted(S::Run), [true, false, false, false, false]);
+        assert_eq!(accepted(S::Hold), [true, false, false, false, false]);
+        assert_eq!(accepted(S::Jog), [false, false, true, false, false]);
+        assert_eq!(accepted(S::Homing), [false; 5]);
+        assert_eq!(
+            accepted(S::Door { open: false }),
+            [true, false, false, false, false]
+        );
+        assert_eq!(accepted(alarm), [false, true, false, true, false]);
+
+        let error = alarm.accepts(Request::Gcode).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "G-code is not accepted in the Alarm state (hard limit triggered on X); \
+             unlock with $X or home with $H"
+        );
+    }
+
+    #[test]
+    fn realtime_bytes() {
+        assert_eq!(
+            RealtimeCommand::from_byte(b'!'),
+            Some(RealtimeCommand::FeedHold)
+        );
+        assert_eq!(
+            RealtimeCommand::from_byte(0x18),
+            Some(RealtimeCommand::Stop)
+        );
+        assert_eq!(
+            RealtimeCommand::from_byte(0x91),
+            Some(RealtimeCommand::Override(OverrideCommand::Feed(Some(10))))
+        );
+        assert_eq!(RealtimeCommand::from_byte(0x98), None);
+        assert_eq!(RealtimeCommand::from_byte(b'G'), None);
+    }
+}
@@ -1,37 +1,82 @@
 use anyhow::{Result, anyhow};
 use embedded_svc::{
-    http::Method,
-    io::Write,
+    http::{Headers, Method},
+    io::{Read, Write},
     wifi::{AccessPointConfiguration, AuthMethod, Configuration as WifiConfiguration},
 };
-use esp_idf_hal::{gpio::PinDriver, modem::Modem, peripherals::Peripherals};
+use esp_idf_hal::{modem::Modem, peripherals::Peripherals};
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
     http::server::{Configuration, EspHttpServer},
     nvs::EspDefaultNvsPartition,
     wifi::{BlockingWifi, EspWifi},
 };
-use esp_idf_sys::esp_timer_get_time;
+use esp_idf_sys::{EspError, esp_timer_get_time};
 use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
     sync::{Arc, Mutex},
-    thread::sleep,
-    time::Duration,
+    thread::{self, sleep},
+    time::{Duration, Instant},
 };
 
 // Importing the crate activates the startup symbols supplied by its `binstart` feature.
 use esp_idf_sys as _;
 
-pub mod commandbuffer;
-pub mod devices;
-pub mod interrupts;
 pub mod peripherals;
-pub mod planner;
 pub mod serial;
+pub mod settings;
+pub mod storage;
 pub mod wifi;
 
-use crate::planner::Planner;
+pub use alumina_firmware::{
+    alarm, autotune, commandbuffer, config, devices, fan, gcode, heater, homing, interrupts, job,
+    machine, planner, runaway, segments, temperature,
+};
+
+use crate::{
+    alarm::Alarm,
+    autotune::{Autotune, AutotuneStatus, TuningRule},
+    commandbuffer::Target,
+    config::{AXES, AXIS_NAMES, HomingConfig, MachineConfig, StepperConfig},
+    devices::{Board, SelectedBoard},
+    fan::{Fan, FanConfig, FanError},
+    gcode::{Command, HeaterSelect, Interpreter, InterpreterError, NonModal},
+    heater::{Heater, HeaterConfig, HeaterError, PidGains},
+    homing::{HomingCycle, HomingError, HomingStep},
+    interrupts::{EndstopInputs, StepOutput, Stepper},
+    job::{Job, JobError, JobStatus},
+    machine::{Event, MachineState, RealtimeCommand, Request, StateError},
+    peripherals::{
+        adc::AdcInputs,
+        board_io::BoardIo,
+        endstops::{GpioEndstops, GpioSafetyDoor},
+        expander::OutputPins,
+        step_output::PinStepOutput,
+        step_timer::StepTimer,
+    },
+    planner::Planner,
+    runaway::ThermalFault,
+    segments::SegmentPreparer,
+    settings::Settings,
+    storage::{FileStore, StorageError},
+    temperature::{Sensor, SensorConfig},
+};
 
 const BLOCK_BUFFER_SIZE: usize = 20;
+/// How often the segment buffer is topped up from the planner.
+const SEGMENT_PREPARATION_INTERVAL: Duration = Duration::from_millis(5);
+/// How often every temperature sensor is sampled and heater power is recomputed.
+const TEMPERATURE_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+/// How often heater and fan outputs are switched to follow their software PWM.
+const OUTPUT_PWM_INTERVAL: Duration = Duration::from_millis(10);
+/// How often a running job is fed into the planner.
+const JOB_FEED_INTERVAL: Duration = Duration::from_millis(10);
+/// Largest `POST /queue` body accepted, in bytes.
+const QUEUE_BODY_LIMIT: usize = 16 * 1_024;
+/// Largest `POST /job` or `POST /autotune` body accepted, in bytes.
+const COMMAND_BODY_LIMIT: usize = 256;
 const WIFI_SSID: &str = "Alumina";
 const WIFI_PSK: &str = "";
 
@@ -52,14 +97,87 @@ const UI_FAVICON: &[u8] = include_bytes!(concat!(
     "/../alumina-interface/dist/favicon.ico"
 ));
 
+/// A stored file running as a job.
+type StoredJob = Job<BufReader<File>>;
+
+/// A heater output regulated by its temperature sensor, listed in the order of the sensor's
+/// [`AdcInputs`] input.
+struct HeaterChannel {
+    name: &'static str,
+    /// Pin switching the heater.
+    output: i32,
+    sensor: Sensor,
+    heater: Heater,
+}
+
+/// A fan output, listed in the order of the board's fans.
+struct FanChannel {
+    name: &'static str,
+    /// Pin switching the fan.
+    output: i32,
+    /// Heater whose temperature switches the fan on, if it is fitted.
+    heater: Option<&'static str>,
+    fan: Fan,
+}
+
+/// Machine state shared by the HTTP handlers, the segment preparation thread, and the heater and
+/// fan thread.
+struct MachineControl {
+    homing_config: HomingConfig,
+    /// Homing cycle requested from `/queue` and run by the segment preparation thread.
+    homing: Option<HomingCycle>,
+    /// Decides which requests are accepted; see [`MachineState::accepts`].
+    state: MachineState,
+    /// A stop or jog cancel is braking the machine; queued motion is discarded once it is held.
+    stop_requested: bool,
+    /// A stored file is feeding the interpreter, so G-code and jogs from `/queue` are refused.
+    job_running: bool,
+    /// Heaters with a sensor; the rest of the board's heaters stay off.
+    heaters: Vec<HeaterChannel>,
+    /// Fans whose outputs the board can drive.
+    fans: Vec<FanChannel>,
+    /// Runaway fault raised by the heater thread, which has already turned the heaters off. The
+    /// segment preparation thread stops the machine and latches the alarm.
+    thermal_fault: Option<(&'static str, ThermalFault)>,
+}
+
+impl MachineControl {
+    /// Returns the heater addressed by a temperature M-code.
+    fn heater_mut(&mut self, select: HeaterSelect) -> Option<&mut HeaterChannel> {
+        let name = match select {
+            HeaterSelect::Hotend(tool) => format!("e{tool}"),
+            HeaterSelect::Bed => "bed".to_string(),
+        };
+        self.heaters.iter_mut().find(|channel| channel.name == name)
+    }
+
+    /// Returns the fan addressed by the `P` of `M106` or `M107`, counting the board's fans from 0.
+    fn fan_mut(&mut self, index: u8) -> Option<&mut FanChannel> {
+        let name = SelectedBoard::FANS.get(usize::from(index))?.name;
+        self.fans.iter_mut().find(|channel| channel.name == name)
+    }
+
+    /// Returns the first heater whose `M109`, `M190`, or `M303` is holding back later lines.
+    fn busy_heater(&self) -> Option<&HeaterChannel> {
+        self.heaters.iter().find(|channel| channel.heater.is_busy())
+    }
+
+    /// Turns every heater off.
+    fn turn_off_heaters(&mut self) {
+        for channel in &mut self.heaters {
+            channel.heater.turn_off();
+        }
+    }
+}
+
 /// Starts an ESP32 access point and waits for its network interface to become ready.
 fn start_access_point(
     ssid: &str,
     password: &str,
     modem: Modem,
     system_event_loop: EspSystemEventLoop,
+    nvs: EspDefaultNvsPartition,
 ) -> Result<BlockingWifi<EspWifi<'static>>> {
-    let nvs = EspDefaultNvsPartition::take()?;
     let mut wifi = BlockingWifi::wrap(
         EspWifi::new(modem, system_event_loop.clone(), Some(nvs))?,
         system_event_loop,
@@ -99,27 +217,1318 @@ fn start_access_point(
     Ok(wifi)
 }
 
+/// Why a parsed line was not executed.
+enum LineError {
+    State(StateError),
+    Interpreter(InterpreterError),
+    /// The line was executed, but the homing cycle it requested could not be planned.
+    Homing(HomingError),
+    /// The line was executed, but its temperature target could not be applied.
+    Heater(HeaterError),
+    /// The line was executed, but its fan speed could not be applied.
+    Fan(FanError),
+    /// An earlier `M109` or `M190` is waiting for the named heater to reach its target, or an
+    /// `M303` is tuning it.
+    HeaterBusy {
+        name: &'static str,
+        target: f32,
+        tuning: bool,
+    },
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::State(error) => write!(f, "{error}"),
+            Self::Interpreter(error) => write!(f, "{error}"),
+            Self::Homing(error) => write!(f, "{error}"),
+            Self::Heater(error) => write!(f, "{error}"),
+            Self::Fan(error) => write!(f, "{error}"),
+            Self::HeaterBusy {
+                name,
+                target,
+                tuning: false,
+            } => write!(f, "waiting for {name} to reach {target:.0} °C"),
+            Self::HeaterBusy {
+                name,
+                target,
+                tuning: true,
+            } => write!(f, "tuning {name} at {target:.0} °C"),
+        }
+    }
+}
+
+/// Executes one parsed line and raises the machine events it causes. Returns `true` if the line
+/// started the homing cycle, after which no further lines are accepted until the cycle ends.
+///
+/// Lines are held back while a heater waits for its target or is being tuned, which queued motion
+/// does not affect.
+fn execute_line(
+    interpreter: &mut Interpreter,
+    planner: &mut Planner,
+    control: &mut MachineControl,
+    command: &Command,
+) -> Result<bool, LineError> {
+    let request = match command.non_modal {
+        Some(NonModal::Home) => Request::Home,
+        _ => Request::Gcode,
+    };
+    control.state.accepts(request).map_err(LineError::State)?;
+    if let Some(channel) = control.busy_heater() {
+        return Err(LineError::HeaterBusy {
+            name: channel.name,
+            target: channel.heater.target(),
+            tuning: channel.heater.wait().is_none(),
+        });
+    }
+    interpreter
+        .execute(command, planner)
+        .map_err(LineError::Interpreter)?;
+    if let Some(request) = interpreter.take_temperature_request() {
+        let channel = control
+            .heater_mut(request.heater)
+            .ok_or(LineError::Heater(HeaterError::NotFitted(request.heater)))?;
+        channel
+            .heater
+            .set_target(request.celsius, request.wait)
+            .map_err(LineError::Heater)?;
+        log::info!("{} target: {:.0} °C", channel.name, request.celsius);
+    }
+    if let Some(request) = interpreter.take_autotune_request() {
+        let channel = control
+            .heater_mut(request.heater)
+            .ok_or(LineError::Heater(HeaterError::NotFitted(request.heater)))?;
+        // As in Marlin, `U1` applies the classic Ziegler–Nichols gains.
+        let rule = request.apply.then_some(TuningRule::ZieglerNichols);
+        channel
+            .heater
+            .start_autotune(request.celsius, request.cycles, rule)
+            .map_err(LineError::Heater)?;
+        log::info!(
+            "{} autotune: {} cycles around {:.0} °C",
+            channel.name,
+            request.cycles,
+            request.celsius
+        );
+    }
+    if let Some(request) = interpreter.take_fan_request() {
+        let channel = control
+            .fan_mut(request.fan)
+            .ok_or(LineError::Fan(FanError::NotFitted(request.fan)))?;
+        channel.fan.set_speed(request.speed);
+        log::info!("{} speed: {:.0}%", channel.name, request.speed * 100.0);
+    }
+    let Some(axes) = interpreter.take_homing_request() else {
+        if !planner.is_empty() || interpreter.has_pending_arc() {
+            control.state.handle(Event::MotionQueued);
+        }
+        return Ok(false);
+    };
+    let cycle = HomingCycle::new(control.homing_config, planner.config(), axes)
+        .map_err(LineError::Homing)?;
+    control.homing = Some(cycle);
+    control.state.handle(Event::HomingStarted);
+    Ok(true)
+}
+
+/// Parses one or more G-code lines and executes them in order.
+///
+/// Every line is parsed before the first one is executed, so a syntax error leaves the modal state
+/// and planner untouched. Execution stops at the first line the machine state does not accept. A
+/// `G28` starts the homing cycle and ends the request; lines after it are refused until homing
+/// finishes, as are lines after an `M109` or `M190` until its heater is at temperature. Lines are
+/// refused while a job is running. Returns the HTTP status, reason phrase, and plain-text body to
+/// send.
+fn queue_gcode(
+    interpreter: &Mutex<Interpreter>,
+    planner: &Mutex<Planner>,
+    control: &Mutex<MachineControl>,
+    program: &str,
+) -> (u16, &'static str, String) {
+    let mut commands = Vec::new();
+    for (index, source) in program.lines().enumerate() {
+        match gcode::parse_line(source).and_then(|line| line.command()) {
+            Ok(command) if command.is_empty() => {}
+            Ok(command) => commands.push((index + 1, command)),
+            Err(error) => {
+                log::warn!("Rejected G-code line {}: {error}", index + 1);
+                return (400, "Bad Request", format!("line {}: {error}\n", index + 1));
+            }
+        }
+    }
+
+    let mut interpreter = interpreter
+        .lock()
+        .expect("G-code interpreter lock poisoned");
+    let mut planner = planner.lock().expect("motion planner lock poisoned");
+    let mut control = control.lock().expect("machine control lock poisoned");
+    if control.job_running {
+        return (409, "Conflict", "a job is running\n".to_string());
+    }
+    let mut executed = 0_usize;
+    let mut result = (200, "OK", String::new());
+    for (index, (line, command)) in commands.iter().enumerate() {
+        match execute_line(&mut interpreter, &mut planner, &mut control, command) {
+            Ok(false) => executed += 1,
+            Ok(true) => {
+                executed += 1;
+                if let Some((next, _)) = commands.get(index + 1) {
+                    result = (
+                        503,
+                        "Service Unavailable",
+                        format!("line {next}: homing in progress after {executed} lines\n"),
+                    );
+                }
+                break;
+            }
+            Err(LineError::State(error)) => {
+                result = (409, "Conflict", format!("line {line}: {error}\n"));
+                break;
+            }
+            Err(LineError::Interpreter(InterpreterError::QueueFull)) => {
+                result = (
+                    503,
+                    "Service Unavailable",
+                    format!("line {line}: motion queue full after {executed} lines\n"),
+                );
+                break;
+            }
+            Err(error @ LineError::HeaterBusy { .. }) => {
+                result = (
+                    503,
+                    "Service Unavailable",
+                    format!("line {line}: {error} after {executed} lines\n"),
+                );
+                break;
+            }
+            Err(error) => {
+                log::warn!("Rejected G-code line {line}: {error}");
+                result = (
+                    400,
+                    "Bad Request",
+                    format!("line {line}: {error}; {executed} lines executed\n"),
+                );
+                break;
+            }
+        }
+    }
+    planner.recalculate_trapezoids();
+
+    if result.0 == 200 {
+        result.2 = format!("Executed {executed} lines\n");
+    }
+    result
+}
+
+/// Parses the words after `$J=` and queues the jog. Returns the HTTP status, reason phrase, and
+/// plain-text body to send.
+fn queue_jog(
+    interpreter: &Mutex<Interpreter>,
+    planner: &Mutex<Planner>,
+    control: &Mutex<MachineControl>,
+    source: &str,
+) -> (u16, &'static str, String) {
+    let command = match gcode::parse_line(source).and_then(|line| line.command()) {
+        Ok(command) => command,
+        Err(error) => return (400, "Bad Request", format!("{error}\n")),
+    };
+
+    let mut interpreter = interpreter
+        .lock()
+        .expect("G-code interpreter lock poisoned");
+    let mut planner = planner.lock().expect("motion planner lock poisoned");
+    let mut control = control.lock().expect("machine control lock poisoned");
+    if let Err(error) = control.state.accepts(Request::Jog) {
+        return (409, "Conflict", format!("{error}\n"));
+    }
+    if control.job_running {
+        return (409, "Conflict", "a job is running\n".to_string());
+    }
+    if control.stop_requested {
+        return (409, "Conflict", "jog cancel in progress\n".to_string());
+    }
+    match interpreter.jog(&command, &mut planner) {
+        Ok(()) => {
+            planner.recalculate_trapezoids();
+            control.state.handle(Event::JogQueued);
+            (200, "OK", "Jogging\n".to_string())
+        }
+        Err(InterpreterError::QueueFull) => (
+            503,
+            "Service Unavailable",
+            "motion queue full\n".to_string(),
+        ),
+        Err(error) => {
+            log::warn!("Rejected jog: {error}");
+            (400, "Bad Request", format!("{error}\n"))
+        }
+    }
+}
+
+/// Abandons queued motion and makes the planner continue from the last emitted step.
+fn stop_motion<O, E>(
+    step_timer: &StepTimer<O, E>,
+    preparer: &mut SegmentPreparer,
+    planner: &mut Planner,
+) where
+    O: StepOutput + Send + 'static,
+    E: EndstopInputs + Send + 'static,
+{
+    let [x, y, z, e] = step_timer.with_stepper(|stepper| {
+        stepper.abort();
+        stepper.position()
+    });
+    preparer.reset();
+    planner.clear(Target { x, y, z, e });
+}
+
+/// Advances `cycle` once the previous homing move has stopped. Returns `Ok(true)` once every pass
+/// has finished.
+fn run_homing<O, E>(
+    cycle: &mut HomingCycle,
+    step_timer: &StepTimer<O, E>,
+    endstops: &GpioEndstops,
+    preparer: &mut SegmentPreparer,
+    planner: &mut Planner,
+) -> Result<bool, HomingError>
+where
+    O: StepOutput + Send + 'static,
+    E: EndstopInputs + Send + 'static,
+{
+    let (busy, reached, hits) = step_timer.with_stepper(|stepper| {
+        (
+            stepper.is_busy(),
+            stepper.endstops_reached(),
+            stepper.endstop_hits(),
+        )
+    });
+    if reached {
+        // Every watched axis is at its switch; the rest of the move would only run out its time.
+        stop_motion(step_timer, preparer, planner);
+    } else if busy || preparer.is_busy() || !planner.is_empty() {
+        return Ok(false);
+    }
+
+    loop {
+        // The machine is stopped, so the planner holds its position.
+        let position = planner.position_steps();
+        step_timer.with_stepper(|stepper| {
+            stepper.set_position([position.x, position.y, position.z, position.e])
+        });
+        match cycle.advance(planner, hits | endstops.triggered())? {
+            HomingStep::Move { watch } => {
+                step_timer.with_stepper(|stepper| stepper.watch_endstops(watch));
+                planner.recalculate_trapezoids();
+                return Ok(false);
+            }
+            HomingStep::Homed(axes) => {
+                let names = AXIS_NAMES
+                    .iter()
+                    .enumerate()
+                    .filter(|(axis, _)| axes & 1 << axis != 0)
+                    .map(|(_, name)| *name)
+                    .collect::<String>();
+                log::info!("Homed {names}");
+            }
+            HomingStep::Done => return Ok(true),
+        }
+    }
+}
+
+/// Clears a latched alarm. Returns the HTTP status, reason phrase, and plain-text body to send.
+fn unlock(control: &Mutex<MachineControl>) -> (u16, &'static str, String) {
+    let mut control = control.lock().expect("machine control lock poisoned");
+    if let Err(error) = control.state.accepts(Request::Unlock) {
+        return (409, "Conflict", format!("{error}\n"));
+    }
+    match control.state.alarm() {
+        Some(alarm) => {
+            control.state.handle(Event::Unlock);
+            // Heaters stay off until they are given new targets.
+            for channel in &mut control.heaters {
+                channel.heater.clear_fault();
+            }
+            log::warn!("Alarm cleared by unlock: {alarm}");
+            (200, "OK", format!("Cleared alarm: {alarm}\n"))
+        }
+        None => (200, "OK", "No alarm\n".to_string()),
+    }
+}
+
+/// Applies a real-time command.
+///
+/// Overrides only take the planner lock and other commands only the machine control lock, so the
+/// command is not queued behind G-code. The segment preparation thread carries out state changes.
+/// Returns the HTTP status, reason phrase, and plain-text body to send.
+fn realtime(
+    planner: &Mutex<Planner>,
+    control: &Mutex<MachineControl>,
+    command: RealtimeCommand,
+) -> (u16, &'static str, String) {
+    if let RealtimeCommand::Override(change) = command {
+        let mut planner = planner.lock().expect("motion planner lock poisoned");
+        let mut overrides = planner.overrides();
+        overrides.apply(change);
+        planner.set_overrides(overrides);
+        log::info!("Overrides: {overrides}");
+        return (200, "OK", format!("{overrides}\n"));
+    }
+
+    let mut control = control.lock().expect("machine control lock poisoned");
+    let previous = control.state;
+    let jogging = control.state == MachineState::Jog;
+    let accepted = match command {
+        // As in Grbl, a feed hold while jogging cancels the jog.
+        RealtimeCommand::FeedHold | RealtimeCommand::JogCancel if jogging => {
+            control.stop_requested = true;
+            true
+        }
+        RealtimeCommand::FeedHold => control.state.handle(Event::FeedHold),
+        RealtimeCommand::JogCancel => false,
+        RealtimeCommand::CycleStart => {
+            let resumed = control.state.handle(Event::CycleResume);
+            control.stop_requested &= !resumed;
+            resumed
+        }
+        RealtimeCommand::Stop => {
+            // A stop also releases lines held back by a temperature wait, keeping the target, and
+            // ends a tuning run.
+            let waiting = control.busy_heater().is_some();
+            for channel in &mut control.heaters {
+                channel.heater.cancel_wait();
+            }
+            let stopping = matches!(
+                control.state,
+                MachineState::Hold | MachineState::Jog | MachineState::Door { .. }
+            ) || control.state.handle(Event::FeedHold);
+            control.stop_requested |= stopping;
+            stopping || waiting
+        }
+        RealtimeCommand::Override(_) => unreachable!("overrides are applied above"),
+    };
+    if accepted {
+        log::info!("{command}: {} -> {}", previous.name(), control.state.name());
+        (200, "OK", format!("{}\n", control.state.name()))
+    } else {
+        (
+            409,
+            "Conflict",
+            format!("{command} has no effect in the {} state\n", previous.name()),
+        )
+    }
+}
+
+/// Returns the HTTP status and reason phrase for a failed file operation.
+fn storage_error_status(error: &StorageError) -> (u16, &'static str) {
+    match error {
+        StorageError::InvalidName => (400, "Bad Request"),
+        StorageError::NotFound => (404, "Not Found"),
+        StorageError::InsufficientSpace { .. } => (507, "Insufficient Storage"),
+        StorageError::Io(_) | StorageError::Esp(_) => (500, "Internal Server Error"),
+    }
+}
+
+/// Starts running the stored file `name`. Returns the HTTP status, reason phrase, and plain-text
+/// body to send.
+fn start_job(
+    job: &Mutex<Option<StoredJob>>,
+    files: &FileStore,
+    control: &Mutex<MachineControl>,
+    name: &str,
+) -> (u16, &'static str, String) {
+    let mut job = job.lock().expect("job lock poisoned");
+    if let Some(running) = job.as_ref().filter(|job| job.is_active()) {
+        let body = format!("job {} is already running\n", running.name());
+        return (409, "Conflict", body);
+    }
+    let file = match files.open(name) {
+        Ok(file) => file,
+        Err(error) => {
+            let (status, reason) = storage_error_status(&error);
+            return (status, reason, format!("{error}\n"));
+        }
+    };
+    let size = match file.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(error) => return (500, "Internal Server Error", format!("{error}\n")),
+    };
+
+    let mut control = control.lock().expect("machine control lock poisoned");
+    if let Err(error) = control.state.accepts(Request::Job) {
+        return (409, "Conflict", format!("{error}\n"));
+    }
+    control.job_running = true;
+    *job = Some(Job::new(name, BufReader::new(file), size, Instant::now()));
+    log::info!("Started job {name}: {size} bytes");
+    (200, "OK", format!("started: {name}\n"))
+}
+
+/// Ends the running job, stops the machine, discarding queued motion, and turns the heaters off.
+/// Returns the plain-text body to send, or `None` if no job is running.
+fn abort_job(
+    job: &Mutex<Option<StoredJob>>,
+    planner: &Mutex<Planner>,
+    control: &Mutex<MachineControl>,
+) -> Option<String> {
+    let mut job = job.lock().expect("job lock poisoned");
+    let running = job.as_mut().filter(|job| job.is_active())?;
+    running.abort(Instant::now());
+    {
+        let mut control = control.lock().expect("machine control lock poisoned");
+        control.job_running = false;
+        control.turn_off_heaters();
+    }
+    // The stop has no effect if the job was waiting on homing with nothing queued.
+    let _ = realtime(planner, control, RealtimeCommand::Stop);
+    log::info!("Aborted job {} at line {}", running.name(), running.line());
+    Some(format!(
+        "aborted: {} at line {}\n",
+        running.name(),
+        running.line()
+    ))
+}
+
+/// Feeds `job` into the interpreter until the planner is full, the machine is held or busy with
+/// something else, a heater is waiting for its target, or the file ends. Ends the job once its
+/// last move has finished, or when a line fails or an alarm latches; a job that fails turns the
+/// heaters off.
+fn feed_job(
+    job: &mut StoredJob,
+    interpreter: &Mutex<Interpreter>,
+    planner: &Mutex<Planner>,
+    control: &Mutex<MachineControl>,
+) {
+    loop {
+        // Reading ahead before taking the motion locks keeps flash access from delaying segment
+        // preparation.
+        let next = job.peek();
+        let now = Instant::now();
+        let mut interpreter = interpreter
+            .lock()
+            .expect("G-code interpreter lock poisoned");
+        let mut planner = planner.lock().expect("motion planner lock poisoned");
+        let mut control = control.lock().expect("machine control lock poisoned");
+
+        let held = matches!(
+            control.state,
+            MachineState::Hold | MachineState::Door { .. }
+        );
+        job.set_paused(held, now);
+        let mut fed = false;
+        match (next, control.state.alarm()) {
+            (Err(error), _) => job.fail(error, now),
+            (_, Some(alarm)) => job.fail(JobError::Alarm(alarm), now),
+            _ if held => {}
+            (Ok(None), None) => {
+                let drained = control.state == MachineState::Idle
+                    && planner.is_empty()
+                    && !interpreter.has_pending_arc();
+                if drained {
+                    job.complete(now);
+                }
+            }
+            (Ok(Some(_)), None) if planner.free_slots() == 0 => {}
+            (Ok(Some((line, command))), None) => {
+                match execute_line(&mut interpreter, &mut planner, &mut control, &command) {
+                    Ok(homing) => {
+                        job.advance();
+                        planner.recalculate_trapezoids();
+                        fed = !homing;
+                    }
+                    // Homing, a jog, a heater wait or tuning run, or an arc that is still streaming
+                    // in; retried later.
+                    Err(LineError::State(_))
+                    | Err(LineError::HeaterBusy { .. })
+                    | Err(LineError::Interpreter(InterpreterError::QueueFull)) => {}
+                    Err(LineError::Interpreter(error)) => {
+                        job.fail(JobError::Interpreter { line, error }, now);
+                    }
+                    Err(LineError::Homing(error)) => {
+                        job.fail(JobError::Homing { line, error }, now);
+                    }
+                    Err(LineError::Heater(error)) => {
+                        job.fail(JobError::Heater { line, error }, now);
+                    }
+                    Err(LineError::Fan(error)) => {
+                        job.fail(JobError::Fan { line, error }, now);
+                    }
+                }
+            }
+        }
+
+        if !job.is_active() {
+            control.job_running = false;
+            match job.status() {
+                JobStatus::Failed(error) => {
+                    control.turn_off_heaters();
+                    log::error!("Job {} failed: {error}", job.name());
+                }
+                status => log::info!(
+                    "Job {} {} after {:.1} s",
+                    job.name(),
+                    status.name(),
+                    job.elapsed(now).as_secs_f32()
+                ),
+            }
+        }
+        if !fed || !job.is_active() {
+            return;
+        }
+    }
+}
+
+/// Formats the job for `/status`, with times in seconds.
+fn job_json(job: &StoredJob, now: Instant) -> String {
+    let eta = match job.eta(now) {
+        Some(eta) => format!("{:.1}", eta.as_secs_f32()),
+        None => "null".to_string(),
+    };
+    let error = match job.status() {
+        JobStatus::Failed(error) => format!(r#""{}""#, json_escape(&error.to_string())),
+        _ => "null".to_string(),
+    };
+    format!(
+        concat!(
+            r#"{{"name":"{}","status":"{}","line":{},"offset":{},"size":{},"#,
+            r#""progress":{:.1},"elapsed":{:.1},"eta":{},"error":{}}}"#,
+        ),
+        job.name(),
+        job.status().name(),
+        job.line(),
+        job.offset(),
+        job.size(),
+        job.progress(),
+        job.elapsed(now).as_secs_f32(),
+        eta,
+        error,
+    )
+}
+
+/// Formats each heater's reading and regulation for `/status`, keyed by heater name.
+fn heaters_json(heaters: &[HeaterChannel]) -> String {
+    heaters
+        .iter()
+        .map(|channel| {
+            let (celsius, fault) = match channel.sensor.reading() {
+                Some(Ok(celsius)) => (format!("{celsius:.1}"), "null".to_string()),
+                Some(Err(fault)) => ("null".to_string(), format!(r#""{fault}""#)),
+                None => ("null".to_string(), "null".to_string()),
+            };
+            let shutdown = match channel.heater.fault() {
+                Some(fault) => format!(r#""{fault}""#),
+                None => "null".to_string(),
+            };
+            format!(
+                concat!(
+                    r#""{}":{{"celsius":{},"fault":{},"target":{:.1},"power":{:.2},"#,
+                    r#""waiting":{},"shutdown":{}}}"#,
+                ),
+                channel.name,
+                celsius,
+                fault,
+                channel.heater.target(),
+                channel.heater.power(),
+                channel.heater.wait().is_some(),
+                shutdown,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Formats each fan's requested speed and output for `/status`, keyed by fan name, with speeds and
+/// duties from 0 to 1.
+fn fans_json(fans: &[FanChannel]) -> String {
+    fans.iter()
+        .map(|channel| {
+            let heater = match channel.heater {
+                Some(heater) => format!(r#""{heater}""#),
+                None => "null".to_string(),
+            };
+            format!(
+                concat!(
+                    r#""{}":{{"speed":{:.2},"duty":{:.2},"heater":{},"auto":{},"#,
+                    r#""kicking":{}}}"#,
+                ),
+                channel.name,
+                channel.fan.speed(),
+                channel.fan.duty(),
+                heater,
+                channel.fan.is_auto(),
+                channel.fan.is_kicking(),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Formats PID gains as a JSON object, or `null` for bang-bang regulation.
+fn gains_json(gains: Option<PidGains>) -> String {
+    match gains {
+        Some(gains) => format!(
+            r#"{{"kp":{:.5},"ki":{:.6},"kd":{:.4}}}"#,
+            gains.kp, gains.ki, gains.kd
+        ),
+        None => "null".to_string(),
+    }
+}
+
+/// Formats each heater's gains and its running or last tuning run for `/autotune`, with the
+/// gains every [`TuningRule`] derives from a completed run.
+fn autotune_json(heaters: &[HeaterChannel]) -> String {
+    let heaters = heaters
+        .iter()
+        .map(|channel| {
+            let autotune = match channel.heater.autotune() {
+                Some(autotune) => {
+                    let (error, result) = match autotune.status() {
+                        AutotuneStatus::Running => ("null".to_string(), "null".to_string()),
+                        AutotuneStatus::Failed(error) => {
+                            (format!(r#""{error}""#), "null".to_string())
+                        }
+                        AutotuneStatus::Completed(oscillation) => {
+                            let gains = TuningRule::ALL
+                                .iter()
+                                .map(|rule| {
+                                    let gains = gains_json(Some(rule.gains(&oscillation)));
+                                    format!(r#""{}":{gains}"#, rule.name())
+                                })
+                                .collect::<Vec<_>>()
+                                .join(",");
+                            let result = format!(
+                                concat!(
+                                    r#"{{"ultimate_gain":{:.5},"ultimate_period":{:.2},"#,
+                                    r#""amplitude":{:.2},"gains":{{{}}}}}"#,
+                                ),
+                                oscillation.ultimate_gain,
+                                oscillation.ultimate_period,
+                                oscillation.amplitude,
+                                gains,
+                            );
+                            ("null".to_string(), result)
+                        }
+                    };
+                    format!(
+                        concat!(
+                            r#"{{"status":"{}","target":{:.1},"cycles":{},"completed":{},"#,
+                            r#""error":{},"result":{}}}"#,
+                        ),
+                        autotune.status().name(),
+                        autotune.target(),
+                        autotune.cycles(),
+                        autotune.completed_cycles(),
+                        error,
+                        result,
+                    )
+                }
+                None => "null".to_string(),
+            };
+            format!(
+                r#""{}":{{"gains":{},"autotune":{}}}"#,
+                channel.name,
+                gains_json(channel.heater.gains()),
+                autotune
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{{{heaters}}}")
+}
+
+/// Handles a `POST /autotune` command: `apply <heater> <rule>` switches a heater to the gains
+/// `rule` derives from its last completed tuning run, and `save <heater>` keeps its current gains
+/// across restarts. Returns the HTTP status, reason phrase, and plain-text body to send.
+fn autotune_command(
+    control: &Mutex<MachineControl>,
+    settings: &Mutex<Settings>,
+    command: &str,
+) -> (u16, &'static str, String) {
+    let words = command.split_whitespace().collect::<Vec<_>>();
+    let (name, rule) = match words.as_slice() {
+        ["apply", name, rule] => match TuningRule::from_name(rule) {
+            Some(rule) => (*name, Some(rule)),
+            None => {
+                let body = format!("rules are ziegler-nichols and tyreus-luyben, not {rule}\n");
+                return (400, "Bad Request", body);
+            }
+        },
+        ["save", name] => (*name, None),
+        _ => {
+            let body = "expected apply <heater> <rule> or save <heater>\n".to_string();
+            return (400, "Bad Request", body);
+        }
+    };
+
+    let mut control = control.lock().expect("machine control lock poisoned");
+    let Some(channel) = control
+        .heaters
+        .iter_mut()
+        .find(|channel| channel.name == name)
+    else {
+        return (404, "Not Found", format!("no heater named {name}\n"));
+    };
+    if let Some(rule) = rule {
+        let status = channel.heater.autotune().map(Autotune::status);
+        let Some(AutotuneStatus::Completed(oscillation)) = status else {
+            let body = format!("{name} has no completed tuning run\n");
+            return (409, "Conflict", body);
+        };
+        let gains = rule.gains(&oscillation);
+        channel.heater.set_gains(gains);
+        log::info!("{name} applied {} gains", rule.name());
+        return (200, "OK", format!("{}\n", gains_json(Some(gains))));
+    }
+    let Some(gains) = channel.heater.gains() else {
+        return (
+            409,
+            "Conflict",
+            format!("{name} uses bang-bang regulation\n"),
+        );
+    };
+    drop(control);
+    match settings
+        .lock()
+        .expect("settings lock poisoned")
+        .set_pid_gains(name, gains)
+    {
+        Ok(()) => {
+            log::info!("Saved {name} PID gains");
+            (200, "OK", format!("saved: {name}\n"))
+        }
+        Err(error) => (500, "Internal Server Error", format!("{error}\n")),
+    }
+}
+
+/// Escapes `text` for use inside a JSON string.
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(character);
+            }
+            control if control.is_control() => {
+                escaped.push_str(&format!("\\u{:04x}", control as u32));
+            }
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// Adds an ADC sample to a heater's sensor and recomputes the heater's power. `interval` is the
+/// time since the previous sample, or `None` for the first one.
+///
+/// A failed ADC read counts as a missing reading, which turns the heater off until the next
+/// successful one. Returns the fault if the reading tripped runaway protection.
+fn update_heater(
+    channel: &mut HeaterChannel,
+    sample: &Result<f32, EspError>,
+    interval: Option<Duration>,
+) -> Option<ThermalFault> {
+    let reading = match sample {
+        Ok(millivolts) => {
+            let previous = channel.sensor.reading();
+            let reading = channel.sensor.update(*millivolts);
+            match reading {
+                Err(fault) if previous != Some(reading) => {
+                    log::warn!("{} sensor: {fault}", channel.name);
+                }
+                _ => {}
+            }
+            Some(reading)
+        }
+        Err(error) => {
+            log::error!("{} sensor: {error}", channel.name);
+            None
+        }
+    };
+    let faulted = channel.heater.fault().is_some();
+    let waiting = channel.heater.wait().is_some();
+    let tuning = channel.heater.autotune().is_some_and(Autotune::is_running);
+    channel.heater.update(reading, interval.unwrap_or_default());
+    if waiting && channel.heater.wait().is_none() {
+        log::info!("{} reached {:.0} °C", channel.name, channel.heater.target());
+    }
+    match channel.heater.autotune().map(Autotune::status) {
+        Some(AutotuneStatus::Completed(oscillation)) if tuning => log::info!(
+            "{} autotune: ultimate gain {:.4}, period {:.1} s, amplitude {:.1} °C",
+            channel.name,
+            oscillation.ultimate_gain,
+            oscillation.ultimate_period,
+            oscillation.amplitude
+        ),
+        Some(AutotuneStatus::Failed(error)) if tuning => {
+            log::error!("{} autotune failed: {error}", channel.name);
+        }
+        _ => {}
+    }
+    match (faulted, channel.heater.fault()) {
+        (false, Some(fault)) => Some(fault),
+        _ => None,
+    }
+}
+
+/// Runs a fan at full speed while its bound heater is hot.
+fn update_fan(channel: &mut FanChannel, heaters: &[HeaterChannel]) {
+    let Some(name) = channel.heater else {
+        return;
+    };
+    let reading = heaters
+        .iter()
+        .find(|heater| heater.name == name)
+        .and_then(|heater| heater.sensor.reading());
+    let auto = channel.fan.is_auto();
+    channel.fan.update_heater(reading);
+    match (auto, channel.fan.is_auto()) {
+        (false, true) => log::info!("{} on while {name} is hot", channel.name),
+        (true, false) => log::info!(
+            "{} back to {:.0}%",
+            channel.name,
+            channel.fan.speed() * 100.0
+        ),
+        _ => {}
+    }
+}
+
+/// Reads a request body until the end of the stream or its `Content-Length`. Returns `None`,
+/// without reading the rest, once the body is known to exceed `limit` bytes.
+fn read_body<R>(request: &mut R, limit: usize) -> Result<Option<Vec<u8>>>
+where
+    R: Headers + Read,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    let declared = request
+        .content_len()
+        .map(|length| usize::try_from(length).unwrap_or(usize::MAX));
+    if declared.is_some_and(|length| length > limit) {
+        return Ok(None);
+    }
+    let mut body = Vec::with_capacity(declared.unwrap_or(0));
+    let mut chunk = [0_u8; 512];
+    while declared.is_none_or(|length| body.len() < length) {
+        let bytes_read = request.read(&mut chunk)?;
+        if bytes_read == 0 {
+            break;
+        }
+        if body.len() + bytes_read > limit {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk[..bytes_read]);
+    }
+    Ok(Some(body))
+}
+
+/// Decodes a body returned by [`read_body`] as text. Returns the HTTP status, reason phrase, and
+/// plain-text body to send instead if it exceeded `limit` bytes or is not UTF-8.
+fn text_body(body: Option<Vec<u8>>, limit: usize) -> Result<String, (u16, &'static str, String)> {
+    let Some(body) = body else {
+        return Err((
+            413,
+            "Payload Too Large",
+            format!("request body exceeds {limit} bytes\n"),
+        ));
+    };
+    String::from_utf8(body).map_err(|_| {
+        (
+            400,
+            "Bad Request",
+            "request body is not UTF-8\n".to_string(),
+        )
+    })
+}
+
+/// Extracts `{name}` from a `/files/{name}` request URI.
+fn file_name(uri: &str) -> &str {
+    let path = uri.split_once('?').map_or(uri, |(path, _)| path);
+    path.strip_prefix("/files/").unwrap_or_default()
+}
+
+/// Splits a `<name>_on` or `<name>_off` command into the output name and requested state.
+fn status_output_command(command: &str) -> Option<(&str, bool)> {
+    command
+        .strip_suffix("_on")
+        .map(|name| (name, true))
+        .or_else(|| command.strip_suffix("_off").map(|name| (name, false)))
+}
+
 fn main() -> Result<()> {
     esp_idf_sys::link_patches();
     esp_idf_svc::log::EspLogger::initialize_default();
 
     let peripherals = Peripherals::take()?;
     let system_event_loop = EspSystemEventLoop::take()?;
-    let _wifi = start_access_point(WIFI_SSID, WIFI_PSK, peripherals.modem, system_event_loop)?;
+    let nvs = EspDefaultNvsPartition::take()?;
+    let _wifi = start_access_point(
+        WIFI_SSID,
+        WIFI_PSK,
+        peripherals.modem,
+        system_event_loop,
+        nvs.clone(),
+    )?;
+    let settings = Arc::new(Mutex::new(Settings::open(nvs)?));
 
-    let planner = Arc::new(Mutex::new(Planner::new(BLOCK_BUFFER_SIZE)));
+    let stepper_config = StepperConfig {
+        backends: SelectedBoard::STEP_BACKENDS,
+        ..StepperConfig::default()
+    };
+    let mut machine_config = MachineConfig::default();
+    let lowered = machine_config.limit_step_rates(&stepper_config);
+    for axis in (0..AXES).filter(|axis| lowered >> axis & 1 == 1) {
+        log::warn!(
+            "{} rate limited to {:.0} mm/min, the fastest its {} step output allows",
+            AXIS_NAMES[axis],
+            machine_config.axes[axis].max_rate,
+            stepper_config.backends[axis].name(),
+        );
+    }
+    let planner = Arc::new(Mutex::new(Planner::new(BLOCK_BUFFER_SIZE, machine_config)));
+    let interpreter = Arc::new(Mutex::new(Interpreter::new()));
+    let board_io = Arc::new(Mutex::new(BoardIo::new::<SelectedBoard>()?));
+    let files = Arc::new(FileStore::mount()?);
+    let job = Arc::new(Mutex::new(None::<StoredJob>));
 
-    // These logical names are the pin labels currently exposed by the web interface.
-    let d0_main = Arc::new(Mutex::new(PinDriver::output(peripherals.pins.gpio2)?));
-    let d1_main = Arc::new(Mutex::new(PinDriver::output(peripherals.pins.gpio23)?));
-    let d3_main = Arc::new(Mutex::new(PinDriver::output(peripherals.pins.gpio4)?));
-    let d4_main = Arc::new(Mutex::new(PinDriver::output(peripherals.pins.gpio5)?));
-    let d5_main = Arc::new(Mutex::new(PinDriver::output(peripherals.pins.gpio16)?));
-    let d6_main = Arc::new(Mutex::new(PinDriver::output(peripherals.pins.gpio17)?));
-    let d7_main = Arc::new(Mutex::new(PinDriver::output(peripherals.pins.gpio18)?));
-    let d12_main = Arc::new(Mutex::new(PinDriver::output(peripherals.pins.gpio19)?));
+    let mut heaters = Vec::new();
+    let mut sensor_pins = Vec::new();
+    for heater in SelectedBoard::HEATERS {
+        let Some(thermistor) = heater.thermistor else {
+            log::warn!("{} heater has no sensor and stays off", heater.name);
+            continue;
+        };
+        // BoardIo has already warned about outputs it cannot drive.
+        if !board_io
+            .lock()
+            .expect("board I/O lock poisoned")
+            .pins_mut()
+            .supports(heater.output)
+        {
+            continue;
+        }
+        let config = match heater.name {
+            "bed" => HeaterConfig::BED,
+            _ => HeaterConfig::HOTEND,
+        };
+        let mut regulation = Heater::new(config);
+        match settings
+            .lock()
+            .expect("settings lock poisoned")
+            .pid_gains(heater.name)
+        {
+            Ok(Some(gains)) => {
+                regulation.set_gains(gains);
+                log::info!("{} uses saved PID gains", heater.name);
+            }
+            Ok(None) => {}
+            Err(error) => log::warn!("Reading saved {} gains failed: {error}", heater.name),
+        }
+        heaters.push(HeaterChannel {
+            name: heater.name,
+            output: heater.output,
+            sensor: Sensor::new(SensorConfig::default()),
+            heater: regulation,
+        });
+        sensor_pins.push(thermistor);
+    }
+    let mut fans = Vec::new();
+    for fan in SelectedBoard::FANS {
+        if !board_io
+            .lock()
+            .expect("board I/O lock poisoned")
+            .pins_mut()
+            .supports(fan.output)
+        {
+            continue;
+        }
+        let heater = fan.heater.filter(|name| {
+            let fitted = heaters.iter().any(|channel| channel.name == *name);
+            if !fitted {
+                log::warn!("{} is bound to {name}, which is not fitted", fan.name);
+            }
+            fitted
+        });
+        fans.push(FanChannel {
+            name: fan.name,
+            output: fan.output,
+            heater,
+            fan: Fan::new(FanConfig::default()),
+        });
+    }
+    let outputs_fitted = !heaters.is_empty() || !fans.is_empty();
+    let control = Arc::new(Mutex::new(MachineControl {
+        homing_config: HomingConfig::default(),
+        homing: None,
+        state: MachineState::Idle,
+        stop_requested: false,
+        job_running: false,
+        heaters,
+        fans,
+        thermal_fault: None,
+    }));
+
+    if outputs_fitted {
+        let mut adc = match sensor_pins.is_empty() {
+            true => None,
+            false => Some(AdcInputs::new(&sensor_pins)?),
+        };
+        let control = Arc::clone(&control);
+        let board_io = Arc::clone(&board_io);
+        thread::spawn(move || {
+            let mut samples = Vec::with_capacity(sensor_pins.len());
+            let mut levels = Vec::new();
+            let mut last_sample = None::<Instant>;
+            let mut last_switch = Instant::now();
+            loop {
+                let now = Instant::now();
+                let sample_due = last_sample
+                    .is_none_or(|last| now.duration_since(last) >= TEMPERATURE_SAMPLE_INTERVAL);
+                if let (true, Some(adc)) = (sample_due, &mut adc) {
+                    // Sample without the lock so that motion never waits for the ADC.
+                    samples.clear();
+                    samples.extend((0..sensor_pins.len()).map(|index| adc.read_millivolts(index)));
+                }
 
-    let mut server = EspHttpServer::new(&Configuration::default())?;
+                let mut control = control.lock().expect("machine control lock poisoned");
+                if sample_due {
+                    let interval = last_sample.map(|last| now - last);
+                    let mut fault = None;
+                    for (channel, sample) in control.heaters.iter_mut().zip(&samples) {
+                        if let Some(tripped) = update_heater(channel, sample, interval) {
+                            fault = fault.or(Some((channel.name, tripped)));
+                        }
+                    }
+                    // One runaway heater shuts them all down before their outputs are set.
+                    if let Some((name, tripped)) = fault {
+                        log::error!("{name} heater shut down: {tripped}");
+                        control.turn_off_heaters();
+                        control.thermal_fault = control.thermal_fault.or(fault);
+                    }
+                    let MachineControl { heaters, fans, .. } = &mut *control;
+                    for channel in fans {
+                        update_fan(channel, heaters);
+                    }
+                }
+                levels.clear();
+                for channel in &mut control.heaters {
+                    levels.push((
+                        channel.output,
+                        channel.heater.output_level(now - last_switch),
+                    ));
+                }
+                for channel in &mut control.fans {
+                    levels.push((channel.output, channel.fan.output_level(now - last_switch)));
+                }
+                drop(control);
+                if sample_due {
+                    last_sample = Some(now);
+                }
+                last_switch = now;
+
+                let mut board_io = board_io.lock().expect("board I/O lock poisoned");
+                // Flushing only sends expander outputs whose level changed.
+                let pins = board_io.pins_mut();
+                let written = levels
+                    .iter()
+                    .try_for_each(|&(pin, high)| pins.set_level(pin, high))
+                    .and_then(|()| pins.flush());
+                if let Err(error) = written {
+                    log::error!("Heater and fan outputs: {error}");
+                }
+                drop(board_io);
+                sleep(OUTPUT_PWM_INTERVAL);
+            }
+        });
+    }
+
+    // Drivers behind an I/O expander cannot be stepped from the interrupt yet, so their axes
+    // refuse motion rather than report moves that never reach a motor.
+    let undriven_axes = devices::undriven_axes(SelectedBoard::STEPPERS);
+    for axis in (0..AXES).filter(|axis| undriven_axes >> axis & 1 == 1) {
+        log::error!(
+            "{} has no stepper on native GPIO for the {} axis; moves on it are refused",
+            SelectedBoard::DISPLAY_NAME,
+            AXIS_NAMES[axis],
+        );
+    }
+    planner
+        .lock()
+        .expect("motion planner lock poisoned")
+        .set_undriven_axes(undriven_axes);
+    let step_channels = SelectedBoard::STEPPERS
+        .iter()
+        .copied()
+        .filter(|channel| undriven_axes >> channel.axis & 1 == 0)
+        .collect::<Vec<_>>();
+    let endstops = GpioEndstops::new(SelectedBoard::ENDSTOPS);
+    let safety_door = GpioSafetyDoor::new(SelectedBoard::SAFETY_DOOR);
+    let (segment_producer, segment_consumer) = segments::segment_queue();
+    let mut step_timer = StepTimer::new(
+        Stepper::new(segment_consumer),
+        PinStepOutput::new(&step_channels, stepper_config)?,
+        endstops.clone(),
+    )?;
+    let hard_limits = planner
+        .lock()
+        .expect("motion planner lock poisoned")
+        .config()
+        .hard_limits;
+    if hard_limits {
+        step_timer.attach_endstop_interrupts(endstops.pins())?;
+    }
+    let step_timer = Arc::new(step_timer);
+    let mut segment_preparer = SegmentPreparer::new(segment_producer);
+    let preparer_interpreter = Arc::clone(&interpreter);
+    let preparer_planner = Arc::clone(&planner);
+    let preparer_control = Arc::clone(&control);
+    let preparer_step_timer = Arc::clone(&step_timer);
+    thread::spawn(move || {
+        let step_timer = &*preparer_step_timer;
+        let mut hard_limits_armed = false;
+        let mut door_was_open = false;
+        loop {
+            // Lock in the same order as `queue_gcode`.
+            let mut interpreter = preparer_interpreter
+                .lock()
+                .expect("G-code interpreter lock poisoned");
+            let mut planner = preparer_planner
+                .lock()
+                .expect("motion planner lock poisoned");
+            // Arcs longer than the planner buffer stream in as blocks are executed.
+            if interpreter.has_pending_arc() {
+                interpreter.queue_pending_arc(&mut planner);
+                planner.recalculate_trapezoids();
+            }
+            let mut control = preparer_control
+                .lock()
+                .expect("machine control lock poisoned");
+
+            // Homing drives axes onto their switches, so the hard-limit kill waits until it ends.
+            let arm = hard_limits && control.state != MachineState::Homing;
+            if arm != hard_limits_armed {
+                step_timer.with_stepper(|stepper| stepper.arm_hard_limits(arm));
+                hard_limits_armed = arm;
+            }
+            let limit_axes = step_timer.with_stepper(|stepper| stepper.take_limit_alarm());
+            if limit_axes != 0 {
+                stop_motion(step_timer, &mut segment_preparer, &mut planner);
+                interpreter.abandon_motion(planner.position());
+                log::error!("Alarm: {}", Alarm::HardLimit(limit_axes));
+                control.state.handle(Event::HardLimit(limit_axes));
+            }
+            // A heater fault brakes the machine to a stop, which keeps its position, and abandons
+            // homing.
+            if let Some((heater, fault)) = control.thermal_fault.take() {
+                log::error!("Alarm: {}", Alarm::Thermal { heater, fault });
+                if control.homing.take().is_some() {
+                    step_timer.with_stepper(|stepper| stepper.watch_endstops(0));
+                }
+                control.stop_requested = true;
+                control
+                    .state
+                    .handle(Event::ThermalRunaway { heater, fault });
+            }
+            // The door is polled at the preparation interval. Opening it abandons homing, cancels
+            // a jog, and holds any other motion until it closes and the cycle is resumed.
+            let door_open = safety_door.is_open();
+            if door_open != door_was_open {
+                door_was_open = door_open;
+                match door_open {
+                    true => {
+                        if control.homing.take().is_some() {
+                            step_timer.with_stepper(|stepper| stepper.watch_endstops(0));
+                            control.stop_requested = true;
+                            log::error!("Alarm: {}", Alarm::DoorDuringHoming);
+                        }
+                        control.stop_requested |= control.state == MachineState::Jog;
+                        control.state.handle(Event::DoorOpened);
+                        log::warn!("Safety door opened");
+                    }
+                    false => {
+                        control.state.handle(Event::DoorClosed);
+                        log::info!("Safety door closed");
+                    }
+                }
+            }
+
+            if let Some(cycle) = &mut control.homing {
+                match run_homing(
+                    cycle,
+                    step_timer,
+                    &endstops,
+                    &mut segment_preparer,
+                    &mut planner,
+                ) {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        log::info!("Homing complete");
+                        control.homing = None;
+                        control.state.handle(Event::HomingComplete);
+                        interpreter.abandon_motion(planner.position());
+                    }
+                    Err(error) => {
+                        log::error!("Alarm: {}", Alarm::from(error));
+                        step_timer.with_stepper(|stepper| stepper.watch_endstops(0));
+                        control.homing = None;
+                        control.state.handle(Event::HomingFailed(error));
+                    }
+                }
+            }
+
+            // A feed hold or open door brakes the machine and keeps it stopped until resumed; a
+            // stop or jog cancel brakes before the queue is discarded.
+            let hold = control.stop_requested
+                || matches!(
+                    control.state,
+                    MachineState::Hold | MachineState::Door { .. }
+                );
+            if hold && !segment_preparer.is_holding() {
+                segment_preparer.feed_hold();
+            } else if !hold && segment_preparer.is_holding() {
+                segment_preparer.resume(&mut planner);
+            }
+            if control.stop_requested && segment_preparer.is_held() && !step_timer.is_busy() {
+                stop_motion(step_timer, &mut segment_preparer, &mut planner);
+                interpreter.abandon_motion(planner.position());
+                control.stop_requested = false;
+                control.state.handle(Event::QueueFlushed);
+                log::info!("Stopped; queued motion discarded");
+            }
+
+            let drained = planner.is_empty()
+                && !interpreter.has_pending_arc()
+                && !segment_preparer.is_busy()
+                && !step_timer.is_busy();
+            if drained {
+                control.state.handle(Event::MotionComplete);
+            }
+            drop(control);
+            drop(interpreter);
+            segment_preparer.fill(&mut planner);
+            drop(planner);
+            sleep(SEGMENT_PREPARATION_INTERVAL);
+        }
+    });
+
+    {
+        let job = Arc::clone(&job);
+        let interpreter = Arc::clone(&interpreter);
+        let planner = Arc::clone(&planner);
+        let control = Arc::clone(&control);
+        thread::spawn(move || {
+            loop {
+                sleep(JOB_FEED_INTERVAL);
+                let mut job = job.lock().expect("job lock poisoned");
+                if let Some(job) = job.as_mut().filter(|job| job.is_active()) {
+                    feed_job(job, &interpreter, &planner, &control);
+                }
+            }
+        });
+    }
+
+    let mut server = EspHttpServer::new(&Configuration {
+        uri_match_wildcard: true,
+        ..Default::default()
+    })?;
 
     server.fn_handler("/", Method::Get, |request| -> Result<()> {
         let mut response =
@@ -168,9 +1577,9 @@ fn main() -> Result<()> {
     server.fn_handler("/device", Method::Get, |request| -> Result<()> {
         let body = format!(
             r#"{{"name":"{}","display_name":"{}","image_mime":"{}","image_url":"/device/image"}}"#,
-            devices::Device::NAME,
-            devices::Device::DISPLAY_NAME,
-            devices::Device::IMAGE_MIME,
+            SelectedBoard::NAME,
+            SelectedBoard::DISPLAY_NAME,
+            SelectedBoard::IMAGE_MIME,
         );
         let mut response =
             request.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?;
@@ -183,11 +1592,11 @@ fn main() -> Result<()> {
             200,
             Some("OK"),
             &[
-                ("Content-Type", devices::Device::IMAGE_MIME),
+                ("Content-Type", SelectedBoard::IMAGE_MIME),
                 ("Cache-Control", "public, max-age=86400"),
             ],
         )?;
-        response.write_all(devices::Device::IMAGE_BYTES)?;
+        response.write_all(SelectedBoard::IMAGE_BYTES)?;
         Ok(())
     })?;
 
@@ -201,54 +1610,317 @@ fn main() -> Result<()> {
         Ok(())
     })?;
 
-    server.fn_handler("/files", Method::Post, |mut request| -> Result<()> {
-        let mut chunk = [0_u8; 2_048];
-        let mut received = 0_usize;
-        loop {
-            let bytes_read = request.read(&mut chunk)?;
-            if bytes_read == 0 {
-                break;
-            }
-            received = received.saturating_add(bytes_read);
-        }
-
-        let body = format!("received: {received} bytes\n");
+    server.fn_handler("/steppers", Method::Get, move |request| -> Result<()> {
+        let axes = AXIS_NAMES
+            .iter()
+            .enumerate()
+            .map(|(axis, name)| {
+                format!(
+                    r#"{{"axis":"{name}","driven":{},"backend":"{}","max_step_rate":{}}}"#,
+                    undriven_axes >> axis & 1 == 0,
+                    stepper_config.backends[axis].name(),
+                    stepper_config.max_step_rate(axis),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let body = format!(
+            r#"{{"pulse_us":{},"direction_setup_us":{},"direction_hold_us":{},"axes":[{axes}]}}"#,
+            stepper_config.pulse_microseconds,
+            stepper_config.direction_setup_microseconds,
+            stepper_config.direction_hold_microseconds,
+        );
         let mut response =
-            request.into_response(200, Some("OK"), &[("Content-Type", "text/plain")])?;
+            request.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?;
         response.write_all(body.as_bytes())?;
         Ok(())
     })?;
 
-    server.fn_handler("/queue", Method::Get, |request| -> Result<()> {
-        let mut response =
-            request.into_response(200, Some("OK"), &[("Content-Type", "text/plain")])?;
-        response.write_all(b"Queue: []\n")?;
-        Ok(())
-    })?;
+    {
+        let planner = Arc::clone(&planner);
+        let control = Arc::clone(&control);
+        let step_timer = Arc::clone(&step_timer);
+        let job = Arc::clone(&job);
+        server.fn_handler("/status", Method::Get, move |request| -> Result<()> {
+            let job = match &*job.lock().expect("job lock poisoned") {
+                Some(job) => job_json(job, Instant::now()),
+                None => "null".to_string(),
+            };
+            let (axes, overrides) = {
+                let planner = planner.lock().expect("motion planner lock poisoned");
+                (planner.config().axes, planner.overrides())
+            };
+            let (state, temperatures, fans) = {
+                let control = control.lock().expect("machine control lock poisoned");
+                (
+                    control.state,
+                    heaters_json(&control.heaters),
+                    fans_json(&control.fans),
+                )
+            };
+            let steps = step_timer.with_stepper(|stepper| stepper.position());
+            let position = AXIS_NAMES
+                .iter()
+                .enumerate()
+                .map(|(axis, name)| {
+                    let millimetres = steps[axis] as f32 / axes[axis].steps_per_mm;
+                    format!(r#""{name}":{millimetres:.3}"#)
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            let alarm = match state.alarm() {
+                Some(alarm) => format!(r#""{alarm}""#),
+                None => "null".to_string(),
+            };
+            let body = format!(
+                concat!(
+                    r#"{{"state":"{}","alarm":{},"position":{{{}}},"#,
+                    r#""overrides":{{"feed":{},"rapid":{},"spindle":{}}},"#,
+                    r#""temperatures":{{{}}},"fans":{{{}}},"job":{}}}"#,
+                ),
+                state.name(),
+                alarm,
+                position,
+                overrides.feed,
+                overrides.rapid,
+                overrides.spindle,
+                temperatures,
+                fans,
+                job,
+            );
+            let mut response = request.into_response(
+                200,
+                Some("OK"),
+                &[
+                    ("Content-Type", "application/json"),
+                    ("Cache-Control", "no-store"),
+                ],
+            )?;
+            response.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+    }
 
     {
-        let d0 = Arc::clone(&d0_main);
-        let d1 = Arc::clone(&d1_main);
-        let d3 = Arc::clone(&d3_main);
-        let d4 = Arc::clone(&d4_main);
-        let d5 = Arc::clone(&d5_main);
-        let d6 = Arc::clone(&d6_main);
-        let d7 = Arc::clone(&d7_main);
-        let d12 = Arc::clone(&d12_main);
+        let files = Arc::clone(&files);
+        server.fn_handler("/files", Method::Get, move |request| -> Result<()> {
+            let listing = files
+                .list()
+                .and_then(|entries| Ok((files.usage()?, entries)));
+            let (usage, entries) = match listing {
+                Ok(listing) => listing,
+                Err(error) => {
+                    let (status, reason) = storage_error_status(&error);
+                    let mut response = request.into_response(
+                        status,
+                        Some(reason),
+                        &[("Content-Type", "text/plain")],
+                    )?;
+                    response.write_all(format!("{error}\n").as_bytes())?;
+                    return Ok(());
+                }
+            };
+            let entries = entries
+                .iter()
+                .map(|entry| format!(r#"{{"name":"{}","size":{}}}"#, entry.name, entry.size))
+                .collect::<Vec<_>>()
+                .join(",");
+            let body = format!(
+                r#"{{"total":{},"used":{},"free":{},"files":[{entries}]}}"#,
+                usage.total,
+                usage.used,
+                usage.free(),
+            );
+            let mut response = request.into_response(
+                200,
+                Some("OK"),
+                &[
+                    ("Content-Type", "application/json"),
+                    ("Cache-Control", "no-store"),
+                ],
+            )?;
+            response.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+    }
 
-        server.fn_handler("/pins", Method::Get, move |request| -> Result<()> {
-            // Output pins expose their latched state even when physical input sampling is unavailable.
+    {
+        let files = Arc::clone(&files);
+        server.fn_handler("/files/*", Method::Get, move |request| -> Result<()> {
+            let name = file_name(request.uri()).to_string();
+            let mut file = match files.open(&name) {
+                Ok(file) => file,
+                Err(error) => {
+                    let (status, reason) = storage_error_status(&error);
+                    let mut response = request.into_response(
+                        status,
+                        Some(reason),
+                        &[("Content-Type", "text/plain")],
+                    )?;
+                    response.write_all(format!("{error}\n").as_bytes())?;
+                    return Ok(());
+                }
+            };
+            let length = file.metadata()?.len().to_string();
+            let disposition = format!(r#"attachment; filename="{name}""#);
+            let mut response = request.into_response(
+                200,
+                Some("OK"),
+                &[
+                    ("Content-Type", "application/octet-stream"),
+                    ("Content-Length", &length),
+                    ("Content-Disposition", &disposition),
+                    ("Cache-Control", "no-store"),
+                ],
+            )?;
+            let mut chunk = [0_u8; 2_048];
+            loop {
+                let bytes_read = std::io::Read::read(&mut file, &mut chunk)?;
+                if bytes_read == 0 {
+                    break;
+                }
+                response.write_all(&chunk[..bytes_read])?;
+            }
+            Ok(())
+        })?;
+    }
+
+    {
+        let files = Arc::clone(&files);
+        server.fn_handler("/files/*", Method::Delete, move |request| -> Result<()> {
+            let name = file_name(request.uri()).to_string();
+            let (status, reason, body) = match files.remove(&name) {
+                Ok(()) => {
+                    log::info!("Deleted {name}");
+                    (200, "OK", format!("deleted: {name}\n"))
+                }
+                Err(error) => {
+                    let (status, reason) = storage_error_status(&error);
+                    (status, reason, format!("{error}\n"))
+                }
+            };
+            let mut response =
+                request.into_response(status, Some(reason), &[("Content-Type", "text/plain")])?;
+            response.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+    }
+
+    {
+        let files = Arc::clone(&files);
+        server.fn_handler("/files/*", Method::Post, move |mut request| -> Result<()> {
+            let name = file_name(request.uri()).to_string();
+            let length = request.content_len();
+            // A rejected upload is answered without reading the rest of its body.
+            let result = match files.begin_upload(&name, length) {
+                Ok(mut upload) => {
+                    let mut chunk = [0_u8; 2_048];
+                    loop {
+                        let bytes_read = request.read(&mut chunk)?;
+                        if bytes_read == 0 {
+                            break upload.finish();
+                        }
+                        if let Err(error) = upload.write(&chunk[..bytes_read]) {
+                            break Err(error);
+                        }
+                    }
+                }
+                Err(error) => Err(error),
+            };
+
+            let (status, reason, body) = match result {
+                Ok(size) => {
+                    log::info!("Stored {name}: {size} bytes");
+                    (201, "Created", format!("stored: {name}, {size} bytes\n"))
+                }
+                Err(error) => {
+                    log::warn!("Upload of {name} failed: {error}");
+                    let (status, reason) = storage_error_status(&error);
+                    (status, reason, format!("{error}\n"))
+                }
+            };
+            let mut response =
+                request.into_response(status, Some(reason), &[("Content-Type", "text/plain")])?;
+            response.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+    }
+
+    {
+        let planner = Arc::clone(&planner);
+        server.fn_handler("/queue", Method::Get, move |request| -> Result<()> {
+            let planner = planner.lock().expect("motion planner lock poisoned");
+            let steps_per_mm = planner.config().steps_per_mm();
+            let blocks = planner
+                .blocks()
+                .map(|block| {
+                    let target = [
+                        block.target.x,
+                        block.target.y,
+                        block.target.z,
+                        block.target.e,
+                    ];
+                    let target = AXIS_NAMES
+                        .iter()
+                        .enumerate()
+                        .map(|(axis, name)| {
+                            let millimetres = target[axis] as f32 / steps_per_mm[axis];
+                            format!(r#""{name}":{millimetres:.3}"#)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    format!(
+                        concat!(
+                            r#"{{"target":{{{}}},"feed_rate":{:.1},"nominal_rate":{:.1},"#,
+                            r#""entry_rate":{:.1},"exit_rate":{:.1},"accel_until":{},"#,
+                            r#""decel_after":{}}}"#,
+                        ),
+                        target,
+                        block.feed_rate,
+                        block.nominal_rate,
+                        block.entry_rate,
+                        block.exit_rate,
+                        block.accel_until,
+                        block.decel_after,
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            // Only the current block can be executing; later blocks wait for the segment preparer.
+            let executing = if planner.is_current_block_started() {
+                "0"
+            } else {
+                "null"
+            };
             let body = format!(
-                r#"{{"D0":{},"D1":{},"D3":{},"D4":{},"D5":{},"D6":{},"D7":{},"D12":{}}}"#,
-                d0.lock().expect("D0 lock poisoned").is_set_high() as u8,
-                d1.lock().expect("D1 lock poisoned").is_set_high() as u8,
-                d3.lock().expect("D3 lock poisoned").is_set_high() as u8,
-                d4.lock().expect("D4 lock poisoned").is_set_high() as u8,
-                d5.lock().expect("D5 lock poisoned").is_set_high() as u8,
-                d6.lock().expect("D6 lock poisoned").is_set_high() as u8,
-                d7.lock().expect("D7 lock poisoned").is_set_high() as u8,
-                d12.lock().expect("D12 lock poisoned").is_set_high() as u8,
+                r#"{{"free":{},"executing":{executing},"blocks":[{blocks}]}}"#,
+                planner.free_slots(),
             );
+            drop(planner);
+            let mut response = request.into_response(
+                200,
+                Some("OK"),
+                &[
+                    ("Content-Type", "application/json"),
+                    ("Cache-Control", "no-store"),
+                ],
+            )?;
+            response.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+    }
+
+    {
+        let board_io = Arc::clone(&board_io);
+        server.fn_handler("/pins", Method::Get, move |request| -> Result<()> {
+            let outputs = board_io
+                .lock()
+                .expect("board I/O lock poisoned")
+                .status_outputs()
+                .map(|(name, on)| format!(r#""{name}":{}"#, on as u8))
+                .collect::<Vec<_>>()
+                .join(",");
+            let body = format!("{{{outputs}}}");
             let mut response = request.into_response(
                 200,
                 Some("OK"),
@@ -262,21 +1934,105 @@ fn main() -> Result<()> {
         })?;
     }
 
-    let d0 = Arc::clone(&d0_main);
-    let d1 = Arc::clone(&d1_main);
-    let d3 = Arc::clone(&d3_main);
-    let d4 = Arc::clone(&d4_main);
-    let d5 = Arc::clone(&d5_main);
-    let d6 = Arc::clone(&d6_main);
-    let d7 = Arc::clone(&d7_main);
-    let status_led = Arc::clone(&d12_main);
-    let relay = Arc::clone(&d1_main);
+    {
+        let job = Arc::clone(&job);
+        let files = Arc::clone(&files);
+        let planner = Arc::clone(&planner);
+        let control = Arc::clone(&control);
+        server.fn_handler("/job", Method::Post, move |mut request| -> Result<()> {
+            let body = read_body(&mut request, COMMAND_BODY_LIMIT)?;
+            let command = match text_body(body, COMMAND_BODY_LIMIT) {
+                Ok(command) => command,
+                Err((status, reason, body)) => {
+                    let mut response = request.into_response(
+                        status,
+                        Some(reason),
+                        &[("Content-Type", "text/plain")],
+                    )?;
+                    response.write_all(body.as_bytes())?;
+                    return Ok(());
+                }
+            };
+            let command = command.trim();
+            let active = job
+                .lock()
+                .expect("job lock poisoned")
+                .as_ref()
+                .is_some_and(|job| job.is_active());
 
-    server.fn_handler("/queue", Method::Post, move |mut request| -> Result<()> {
-        let mut buffer = [0_u8; 1_024];
-        let bytes_read = request.read(&mut buffer)?;
-        let command = std::str::from_utf8(&buffer[..bytes_read])?.trim();
+            let (status, reason, body) = match command.split_once(' ') {
+                Some(("start", name)) => start_job(&job, &files, &control, name.trim()),
+                None if !active && matches!(command, "pause" | "resume" | "abort") => {
+                    (409, "Conflict", "no job is running\n".to_string())
+                }
+                None if command == "pause" => {
+                    realtime(&planner, &control, RealtimeCommand::FeedHold)
+                }
+                None if command == "resume" => {
+                    realtime(&planner, &control, RealtimeCommand::CycleStart)
+                }
+                None if command == "abort" => match abort_job(&job, &planner, &control) {
+                    Some(body) => (200, "OK", body),
+                    None => (409, "Conflict", "no job is running\n".to_string()),
+                },
+                _ => (
+                    400,
+                    "Bad Request",
+                    "expected start <name>, pause, resume, or abort\n".to_string(),
+                ),
+            };
+            let mut response =
+                request.into_response(status, Some(reason), &[("Content-Type", "text/plain")])?;
+            response.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+    }
+
+    {
+        let control = Arc::clone(&control);
+        server.fn_handler("/autotune", Method::Get, move |request| -> Result<()> {
+            let body = autotune_json(
+                &control
+                    .lock()
+                    .expect("machine control lock poisoned")
+                    .heaters,
+            );
+            let mut response = request.into_response(
+                200,
+                Some("OK"),
+                &[
+                    ("Content-Type", "application/json"),
+                    ("Cache-Control", "no-store"),
+                ],
+            )?;
+            response.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+    }
 
+    {
+        let control = Arc::clone(&control);
+        server.fn_handler(
+            "/autotune",
+            Method::Post,
+            move |mut request| -> Result<()> {
+                let body = read_body(&mut request, COMMAND_BODY_LIMIT)?;
+                let (status, reason, body) = match text_body(body, COMMAND_BODY_LIMIT) {
+                    Ok(command) => autotune_command(&control, &settings, command.trim()),
+                    Err(error) => error,
+                };
+                let mut response = request.into_response(
+                    status,
+                    Some(reason),
+                    &[("Content-Type", "text/plain")],
+                )?;
+                response.write_all(body.as_bytes())?;
+                Ok(())
+            },
+        )?;
+    }
+
+    server.fn_handler("/queue", Method::Post, move |mut request| -> Result<()> {
         macro_rules! respond {
             ($status:expr, $reason:expr, $body:expr) => {{
                 let body = $body;
@@ -289,84 +2045,47 @@ fn main() -> Result<()> {
             }};
         }
 
+        let Some(buffer) = read_body(&mut request, QUEUE_BODY_LIMIT)? else {
+            respond!(
+                413,
+                "Payload Too Large",
+                format!("request body exceeds {QUEUE_BODY_LIMIT} bytes\n")
+            );
+            return Ok(());
+        };
+        // Override bytes are not valid UTF-8 on their own, so real-time commands are matched first.
+        let realtime_command = match buffer.trim_ascii() {
+            [byte] => RealtimeCommand::from_byte(*byte),
+            _ => None,
+        };
+
+        if let Some(command) = realtime_command {
+            // A stop also ends the running job, which would otherwise refill the queue.
+            let aborted = match command {
+                RealtimeCommand::Stop => abort_job(&job, &planner, &control),
+                _ => None,
+            };
+            let (status, reason, body) = match aborted {
+                Some(body) => (200, "OK", body),
+                None => realtime(&planner, &control, command),
+            };
+            respond!(status, reason, body);
+            return Ok(());
+        }
+        let Ok(command) = std::str::from_utf8(&buffer) else {
+            respond!(400, "Bad Request", "request body is not UTF-8\n");
+            return Ok(());
+        };
+        let command = command.trim();
+
         match command {
-            "status_on" => {
-                status_led
-                    .lock()
-                    .expect("status LED lock poisoned")
-                    .set_high()?;
-                respond!(200, "OK", "Status LED on\n");
-            }
-            "status_off" => {
-                status_led
-                    .lock()
-                    .expect("status LED lock poisoned")
-                    .set_low()?;
-                respond!(200, "OK", "Status LED off\n");
-            }
-            "relay_on" => {
-                relay.lock().expect("relay lock poisoned").set_low()?;
-                respond!(200, "OK", "Relay on\n");
-            }
-            "relay_off" => {
-                relay.lock().expect("relay lock poisoned").set_high()?;
-                respond!(200, "OK", "Relay off\n");
-            }
-            "d0_high" => {
-                d0.lock().expect("D0 lock poisoned").set_high()?;
-                respond!(200, "OK", "D0 high\n");
-            }
-            "d0_low" => {
-                d0.lock().expect("D0 lock poisoned").set_low()?;
-                respond!(200, "OK", "D0 low\n");
-            }
-            "d1_high" => {
-                d1.lock().expect("D1 lock poisoned").set_high()?;
-                respond!(200, "OK", "D1 high\n");
-            }
-            "d1_low" => {
-                d1.lock().expect("D1 lock poisoned").set_low()?;
-                respond!(200, "OK", "D1 low\n");
-            }
-            "d3_high" => {
-                d3.lock().expect("D3 lock poisoned").set_high()?;
-                respond!(200, "OK", "D3 high\n");
-            }
-            "d3_low" => {
-                d3.lock().expect("D3 lock poisoned").set_low()?;
-                respond!(200, "OK", "D3 low\n");
-            }
-            "d4_high" => {
-                d4.lock().expect("D4 lock poisoned").set_high()?;
-                respond!(200, "OK", "D4 high\n");
+            "$H" => {
+                let (status, reason, body) = queue_gcode(&interpreter, &planner, &control, "G28");
+                respond!(status, reason, body);
             }
-            "d4_low" => {
-                d4.lock().expect("D4 lock poisoned").set_low()?;
-                respond!(200, "OK", "D4 low\n");
-            }
-            "d5_high" => {
-                d5.lock().expect("D5 lock poisoned").set_high()?;
-                respond!(200, "OK", "D5 high\n");
-            }
-            "d5_low" => {
-                d5.lock().expect("D5 lock poisoned").set_low()?;
-                respond!(200, "OK", "D5 low\n");
-            }
-            "d6_high" => {
-                d6.lock().expect("D6 lock poisoned").set_high()?;
-                respond!(200, "OK", "D6 high\n");
-            }
-            "d6_low" => {
-                d6.lock().expect("D6 lock poisoned").set_low()?;
-                respond!(200, "OK", "D6 low\n");
-            }
-            "d7_high" => {
-                d7.lock().expect("D7 lock poisoned").set_high()?;
-                respond!(200, "OK", "D7 high\n");
-            }
-            "d7_low" => {
-                d7.lock().expect("D7 lock poisoned").set_low()?;
-                respond!(200, "OK", "D7 low\n");
+            "$X" => {
+                let (status, reason, body) = unlock(&control);
+                respond!(status, reason, body);
             }
             "scan_wifi" | "set_wifi" => {
                 respond!(
@@ -375,39 +2094,22 @@ fn main() -> Result<()> {
                     "Wi-Fi configuration is not implemented\n"
                 );
             }
-            command if command == "g0" || command.starts_with("g0 ") => {
-                let mut x = 10.0;
-                let mut y = 0.0;
-                let mut z = 0.0;
-                let mut feed_rate = 1_500.0;
-
-                for word in command.split_whitespace().skip(1) {
-                    let Some((axis, value)) = word.get(..1).zip(word.get(1..)) else {
-                        continue;
-                    };
-                    let Ok(value) = value.parse::<f32>() else {
-                        continue;
-                    };
-                    match axis {
-                        "x" | "X" => x = value,
-                        "y" | "Y" => y = value,
-                        "z" | "Z" => z = value,
-                        "f" | "F" => feed_rate = value,
-                        _ => {}
+            jog if jog.starts_with("$J=") => {
+                let (status, reason, body) =
+                    queue_jog(&interpreter, &planner, &control, &jog["$J=".len()..]);
+                respond!(status, reason, body);
+            }
+            program => {
+                if let Some((name, on)) = status_output_command(program) {
+                    let mut board_io = board_io.lock().expect("board I/O lock poisoned");
+                    if board_io.set_status_output(name, on)? {
+                        let state = if on { "on" } else { "off" };
+                        respond!(200, "OK", format!("{name} {state}\n"));
+                        return Ok(());
                     }
                 }
-
-                let mut planner = planner.lock().expect("motion planner lock poisoned");
-                if planner.buffer_line(x, y, z, 0.0, feed_rate) {
-                    planner.recalculate_trapezoids();
-                    respond!(200, "OK", "Queued G0\n");
-                } else {
-                    respond!(503, "Service Unavailable", "Motion queue full\n");
-                }
-            }
-            unknown => {
-                log::warn!("Unknown queue command: {unknown}");
-                respond!(400, "Bad Request", "Unknown command\n");
+                let (status, reason, body) = queue_gcode(&interpreter, &planner, &control, program);
+                respond!(status, reason, body);
             }
         }
 
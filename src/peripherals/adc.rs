@@ -0,0 +1,124 @@
+//! Calibrated analog inputs on ADC1.
+//!
+//! Inputs use the ESP-IDF one-shot driver with 12 dB attenuation, which covers roughly 0–3.1 V.
+//! ADC2 is unavailable while Wi-Fi is active, so only ADC1 pins are accepted. Readings go through
+//! the chip's eFuse line-fitting calibration when it is available.
+
+use core::ptr;
+
+use esp_idf_sys::{self as sys, EspError, esp};
+
+/// Raw samples averaged into each reading.
+const OVERSAMPLING: u32 = 16;
+/// Full-scale voltage assumed when the chip has no calibration data.
+const UNCALIBRATED_FULL_SCALE_MV: f32 = 3_300.0;
+/// Largest raw value at the default 12-bit width.
+const RAW_MAX: f32 = 4_095.0;
+/// Mean raw value at or above which an input counts as saturated. With 12 dB attenuation the
+/// input saturates around 3.1 V, below the supply, so a divider's open-circuit voltage never
+/// shows up as millivolts.
+const RAW_SATURATED: f32 = 4_080.0;
+/// Reference voltage the line-fitting scheme assumes when the eFuse holds none.
+const DEFAULT_VREF_MV: u32 = 1_100;
+
+/// One-shot ADC1 channels read as millivolts.
+pub struct AdcInputs {
+    unit: sys::adc_oneshot_unit_handle_t,
+    calibration: Option<sys::adc_cali_handle_t>,
+    /// ADC channel of each input, in the order their pins were passed to `new`.
+    channels: Vec<sys::adc_channel_t>,
+}
+
+// The driver handles are owned by this value and only used through `&mut self`.
+unsafe impl Send for AdcInputs {}
+
+impl AdcInputs {
+    /// Configures each of `pins` as an ADC1 input.
+    pub fn new(pins: &[i32]) -> Result<Self, EspError> {
+        let mut unit = ptr::null_mut();
+        let unit_config = sys::adc_oneshot_unit_init_cfg_t {
+            unit_id: sys::adc_unit_t_ADC_UNIT_1,
+            ..Default::default()
+        };
+        esp!(unsafe { sys::adc_oneshot_new_unit(&unit_config, &mut unit) })?;
+        let mut inputs = Self {
+            unit,
+            calibration: None,
+            channels: Vec::with_capacity(pins.len()),
+        };
+
+        let channel_config = sys::adc_oneshot_chan_cfg_t {
+            atten: sys::adc_atten_t_ADC_ATTEN_DB_12,
+            bitwidth: sys::adc_bitwidth_t_ADC_BITWIDTH_DEFAULT,
+        };
+        for &pin in pins {
+            let mut unit_id = 0;
+            let mut channel = 0;
+            esp!(unsafe { sys::adc_oneshot_io_to_channel(pin, &mut unit_id, &mut channel) })?;
+            if unit_id != sys::adc_unit_t_ADC_UNIT_1 {
+                return Err(EspError::from_infallible::<
+                    { sys::ESP_ERR_NOT_SUPPORTED as sys::esp_err_t },
+                >());
+            }
+            esp!(unsafe { sys::adc_oneshot_config_channel(unit, channel, &channel_config) })?;
+            inputs.channels.push(channel);
+        }
+
+        let calibration_config = sys::adc_cali_line_fitting_config_t {
+            unit_id: sys::adc_unit_t_ADC_UNIT_1,
+            atten: channel_config.atten,
+            bitwidth: channel_config.bitwidth,
+            default_vref: DEFAULT_VREF_MV,
+        };
+        let mut calibration = ptr::null_mut();
+        match esp!(unsafe {
+            sys::adc_cali_create_scheme_line_fitting(&calibration_config, &mut calibration)
+        }) {
+            Ok(()) => inputs.calibration = Some(calibration),
+            Err(error) => {
+                log::warn!("ADC calibration unavailable, readings are approximate: {error}")
+            }
+        }
+        Ok(inputs)
+    }
+
+    /// Returns the mean of several samples of input `index`, in millivolts.
+    ///
+    /// A saturated input reads as infinity, which a [`Sensor`](crate::temperature::Sensor)
+    /// reports as an open circuit whatever its threshold.
+    pub fn read_millivolts(&mut self, index: usize) -> Result<f32, EspError> {
+        let channel = self.channels[index];
+        let mut total = 0.0;
+        let mut raw_total = 0.0;
+        for _ in 0..OVERSAMPLING {
+            let mut raw = 0;
+            esp!(unsafe { sys::adc_oneshot_read(self.unit, channel, &mut raw) })?;
+            raw_total += raw as f32;
+            total += match self.calibration {
+                Some(calibration) => {
+                    let mut millivolts = 0;
+                    esp!(unsafe {
+                        sys::adc_cali_raw_to_voltage(calibration, raw, &mut millivolts)
+                    })?;
+                    millivolts as f32
+                }
+                None => raw as f32 * UNCALIBRATED_FULL_SCALE_MV / RAW_MAX,
+            };
+        }
+        if raw_total / OVERSAMPLING as f32 >= RAW_SATURATED {
+            return Ok(f32::INFINITY);
+        }
+        Ok(total / OVERSAMPLING as f32)
+    }
+}
+
+impl Drop for AdcInputs {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(calibration) = self.calibration {
+                sys::adc_cali_delete_scheme_line_fitting(calibration);
+            }
+            sys::adc_oneshot_del_unit(self.unit);
+        }
+    }
+}
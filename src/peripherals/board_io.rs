@@ -0,0 +1,221 @@
+//! Board outputs and inputs built from the selected [`Board`] description.
+//!
+//! [`BoardPins`] drives native GPIO and the board's expander through one [`OutputPins`]
+//! interface, so callers address every pin in the board's pin map by number. Heater, fan, and
+//! spindle outputs are driven off at startup so that nothing powers up before the firmware
+//! controls it. Status outputs stay switchable by name from the HTTP interface.
+
+use esp_idf_sys::{self as sys, EspError, esp};
+
+use crate::{
+    devices::{Board, ExpanderBus, StatusOutput, is_virtual_pin, undriven_axes},
+    peripherals::{
+        expander::{OutputExpander, OutputPins, PinError},
+        expander_bus::BusTransport,
+    },
+};
+
+/// Number of native GPIO numbers on the ESP32.
+const NATIVE_GPIO_COUNT: i32 = 40;
+/// Lowest GPIO number that is input-only on the ESP32 and has no internal pull resistors.
+const FIRST_INPUT_ONLY_GPIO: i32 = 34;
+
+/// Native GPIO outputs plus the board's optional expander.
+pub struct BoardPins {
+    expander: Option<OutputExpander<BusTransport>>,
+    /// Requested level of each native output, bit `n` for GPIO `n`.
+    native_levels: u64,
+}
+
+impl BoardPins {
+    /// Sets up the expander bus, if the board has one.
+    pub fn new(bus: Option<ExpanderBus>) -> Result<Self, EspError> {
+        let expander = bus
+            .map(BusTransport::new)
+            .transpose()?
+            .map(OutputExpander::new);
+        Ok(Self {
+            expander,
+            native_levels: 0,
+        })
+    }
+
+    /// Returns `true` if `pin` is a native GPIO or an output on the configured expander.
+    pub fn supports(&self, pin: i32) -> bool {
+        if is_virtual_pin(pin) {
+            self.expander.is_some() && OutputExpander::<BusTransport>::contains(pin)
+        } else {
+            (0..NATIVE_GPIO_COUNT).contains(&pin)
+        }
+    }
+
+    /// Makes `pin` an output at `high`. Expander pins take the level at the next flush.
+    pub fn configure_output(&mut self, pin: i32, high: bool) -> Result<(), PinError<EspError>> {
+        if !is_virtual_pin(pin) {
+            esp!(unsafe { sys::gpio_reset_pin(pin) }).map_err(PinError::Bus)?;
+            let mode = sys::gpio_mode_t_GPIO_MODE_OUTPUT;
+            esp!(unsafe { sys::gpio_set_direction(pin, mode) }).map_err(PinError::Bus)?;
+        }
+        self.set_level(pin, high)
+    }
+}
+
+impl OutputPins for BoardPins {
+    type Error = EspError;
+
+    fn set_level(&mut self, pin: i32, high: bool) -> Result<(), PinError<EspError>> {
+        if is_virtual_pin(pin) {
+            return match &mut self.expander {
+                Some(expander) => expander.set_level(pin, high),
+                None => Err(PinError::InvalidPin(pin)),
+            };
+        }
+        if !(0..NATIVE_GPIO_COUNT).contains(&pin) {
+            return Err(PinError::InvalidPin(pin));
+        }
+        esp!(unsafe { sys::gpio_set_level(pin, u32::from(high)) }).map_err(PinError::Bus)?;
+        if high {
+            self.native_levels |= 1 << pin;
+        } else {
+            self.native_levels &= !(1 << pin);
+        }
+        Ok(())
+    }
+
+    fn is_set_high(&self, pin: i32) -> bool {
+        if is_virtual_pin(pin) {
+            self.expander
+                .as_ref()
+                .is_some_and(|expander| expander.is_set_high(pin))
+        } else {
+            (0..NATIVE_GPIO_COUNT).contains(&pin) && self.native_levels >> pin & 1 == 1
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), PinError<EspError>> {
+        match &mut self.expander {
+            Some(expander) => expander.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The selected board's outputs and inputs outside the step generator.
+pub struct BoardIo {
+    pins: BoardPins,
+    status_outputs: Vec<StatusOutput>,
+}
+
+impl BoardIo {
+    /// Configures `B`'s heater, fan, spindle, and status outputs off and its endstops and safety
+    /// door as inputs.
+    ///
+    /// Stepper drivers on axes that
+    /// [`PinStepOutput`](crate::peripherals::step_output::PinStepOutput) cannot pulse are
+    /// disabled, including native drivers ganged with one it cannot reach.
+    pub fn new<B: Board>() -> Result<Self, PinError<EspError>> {
+        let mut pins = BoardPins::new(B::EXPANDER).map_err(PinError::Bus)?;
+
+        let spindle = B::SPINDLE
+            .into_iter()
+            .flat_map(|spindle| [spindle.enable, spindle.pwm]);
+        let power_outputs = B::HEATERS
+            .iter()
+            .map(|heater| heater.output)
+            .chain(B::FANS.iter().map(|fan| fan.output))
+            .chain(spindle.flatten());
+        for pin in power_outputs {
+            if supported(&pins, pin, "power output") {
+                pins.configure_output(pin, false)?;
+            }
+        }
+
+        // Drivers the step interrupt cannot reach stay disabled through their active-low enable.
+        let undriven = undriven_axes(B::STEPPERS);
+        let unreachable_drivers = B::STEPPERS
+            .iter()
+            .filter(|channel| undriven >> channel.axis & 1 == 1);
+        for pin in unreachable_drivers.filter_map(|channel| channel.enable) {
+            if supported(&pins, pin, "stepper enable") {
+                pins.configure_output(pin, true)?;
+            }
+        }
+
+        let mut status_outputs = Vec::with_capacity(B::STATUS_OUTPUTS.len());
+        for output in B::STATUS_OUTPUTS {
+            if supported(&pins, output.pin, output.name) {
+                pins.configure_output(output.pin, output.active_low)?;
+                status_outputs.push(*output);
+            }
+        }
+        pins.flush()?;
+
+        for endstop in B::ENDSTOPS {
+            if is_virtual_pin(endstop.pin) {
+                log::warn!("Skipping endstop on virtual pin {}", endstop.pin);
+            } else {
+                configure_input(endstop.pin).map_err(PinError::Bus)?;
+            }
+        }
+        if let Some(door) = B::SAFETY_DOOR {
+            if is_virtual_pin(door.pin) {
+                log::warn!("Skipping safety door on virtual pin {}", door.pin);
+            } else {
+                configure_input(door.pin).map_err(PinError::Bus)?;
+            }
+        }
+
+        Ok(Self {
+            pins,
+            status_outputs,
+        })
+    }
+
+    /// Returns the pins for motion and thermal code. Call [`OutputPins::flush`] after changes.
+    pub fn pins_mut(&mut self) -> &mut BoardPins {
+        &mut self.pins
+    }
+
+    /// Returns each status output's name and whether it is on.
+    pub fn status_outputs(&self) -> impl Iterator<Item = (&'static str, bool)> + '_ {
+        self.status_outputs.iter().map(|output| {
+            (
+                output.name,
+                self.pins.is_set_high(output.pin) != output.active_low,
+            )
+        })
+    }
+
+    /// Switches the status output called `name`. Returns `false` if the board has no such output.
+    pub fn set_status_output(&mut self, name: &str, on: bool) -> Result<bool, PinError<EspError>> {
+        let Some(output) = self
+            .status_outputs
+            .iter()
+            .find(|output| output.name == name)
+        else {
+            return Ok(false);
+        };
+        self.pins.set_level(output.pin, on != output.active_low)?;
+        self.pins.flush()?;
+        Ok(true)
+    }
+}
+
+/// Returns `true` if `pins` can drive `pin`, logging a warning otherwise.
+fn supported(pins: &BoardPins, pin: i32, role: &str) -> bool {
+    let supported = pins.supports(pin);
+    if !supported {
+        log::warn!("Skipping {role} on pin {pin}, which this board cannot drive");
+    }
+    supported
+}
+
+/// Resets `pin` to an input, pulled up where the pin supports it.
+fn configure_input(pin: i32) -> Result<(), EspError> {
+    esp!(unsafe { sys::gpio_reset_pin(pin) })?;
+    esp!(unsafe { sys::gpio_set_direction(pin, sys::gpio_mode_t_GPIO_MODE_INPUT) })?;
+    if pin < FIRST_INPUT_ONLY_GPIO {
+        esp!(unsafe { sys::gpio_set_pull_mode(pin, sys::gpio_pull_mode_t_GPIO_PULLUP_ONLY) })?;
+    }
+    Ok(())
+}
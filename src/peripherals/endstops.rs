@@ -0,0 +1,80 @@
+//! Endstop and safety-door switches read from native GPIO.
+//!
+//! [`BoardIo`](crate::peripherals::board_io::BoardIo) configures the pins as inputs at startup;
+//! this module only samples them, which is cheap enough to do on every step interrupt while a
+//! homing move watches the switches and on every edge that could be a hard limit.
+
+use esp_idf_sys as sys;
+
+use crate::{
+    devices::{Endstop, SafetyDoor, is_virtual_pin},
+    interrupts::EndstopInputs,
+};
+
+/// The board's native endstop inputs.
+///
+/// Switches that share an axis are combined, so a ganged axis stops at whichever of its switches
+/// triggers first.
+#[derive(Clone, Debug)]
+pub struct GpioEndstops {
+    endstops: Vec<Endstop>,
+}
+
+impl GpioEndstops {
+    /// Samples the switches in `endstops` that sit on native GPIO.
+    pub fn new(endstops: &[Endstop]) -> Self {
+        Self {
+            endstops: endstops
+                .iter()
+                .copied()
+                .filter(|endstop| !is_virtual_pin(endstop.pin))
+                .collect(),
+        }
+    }
+
+    /// Returns the GPIO number of every sampled switch.
+    pub fn pins(&self) -> impl Iterator<Item = i32> + '_ {
+        self.endstops.iter().map(|endstop| endstop.pin)
+    }
+
+    /// Returns a mask with bit `n` set while a switch on axis `n` is triggered.
+    pub fn triggered(&self) -> u8 {
+        self.endstops.iter().fold(0, |triggered, endstop| {
+            let high = unsafe { sys::gpio_get_level(endstop.pin) } != 0;
+            if high != endstop.active_low {
+                triggered | 1 << endstop.axis
+            } else {
+                triggered
+            }
+        })
+    }
+}
+
+impl EndstopInputs for GpioEndstops {
+    fn triggered(&mut self) -> u8 {
+        GpioEndstops::triggered(self)
+    }
+}
+
+/// The board's safety-door switch, if it has one on native GPIO.
+#[derive(Clone, Copy, Debug)]
+pub struct GpioSafetyDoor {
+    door: Option<SafetyDoor>,
+}
+
+impl GpioSafetyDoor {
+    /// Samples `door` if it sits on native GPIO.
+    pub fn new(door: Option<SafetyDoor>) -> Self {
+        Self {
+            door: door.filter(|door| !is_virtual_pin(door.pin)),
+        }
+    }
+
+    /// Returns `true` while the door is open. A board without a door input reports it closed.
+    pub fn is_open(&self) -> bool {
+        self.door.is_some_and(|door| {
+            let high = unsafe { sys::gpio_get_level(door.pin) } != 0;
+            high != door.active_low
+        })
+    }
+}
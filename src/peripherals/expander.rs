@@ -0,0 +1,207 @@
+//! Output expanders addressed through the virtual-pin range.
+//!
+//! [`OutputExpander`] keeps the requested level of every virtual pin and sends them to its
+//! [`ExpanderTransport`] in one write per [`OutputPins::flush`], so changing several outputs costs
+//! a single bus transaction.
+
+use core::fmt;
+
+use crate::devices::{VIRTUAL_PIN_BASE, is_virtual_pin};
+
+/// Number of virtual pins one expander can address.
+pub const EXPANDER_PINS: i32 = 32;
+
+/// An output pin number that the addressed driver cannot drive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinError<E> {
+    InvalidPin(i32),
+    Bus(E),
+}
+
+impl<E: fmt::Display> fmt::Display for PinError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidPin(pin) => write!(f, "pin {pin} is not available"),
+            Self::Bus(error) => write!(f, "output bus error: {error}"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for PinError<E> {}
+
+/// Digital outputs addressed by firmware pin number, native GPIO and virtual pins alike.
+pub trait OutputPins {
+    type Error;
+
+    /// Requests a level for `pin`. Virtual pins change on the next [`flush`](Self::flush).
+    fn set_level(&mut self, pin: i32, high: bool) -> Result<(), PinError<Self::Error>>;
+    /// Returns the level last requested for `pin`.
+    fn is_set_high(&self, pin: i32) -> bool;
+    /// Sends every level requested since the last flush.
+    fn flush(&mut self) -> Result<(), PinError<Self::Error>>;
+}
+
+/// Bus that shifts a full set of expander outputs in one transaction.
+pub trait ExpanderTransport {
+    type Error;
+
+    /// Writes `bits`, where bit `n` drives virtual pin `VIRTUAL_PIN_BASE + n`. `changed` marks
+    /// the bits that differ from the previous write, so transports with several chips can skip
+    /// unchanged ones.
+    fn write(&mut self, bits: u32, changed: u32) -> Result<(), Self::Error>;
+}
+
+/// Batched outputs on one expander.
+pub struct OutputExpander<T> {
+    transport: T,
+    requested: u32,
+    /// Bits last sent to the transport, or `None` before the first write.
+    written: Option<u32>,
+}
+
+impl<T: ExpanderTransport> OutputExpander<T> {
+    /// Creates an expander with every output low. Nothing is sent until the first flush.
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            requested: 0,
+            written: None,
+        }
+    }
+
+    /// Returns `true` if `pin` falls inside this expander's virtual-pin range.
+    pub fn contains(pin: i32) -> bool {
+        is_virtual_pin(pin) && pin - VIRTUAL_PIN_BASE < EXPANDER_PINS
+    }
+
+    /// Returns the transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: ExpanderTransport> OutputPins for OutputExpander<T> {
+    type Error = T::Error;
+
+    fn set_level(&mut self, pin: i32, high: bool) -> Result<(), PinError<T::Error>> {
+        if !Self::contains(pin) {
+            return Err(PinError::InvalidPin(pin));
+        }
+        let bit = 1 << (pin - VIRTUAL_PIN_BASE);
+        if high {
+            self.requested |= bit;
+        } else {
+            self.requested &= !bit;
+        }
+        Ok(())
+    }
+
+    fn is_set_high(&self, pin: i32) -> bool {
+        Self::contains(pin) && self.requested >> (pin - VIRTUAL_PIN_BASE) & 1 == 1
+    }
+
+    fn flush(&mut self) -> Result<(), PinError<T::Error>> {
+        let changed = match self.written {
+            Some(written) => written ^ self.requested,
+            None => u32::MAX,
+        };
+        if changed == 0 {
+            return Ok(());
+        }
+        self.transport
+            .write(self.requested, changed)
+            .map_err(PinError::Bus)?;
+        self.written = Some(self.requested);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use super::*;
+
+    /// Transport that records each write instead of driving a bus.
+    #[derive(Debug, Default)]
+    struct MockTransport {
+        /// `(bits, changed)` for every write, oldest first.
+        writes: Vec<(u32, u32)>,
+    }
+
+    impl ExpanderTransport for MockTransport {
+        type Error = Infallible;
+
+        fn write(&mut self, bits: u32, changed: u32) -> Result<(), Infallible> {
+            self.writes.push((bits, changed));
+            Ok(())
+        }
+    }
+
+    /// Transport whose bus never acknowledges.
+    struct FailingTransport;
+
+    impl ExpanderTransport for FailingTransport {
+        type Error = &'static str;
+
+        fn write(&mut self, _bits: u32, _changed: u32) -> Result<(), &'static str> {
+            Err("no acknowledge")
+        }
+    }
+
+    #[test]
+    fn flush_batches_requested_levels() {
+        let mut expander = OutputExpander::new(MockTransport::default());
+        expander.set_level(VIRTUAL_PIN_BASE, true).unwrap();
+        expander.set_level(VIRTUAL_PIN_BASE + 5, true).unwrap();
+        expander.set_level(VIRTUAL_PIN_BASE + 31, true).unwrap();
+        assert!(expander.transport().writes.is_empty());
+        assert!(expander.is_set_high(VIRTUAL_PIN_BASE + 5));
+        assert!(!expander.is_set_high(VIRTUAL_PIN_BASE + 6));
+
+        // The first write sends every bit, since the outputs' power-on state is unknown.
+        expander.flush().unwrap();
+        let bits = 1 | 1 << 5 | 1 << 31;
+        assert_eq!(expander.transport().writes, [(bits, u32::MAX)]);
+
+        expander.set_level(VIRTUAL_PIN_BASE + 5, false).unwrap();
+        expander.set_level(VIRTUAL_PIN_BASE + 7, true).unwrap();
+        expander.flush().unwrap();
+        assert_eq!(
+            expander.transport().writes[1],
+            (1 | 1 << 7 | 1 << 31, 1 << 5 | 1 << 7)
+        );
+    }
+
+    #[test]
+    fn unchanged_flush_skips_the_bus() {
+        let mut expander = OutputExpander::new(MockTransport::default());
+        expander.flush().unwrap();
+        expander.set_level(VIRTUAL_PIN_BASE + 2, true).unwrap();
+        expander.set_level(VIRTUAL_PIN_BASE + 2, false).unwrap();
+        expander.flush().unwrap();
+        assert_eq!(expander.transport().writes, [(0, u32::MAX)]);
+    }
+
+    #[test]
+    fn pins_outside_the_range_are_rejected() {
+        let mut expander = OutputExpander::new(MockTransport::default());
+        for pin in [4, VIRTUAL_PIN_BASE - 1, VIRTUAL_PIN_BASE + EXPANDER_PINS] {
+            assert_eq!(
+                expander.set_level(pin, true),
+                Err(PinError::InvalidPin(pin))
+            );
+            assert!(!expander.is_set_high(pin));
+        }
+        expander.flush().unwrap();
+        assert_eq!(expander.transport().writes, [(0, u32::MAX)]);
+    }
+
+    #[test]
+    fn failed_write_is_retried_on_the_next_flush() {
+        let mut expander = OutputExpander::new(FailingTransport);
+        expander.set_level(VIRTUAL_PIN_BASE, true).unwrap();
+        assert_eq!(expander.flush(), Err(PinError::Bus("no acknowledge")));
+        assert_eq!(expander.written, None);
+    }
+}
@@ -0,0 +1,135 @@
+//! ESP32 buses behind [`OutputExpander`](crate::peripherals::expander::OutputExpander).
+//!
+//! Both transports run in task context. The shift-register chain is bit-banged on native GPIO and
+//! latched once per write; PCF8575 chips are written over the legacy I2C master driver, skipping
+//! chips whose outputs did not change.
+
+use esp_idf_sys::{self as sys, EspError, esp};
+
+use crate::{
+    devices::ExpanderBus,
+    peripherals::expander::{EXPANDER_PINS, ExpanderTransport},
+};
+
+/// I2C controller used for PCF8575 expanders.
+const I2C_PORT: sys::i2c_port_t = 0;
+/// PCF8575 bus clock in hertz.
+const I2C_FREQUENCY: u32 = 400_000;
+/// Longest wait for one I2C write, in FreeRTOS ticks.
+const I2C_TIMEOUT_TICKS: u32 = 10;
+
+/// Transport for the bus a board describes.
+pub enum BusTransport {
+    ShiftRegister {
+        data: i32,
+        clock: i32,
+        latch: i32,
+        outputs: u8,
+    },
+    Pcf8575 {
+        address: u8,
+        chips: u8,
+    },
+}
+
+impl BusTransport {
+    /// Configures the pins or I2C controller for `bus`.
+    pub fn new(bus: ExpanderBus) -> Result<Self, EspError> {
+        match bus {
+            ExpanderBus::ShiftRegister {
+                data,
+                clock,
+                latch,
+                outputs,
+            } => {
+                for pin in [data, clock, latch] {
+                    esp!(unsafe { sys::gpio_reset_pin(pin) })?;
+                    esp!(unsafe {
+                        sys::gpio_set_direction(pin, sys::gpio_mode_t_GPIO_MODE_OUTPUT)
+                    })?;
+                    esp!(unsafe { sys::gpio_set_level(pin, 0) })?;
+                }
+                Ok(Self::ShiftRegister {
+                    data,
+                    clock,
+                    latch,
+                    outputs,
+                })
+            }
+            ExpanderBus::Pcf8575 {
+                sda,
+                scl,
+                address,
+                chips,
+            } => {
+                let config = sys::i2c_config_t {
+                    mode: sys::i2c_mode_t_I2C_MODE_MASTER,
+                    sda_io_num: sda,
+                    scl_io_num: scl,
+                    sda_pullup_en: true,
+                    scl_pullup_en: true,
+                    __bindgen_anon_1: sys::i2c_config_t__bindgen_ty_1 {
+                        master: sys::i2c_config_t__bindgen_ty_1__bindgen_ty_1 {
+                            clk_speed: I2C_FREQUENCY,
+                        },
+                    },
+                    clk_flags: 0,
+                };
+                esp!(unsafe { sys::i2c_param_config(I2C_PORT, &config) })?;
+                esp!(unsafe { sys::i2c_driver_install(I2C_PORT, config.mode, 0, 0, 0) })?;
+                Ok(Self::Pcf8575 { address, chips })
+            }
+        }
+    }
+}
+
+impl ExpanderTransport for BusTransport {
+    type Error = EspError;
+
+    fn write(&mut self, bits: u32, changed: u32) -> Result<(), EspError> {
+        match *self {
+            Self::ShiftRegister {
+                data,
+                clock,
+                latch,
+                outputs,
+            } => {
+                // The first bit shifted in ends up on the last register, so shift the highest
+                // output first.
+                for bit in (0..u32::from(outputs)).rev() {
+                    unsafe {
+                        sys::gpio_set_level(data, bits >> bit & 1);
+                        sys::gpio_set_level(clock, 1);
+                        sys::gpio_set_level(clock, 0);
+                    }
+                }
+                unsafe {
+                    sys::gpio_set_level(latch, 1);
+                    sys::gpio_set_level(latch, 0);
+                }
+                Ok(())
+            }
+            Self::Pcf8575 { address, chips } => {
+                let chips = u32::from(chips).min(EXPANDER_PINS as u32 / 16);
+                for chip in 0..chips {
+                    let shift = 16 * chip;
+                    if changed >> shift & 0xffff == 0 {
+                        continue;
+                    }
+                    // Port P0 is sent first, then port P1.
+                    let ports = ((bits >> shift) as u16).to_le_bytes();
+                    esp!(unsafe {
+                        sys::i2c_master_write_to_device(
+                            I2C_PORT,
+                            address + chip as u8,
+                            ports.as_ptr(),
+                            ports.len(),
+                            I2C_TIMEOUT_TICKS,
+                        )
+                    })?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -1,3 +1,15 @@
 //! Device-independent peripheral drivers.
 //!
-//! Drivers will be added here as the firmware grows beyond its current GPIO and HTTP prototype.
+//! Drivers here wrap ESP-IDF peripherals for the hardware-independent logic elsewhere in the
+//! firmware.
+
+pub mod adc;
+pub mod board_io;
+pub mod endstops;
+pub mod expander_bus;
+pub mod rmt_step;
+pub mod step_output;
+pub mod step_timer;
+
+// The expander batching logic is shared with the host-buildable library.
+pub use alumina_firmware::peripherals::expander;
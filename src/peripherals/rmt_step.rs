@@ -0,0 +1,57 @@
+//! Step pulses shaped by the RMT peripheral.
+//!
+//! Each channel holds a single preloaded item: the pin high for the configured pulse width,
+//! followed by an end marker. Starting a transmission replays that item, so the step interrupt
+//! only has to trigger the channel and the pulse width does not depend on interrupt latency.
+//! Channels are configured through the legacy RMT driver, whose start call is safe to use from
+//! interrupt context.
+
+use esp_idf_sys::{self as sys, EspError, esp};
+
+/// APB clock divider giving one RMT tick per microsecond from the 80 MHz APB clock.
+const RMT_CLOCK_DIVIDER: u8 = 80;
+/// Longest duration one RMT item half can encode, in ticks.
+const RMT_MAXIMUM_DURATION: u32 = 0x7fff;
+
+/// One RMT transmit channel that emits a fixed-width step pulse on demand.
+pub struct RmtStepChannel {
+    channel: sys::rmt_channel_t,
+}
+
+impl RmtStepChannel {
+    /// Routes `channel` to `pin` and preloads a high pulse of `pulse_microseconds`.
+    pub fn new(channel: u8, pin: i32, pulse_microseconds: u32) -> Result<Self, EspError> {
+        let channel = sys::rmt_channel_t::from(channel);
+        let config = sys::rmt_config_t {
+            rmt_mode: sys::rmt_mode_t_RMT_MODE_TX,
+            channel,
+            gpio_num: pin,
+            clk_div: RMT_CLOCK_DIVIDER,
+            mem_block_num: 1,
+            flags: 0,
+            __bindgen_anon_1: sys::rmt_config_t__bindgen_ty_1 {
+                tx_config: sys::rmt_tx_config_t {
+                    idle_level: sys::rmt_idle_level_t_RMT_IDLE_LEVEL_LOW,
+                    idle_output_en: true,
+                    ..Default::default()
+                },
+            },
+        };
+        esp!(unsafe { sys::rmt_config(&config) })?;
+
+        // Level 1 for the pulse width, then a zero duration that ends the transmission.
+        let pulse = pulse_microseconds.clamp(1, RMT_MAXIMUM_DURATION) | 1 << 15;
+        let items = [pulse, 0].map(|val| sys::rmt_item32_t {
+            __bindgen_anon_1: sys::rmt_item32_t__bindgen_ty_1 { val },
+        });
+        esp!(unsafe { sys::rmt_fill_tx_items(channel, items.as_ptr(), items.len() as u16, 0) })?;
+
+        Ok(Self { channel })
+    }
+
+    /// Starts one pulse from the beginning of the preloaded item. Safe to call from interrupt
+    /// context.
+    pub fn pulse(&self) {
+        unsafe { sys::rmt_tx_start(self.channel, true) };
+    }
+}
@@ -0,0 +1,134 @@
+//! STEP/DIR outputs for [`Stepper`](crate::interrupts::Stepper).
+//!
+//! Direction and enable pins are always native GPIO. Each channel's step pin is driven by the
+//! [`StepBackend`] of its axis: timer-driven pins are raised and lowered by the step interrupt
+//! itself, while RMT pins are handed to an [`RmtStepChannel`] that shapes the pulse in hardware.
+
+use esp_idf_sys::{self as sys, EspError, esp};
+
+use crate::{
+    config::{StepBackend, StepperConfig},
+    devices::StepperChannel,
+    interrupts::StepOutput,
+    peripherals::rmt_step::RmtStepChannel,
+};
+
+/// How one channel's step pin is driven.
+enum StepPin {
+    Timer(i32),
+    Rmt(RmtStepChannel),
+}
+
+/// One configured stepper driver.
+struct Channel {
+    axis: usize,
+    step: StepPin,
+    direction: i32,
+}
+
+/// Step and direction outputs on ESP32 pins, with a per-axis pulse backend.
+pub struct PinStepOutput {
+    channels: Vec<Channel>,
+    config: StepperConfig,
+}
+
+impl PinStepOutput {
+    /// Configures each channel's pins as outputs and drives its enable pin low.
+    ///
+    /// Channels on an RMT axis take RMT channels in the order they are listed. Several channels
+    /// may follow the same axis, as with ganged gantry motors.
+    pub fn new(channels: &[StepperChannel], config: StepperConfig) -> Result<Self, EspError> {
+        let mut rmt_channels = 0;
+        let mut configured = Vec::with_capacity(channels.len());
+        for channel in channels {
+            configure_output(channel.direction)?;
+            if let Some(enable) = channel.enable {
+                configure_output(enable)?;
+            }
+            let step = match config.backends[channel.axis] {
+                StepBackend::Timer => {
+                    configure_output(channel.step)?;
+                    StepPin::Timer(channel.step)
+                }
+                StepBackend::Rmt => {
+                    let rmt_channel = rmt_channels;
+                    rmt_channels += 1;
+                    StepPin::Rmt(RmtStepChannel::new(
+                        rmt_channel,
+                        channel.step,
+                        config.pulse_microseconds,
+                    )?)
+                }
+            };
+            configured.push(Channel {
+                axis: channel.axis,
+                step,
+                direction: channel.direction,
+            });
+        }
+
+        Ok(Self {
+            channels: configured,
+            config,
+        })
+    }
+}
+
+impl StepOutput for PinStepOutput {
+    fn set_directions(&mut self, direction_bits: u8) {
+        // An RMT pulse from the previous interrupt may still be running.
+        let rmt_pulse = if self
+            .channels
+            .iter()
+            .any(|channel| matches!(channel.step, StepPin::Rmt(_)))
+        {
+            self.config.pulse_microseconds
+        } else {
+            0
+        };
+        unsafe { sys::esp_rom_delay_us(rmt_pulse + self.config.direction_hold_microseconds) };
+
+        for channel in &self.channels {
+            let level = u32::from(direction_bits >> channel.axis & 1);
+            unsafe { sys::gpio_set_level(channel.direction, level) };
+        }
+        unsafe { sys::esp_rom_delay_us(self.config.direction_setup_microseconds) };
+    }
+
+    fn step(&mut self, step_bits: u8) {
+        let pins = || {
+            self.channels
+                .iter()
+                .filter(move |channel| step_bits >> channel.axis & 1 == 1)
+                .map(|channel| &channel.step)
+        };
+
+        let mut timer_pulse = false;
+        for pin in pins() {
+            match pin {
+                StepPin::Timer(pin) => {
+                    unsafe { sys::gpio_set_level(*pin, 1) };
+                    timer_pulse = true;
+                }
+                StepPin::Rmt(channel) => channel.pulse(),
+            }
+        }
+        if !timer_pulse {
+            return;
+        }
+
+        unsafe { sys::esp_rom_delay_us(self.config.pulse_microseconds) };
+        for pin in pins() {
+            if let StepPin::Timer(pin) = pin {
+                unsafe { sys::gpio_set_level(*pin, 0) };
+            }
+        }
+    }
+}
+
+/// Resets `pin` to a low push-pull output.
+fn configure_output(pin: i32) -> Result<(), EspError> {
+    esp!(unsafe { sys::gpio_reset_pin(pin) })?;
+    esp!(unsafe { sys::gpio_set_direction(pin, sys::gpio_mode_t_GPIO_MODE_OUTPUT) })?;
+    esp!(unsafe { sys::gpio_set_level(pin, 0) })
+}
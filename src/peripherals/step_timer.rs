@@ -0,0 +1,195 @@
+//! General-purpose timer that runs [`Stepper`] from its alarm interrupt.
+//!
+//! The timer counts at [`STEP_TIMER_FREQUENCY`] and reloads on every alarm. Each alarm calls
+//! [`Stepper::step_interrupt_handler`] with the board's step outputs and endstop inputs and
+//! programs the interval it returns; while the stepper is
+//! idle the alarm keeps firing every [`IDLE_INTERVAL`] ticks so that a newly queued segment starts
+//! without waking the timer from task context.
+//!
+//! Endstop pins can also be attached as edge interrupts that pass the switch state to
+//! [`Stepper::endstop_event`] under the same lock, which kills step generation on a hard limit
+//! without waiting for the next alarm.
+
+use core::{cell::UnsafeCell, ffi::c_void, ptr};
+
+use esp_idf_hal::interrupt::IsrCriticalSection;
+use esp_idf_sys::{self as sys, EspError, esp};
+
+use crate::interrupts::{EndstopInputs, STEP_TIMER_FREQUENCY, StepOutput, Stepper};
+
+/// Alarm interval while no segment is queued, in timer ticks.
+pub const IDLE_INTERVAL: u32 = 1_000;
+
+/// State shared between the alarm interrupt and task context.
+struct Shared<O, E> {
+    lock: IsrCriticalSection,
+    stepper: UnsafeCell<Stepper>,
+    output: UnsafeCell<O>,
+    endstops: UnsafeCell<E>,
+}
+
+// Every access to the cells happens while `lock` is held.
+unsafe impl<O: Send, E: Send> Sync for Shared<O, E> {}
+
+/// A running gptimer that drives one [`Stepper`] through `O`, sampling endstops through `E`.
+pub struct StepTimer<O, E>
+where
+    O: StepOutput + Send + 'static,
+    E: EndstopInputs + Send + 'static,
+{
+    handle: sys::gptimer_handle_t,
+    shared: Box<Shared<O, E>>,
+    /// Pins whose edge interrupt calls [`on_endstop`].
+    endstop_pins: Vec<i32>,
+}
+
+// The handle is only used to tear the timer down, which ESP-IDF allows from any task.
+unsafe impl<O, E> Send for StepTimer<O, E>
+where
+    O: StepOutput + Send + 'static,
+    E: EndstopInputs + Send + 'static,
+{
+}
+
+// Shared references only reach the stepper through `with_stepper`, which holds the lock.
+unsafe impl<O, E> Sync for StepTimer<O, E>
+where
+    O: StepOutput + Send + 'static,
+    E: EndstopInputs + Send + 'static,
+{
+}
+
+impl<O, E> StepTimer<O, E>
+where
+    O: StepOutput + Send + 'static,
+    E: EndstopInputs + Send + 'static,
+{
+    /// Allocates a gptimer, registers the step interrupt, and starts `stepper` idling.
+    pub fn new(stepper: Stepper, output: O, endstops: E) -> Result<Self, EspError> {
+        let shared = Box::new(Shared {
+            lock: IsrCriticalSection::new(),
+            stepper: UnsafeCell::new(stepper),
+            output: UnsafeCell::new(output),
+            endstops: UnsafeCell::new(endstops),
+        });
+
+        let mut handle = ptr::null_mut();
+        let config = sys::gptimer_config_t {
+            clk_src: sys::soc_periph_gptimer_clk_src_t_GPTIMER_CLK_SRC_DEFAULT,
+            direction: sys::gptimer_count_direction_t_GPTIMER_COUNT_UP,
+            resolution_hz: STEP_TIMER_FREQUENCY,
+            ..Default::default()
+        };
+        esp!(unsafe { sys::gptimer_new_timer(&config, &mut handle) })?;
+
+        let callbacks = sys::gptimer_event_callbacks_t {
+            on_alarm: Some(on_alarm::<O, E>),
+        };
+        let context = &*shared as *const Shared<O, E> as *mut c_void;
+        esp!(unsafe { sys::gptimer_register_event_callbacks(handle, &callbacks, context) })?;
+        esp!(unsafe { sys::gptimer_set_alarm_action(handle, &alarm_config(IDLE_INTERVAL)) })?;
+        esp!(unsafe { sys::gptimer_enable(handle) })?;
+        esp!(unsafe { sys::gptimer_start(handle) })?;
+
+        Ok(Self {
+            handle,
+            shared,
+            endstop_pins: Vec::new(),
+        })
+    }
+
+    /// Reports every edge on `pins`, which must already be inputs, to the stepper's hard-limit
+    /// check.
+    ///
+    /// The handler rereads all switches instead of trusting the edge, so spurious interrupts such
+    /// as those GPIO36 and GPIO39 raise while Wi-Fi is active are harmless.
+    pub fn attach_endstop_interrupts(
+        &mut self,
+        pins: impl IntoIterator<Item = i32>,
+    ) -> Result<(), EspError> {
+        // The service is shared by every GPIO interrupt and may already be installed.
+        let installed = unsafe { sys::gpio_install_isr_service(0) };
+        if installed != sys::ESP_ERR_INVALID_STATE as sys::esp_err_t {
+            esp!(installed)?;
+        }
+        let context = &*self.shared as *const Shared<O, E> as *mut c_void;
+        for pin in pins {
+            let edge = sys::gpio_int_type_t_GPIO_INTR_ANYEDGE;
+            esp!(unsafe { sys::gpio_set_intr_type(pin, edge) })?;
+            esp!(unsafe { sys::gpio_isr_handler_add(pin, Some(on_endstop::<O, E>), context) })?;
+            self.endstop_pins.push(pin);
+            esp!(unsafe { sys::gpio_intr_enable(pin) })?;
+        }
+        Ok(())
+    }
+
+    /// Runs `f` with exclusive access to the stepper, holding off the step interrupt.
+    pub fn with_stepper<R>(&self, f: impl FnOnce(&mut Stepper) -> R) -> R {
+        let _guard = self.shared.lock.enter();
+        f(unsafe { &mut *self.shared.stepper.get() })
+    }
+
+    /// Returns `true` while segments are being emitted.
+    pub fn is_busy(&self) -> bool {
+        self.with_stepper(|stepper| stepper.is_busy())
+    }
+}
+
+impl<O, E> Drop for StepTimer<O, E>
+where
+    O: StepOutput + Send + 'static,
+    E: EndstopInputs + Send + 'static,
+{
+    fn drop(&mut self) {
+        for pin in &self.endstop_pins {
+            unsafe { sys::gpio_isr_handler_remove(*pin) };
+        }
+        unsafe {
+            sys::gptimer_stop(self.handle);
+            sys::gptimer_disable(self.handle);
+            sys::gptimer_del_timer(self.handle);
+        }
+    }
+}
+
+/// Returns an auto-reloading alarm that fires after `ticks`.
+fn alarm_config(ticks: u32) -> sys::gptimer_alarm_config_t {
+    let mut config = sys::gptimer_alarm_config_t {
+        alarm_count: u64::from(ticks),
+        reload_count: 0,
+        ..Default::default()
+    };
+    config.flags.set_auto_reload_on_alarm(1);
+    config
+}
+
+/// Alarm interrupt: emits one step event and schedules the next.
+unsafe extern "C" fn on_alarm<O: StepOutput, E: EndstopInputs>(
+    timer: sys::gptimer_handle_t,
+    _event: *const sys::gptimer_alarm_event_data_t,
+    context: *mut c_void,
+) -> bool {
+    let shared = unsafe { &*(context as *const Shared<O, E>) };
+    let interval = {
+        let _guard = shared.lock.enter();
+        let stepper = unsafe { &mut *shared.stepper.get() };
+        let output = unsafe { &mut *shared.output.get() };
+        let endstops = unsafe { &mut *shared.endstops.get() };
+        stepper
+            .step_interrupt_handler(output, endstops)
+            .unwrap_or(IDLE_INTERVAL)
+    };
+    unsafe { sys::gptimer_set_alarm_action(timer, &alarm_config(interval)) };
+
+    // No task was woken, so no context switch is needed on return.
+    false
+}
+
+/// Endstop edge interrupt: hands the current switch state to the stepper's hard-limit check.
+unsafe extern "C" fn on_endstop<O: StepOutput, E: EndstopInputs>(context: *mut c_void) {
+    let shared = unsafe { &*(context as *const Shared<O, E>) };
+    let _guard = shared.lock.enter();
+    let stepper = unsafe { &mut *shared.stepper.get() };
+    let endstops = unsafe { &mut *shared.endstops.get() };
+    stepper.endstop_event(endstops.triggered());
+}
@@ -1,17 +1,146 @@
 //! Fixed-capacity motion planning queue.
 
-use crate::commandbuffer::{Block, Target};
+use core::{fmt, ops::RangeInclusive};
 
-const X_AXIS_STEPS_PER_UNIT: f32 = 10.0;
-const Y_AXIS_STEPS_PER_UNIT: f32 = 10.0;
-const Z_AXIS_STEPS_PER_UNIT: f32 = 10.0;
-const E_AXIS_STEPS_PER_UNIT: f32 = 10.0;
+use crate::{
+    commandbuffer::{Block, MoveKind, Steps, Target},
+    config::{AXES, AXIS_NAMES, MachineConfig, SoftLimits},
+};
+/// Speed in millimetres per second allowed through a full reversal of direction.
+const MINIMUM_JUNCTION_SPEED: f32 = 0.0;
+
+/// A move the planner did not queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlannerError {
+    /// Every slot is in use.
+    QueueFull,
+    /// The move would leave the travel of the axis, and soft limits reject it.
+    SoftLimit(usize),
+    /// The move would drive an axis that has no stepper output, so its steps would go nowhere.
+    NoStepper(usize),
+}
+
+impl fmt::Display for PlannerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QueueFull => f.write_str("motion queue full"),
+            Self::SoftLimit(axis) => write!(f, "move exceeds {} soft limit", AXIS_NAMES[*axis]),
+            Self::NoStepper(axis) => {
+                write!(f, "no stepper output drives the {} axis", AXIS_NAMES[*axis])
+            }
+        }
+    }
+}
+
+impl std::error::Error for PlannerError {}
+
+/// A change to one of the [`Overrides`], as sent by Grbl's real-time override commands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverrideCommand {
+    /// Adds the signed number of percentage points to the feed override, or resets it to 100%.
+    Feed(Option<i16>),
+    /// Sets the rapid override to the given percentage.
+    Rapid(u16),
+    /// Adds the signed number of percentage points to the spindle override, or resets it to
+    /// 100%.
+    Spindle(Option<i16>),
+}
+
+/// Grbl-style overrides in percent of the programmed speeds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overrides {
+    /// Scales feed moves.
+    pub feed: u16,
+    /// Scales `G0` traverses.
+    pub rapid: u16,
+    /// Scales the programmed spindle speed.
+    pub spindle: u16,
+}
+
+impl Overrides {
+    pub const FEED_RANGE: RangeInclusive<u16> = 10..=200;
+    pub const RAPID_LEVELS: [u16; 3] = [25, 50, 100];
+    pub const SPINDLE_RANGE: RangeInclusive<u16> = 10..=200;
+
+    /// Applies `command`, keeping each override within its allowed values.
+    ///
+    /// A rapid percentage between two levels selects the lower one.
+    pub fn apply(&mut self, command: OverrideCommand) {
+        fn adjust(value: u16, change: Option<i16>, range: RangeInclusive<u16>) -> u16 {
+            let Some(change) = change else {
+                return 100;
+            };
+            let value = (i32::from(value) + i32::from(change))
+                .clamp(i32::from(*range.start()), i32::from(*range.end()));
+            value as u16
+        }
+
+        match command {
+            OverrideCommand::Feed(change) => {
+                self.feed = adjust(self.feed, change, Self::FEED_RANGE)
+            }
+            OverrideCommand::Rapid(percent) => {
+                self.rapid = Self::RAPID_LEVELS
+                    .into_iter()
+                    .rev()
+                    .find(|level| *level <= percent)
+                    .unwrap_or(Self::RAPID_LEVELS[0]);
+            }
+            OverrideCommand::Spindle(change) => {
+                self.spindle = adjust(self.spindle, change, Self::SPINDLE_RANGE)
+            }
+        }
+    }
+
+    /// Returns the percentage that scales moves of `kind`.
+    pub fn speed_percent(&self, kind: MoveKind) -> u16 {
+        match kind {
+            MoveKind::Feed => self.feed,
+            MoveKind::Rapid => self.rapid,
+            MoveKind::System => 100,
+        }
+    }
+}
+
+impl Default for Overrides {
+    fn default() -> Self {
+        Self {
+            feed: 100,
+            rapid: 100,
+            spindle: 100,
+        }
+    }
+}
+
+impl fmt::Display for Overrides {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "feed {}%, rapid {}%, spindle {}%",
+            self.feed, self.rapid, self.spindle
+        )
+    }
+}
 
 /// Buffers moves and derives their trapezoidal step-rate profiles.
 pub struct Planner {
     block_buffer: Vec<Block>,
     head: usize,
     tail: usize,
+    /// First block whose entry speed may still change. Earlier blocks are already optimal.
+    planned: usize,
+    /// `true` once the segment preparer has started consuming the block at `tail`.
+    tail_in_progress: bool,
+    /// Position at the end of the most recently queued block, in steps from the origin.
+    position: Target,
+    /// Direction of the most recently queued block, used to find the next junction angle.
+    previous_unit_vector: [f32; AXES],
+    /// Nominal speed of the most recently queued block in millimetres per second.
+    previous_nominal_speed: f32,
+    config: MachineConfig,
+    overrides: Overrides,
+    /// Axes without a stepper output, as a bit mask; moves that drive them are refused.
+    undriven_axes: u8,
 }
 
 impl Planner {
@@ -19,49 +148,668 @@ impl Planner {
     ///
     /// # Panics
     ///
-    /// Panics if `buffer_size` is less than two.
-    pub fn new(buffer_size: usize) -> Self {
+    /// Panics if `buffer_size` is less than two or `config` has a non-positive arc tolerance.
+    pub fn new(buffer_size: usize, config: MachineConfig) -> Self {
         assert!(
             buffer_size >= 2,
             "planner buffer must contain at least two slots"
         );
+        assert_valid_config(&config);
         Self {
             block_buffer: vec![Block::default(); buffer_size],
             head: 0,
             tail: 0,
+            planned: 0,
+            tail_in_progress: false,
+            position: Target::default(),
+            previous_unit_vector: [0.0; AXES],
+            previous_nominal_speed: 0.0,
+            config,
+            overrides: Overrides::default(),
+            undriven_axes: 0,
         }
     }
 
-    /// Adds a linear move, returning `false` when the queue is full.
-    pub fn buffer_line(&mut self, x: f32, y: f32, z: f32, e: f32, feed_rate: f32) -> bool {
+    /// Returns the machine configuration used for new blocks.
+    pub fn config(&self) -> &MachineConfig {
+        &self.config
+    }
+
+    /// Replaces the machine configuration used for moves buffered afterwards.
+    ///
+    /// The current position is kept in millimetres, so changing steps per millimetre does not
+    /// move the machine's notion of where it is.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config` has a non-positive arc tolerance.
+    pub fn set_config(&mut self, config: MachineConfig) {
+        assert_valid_config(&config);
+        let [x, y, z, e] = self.position();
+        self.config = config;
+        self.set_position(x, y, z, e);
+    }
+
+    /// Returns the axes that have no stepper output, as a bit mask.
+    pub fn undriven_axes(&self) -> u8 {
+        self.undriven_axes
+    }
+
+    /// Marks the axes in the `axes` bit mask as having no stepper output, so that moves driving
+    /// them are refused with [`PlannerError::NoStepper`] instead of reporting motion that never
+    /// reaches a motor.
+    pub fn set_undriven_axes(&mut self, axes: u8) {
+        self.undriven_axes = axes;
+    }
+
+    /// Returns the overrides applied to queued and future moves.
+    pub fn overrides(&self) -> Overrides {
+        self.overrides
+    }
+
+    /// Replaces the overrides and replans every queued block, including the one executing, so
+    /// that the change reaches the segment preparer on its next segment.
+    pub fn set_overrides(&mut self, overrides: Overrides) {
+        self.overrides = overrides;
+        let mut previous_nominal_speed = None;
+        let mut block_index = self.tail;
+        while block_index != self.head {
+            let block = &mut self.block_buffer[block_index];
+            block.set_speed_percent(overrides.speed_percent(block.kind));
+            // The move before the current block has already run, so only later corners are
+            // limited by the speed of the move leading into them.
+            block.max_entry_speed_sqr = block
+                .max_junction_speed_sqr
+                .min(block.nominal_speed.powi(2))
+                .min(previous_nominal_speed.map_or(f32::INFINITY, |speed: f32| speed.powi(2)));
+            previous_nominal_speed = Some(block.nominal_speed);
+            block_index = self.next_index(block_index);
+        }
+        if let Some(speed) = previous_nominal_speed {
+            self.previous_nominal_speed = speed;
+        }
+        self.replan_from_current_block();
+    }
+
+    /// Adds a linear move to the absolute position `x`, `y`, `z`, `e` in millimetres.
+    ///
+    /// The block holds the signed step delta from the end of the previously queued move. Moves
+    /// that round to zero steps on every axis are discarded. `feed_rate` is reduced as needed so
+    /// that no axis exceeds its maximum rate; pass `f32::INFINITY` to move as fast as the axes
+    /// allow. A target outside the configured travel is refused or clipped as
+    /// [`MachineConfig::soft_limits`] requires, and a full queue refuses every move. Returns the
+    /// end point of the queued move.
+    pub fn buffer_line(
+        &mut self,
+        x: f32,
+        y: f32,
+        z: f32,
+        e: f32,
+        feed_rate: f32,
+    ) -> Result<[f32; AXES], PlannerError> {
+        let target = self.limit_move(&self.position(), &[x, y, z, e])?;
+        let [x, y, z, e] = target;
+        let kind = if feed_rate == f32::INFINITY {
+            MoveKind::Rapid
+        } else {
+            MoveKind::Feed
+        };
+        self.queue_block(x, y, z, e, feed_rate, kind)?;
+        Ok(target)
+    }
+
+    /// Adds a jog move like [`Self::buffer_line`]. Jogs respect soft limits but ignore overrides.
+    pub fn buffer_jog(
+        &mut self,
+        x: f32,
+        y: f32,
+        z: f32,
+        e: f32,
+        feed_rate: f32,
+    ) -> Result<[f32; AXES], PlannerError> {
+        let target = self.limit_move(&self.position(), &[x, y, z, e])?;
+        let [x, y, z, e] = target;
+        self.queue_block(x, y, z, e, feed_rate, MoveKind::System)?;
+        Ok(target)
+    }
+
+    /// Adds a linear move like [`Self::buffer_line`] but ignores soft limits and overrides, for
+    /// homing moves that search past the configured travel.
+    pub fn buffer_line_without_limits(
+        &mut self,
+        x: f32,
+        y: f32,
+        z: f32,
+        e: f32,
+        feed_rate: f32,
+    ) -> Result<(), PlannerError> {
+        self.check_driven(&self.position(), &[x, y, z, e])?;
+        self.queue_block(x, y, z, e, feed_rate, MoveKind::System)
+    }
+
+    /// Appends a move of `kind` to the queue without checking soft limits.
+    fn queue_block(
+        &mut self,
+        x: f32,
+        y: f32,
+        z: f32,
+        e: f32,
+        feed_rate: f32,
+        kind: MoveKind,
+    ) -> Result<(), PlannerError> {
         let next_head = (self.head + 1) % self.block_buffer.len();
         if next_head == self.tail {
-            return false;
+            return Err(PlannerError::QueueFull);
+        }
+
+        let target = self.steps_from_millimetres(x, y, z, e);
+        let steps = Steps::between(&self.position, &target);
+        if steps.step_event_count == 0 {
+            return Ok(());
         }
 
-        let target = Target {
-            x: (x * X_AXIS_STEPS_PER_UNIT) as i32,
-            y: (y * Y_AXIS_STEPS_PER_UNIT) as i32,
-            z: (z * Z_AXIS_STEPS_PER_UNIT) as i32,
-            e: (e * E_AXIS_STEPS_PER_UNIT) as i32,
+        let steps_per_mm = self.config.steps_per_mm();
+        let delta = [
+            steps.x as f32 / steps_per_mm[0],
+            steps.y as f32 / steps_per_mm[1],
+            steps.z as f32 / steps_per_mm[2],
+            steps.e as f32 / steps_per_mm[3],
+        ];
+        let mut block = Block::new(steps, delta, feed_rate, kind, &self.config);
+        block.target = target;
+        block.set_speed_percent(self.overrides.speed_percent(kind));
+
+        // A move queued behind nothing starts from rest; otherwise the corner between the two
+        // moves limits how fast the machine may pass through it.
+        block.max_junction_speed_sqr = if self.head == self.tail {
+            0.0
+        } else {
+            self.junction_speed_sqr(&block)
         };
+        block.max_entry_speed_sqr = block
+            .max_junction_speed_sqr
+            .min(self.previous_nominal_speed.powi(2))
+            .min(block.nominal_speed.powi(2));
 
-        let block = Block::new(target, feed_rate);
+        self.previous_unit_vector = block.unit_vector;
+        self.previous_nominal_speed = block.nominal_speed;
         self.block_buffer[self.head] = block;
         self.head = next_head;
-        true
+        self.position = target;
+        Ok(())
     }
 
-    /// Recomputes profiles for all queued blocks in execution order.
-    pub fn recalculate_trapezoids(&mut self) {
+    /// Returns where a straight move from `from` to `to`, both in millimetres, ends under
+    /// [`MachineConfig::soft_limits`]. A clipped move stops where its path first reaches the edge
+    /// of the travel, so it keeps its direction. A move that drives an undriven axis is refused.
+    pub fn limit_move(
+        &self,
+        from: &[f32; AXES],
+        to: &[f32; AXES],
+    ) -> Result<[f32; AXES], PlannerError> {
+        self.check_driven(from, to)?;
+        match self.config.soft_limits {
+            SoftLimits::Disabled => Ok(*to),
+            SoftLimits::Reject => self.check_soft_limits(to).map(|()| *to),
+            SoftLimits::Clip => {
+                let fraction = (0..AXES)
+                    .map(|axis| {
+                        let (from, to) = (from[axis], to[axis]);
+                        let travel = &self.config.axes[axis];
+                        let edge = match to {
+                            _ if to == from => return 1.0,
+                            to if to > travel.max_travel => travel.max_travel,
+                            to if to < travel.min_travel => travel.min_travel,
+                            _ => return 1.0,
+                        };
+                        ((edge - from) / (to - from)).clamp(0.0, 1.0)
+                    })
+                    .fold(1.0, f32::min);
+                Ok([0, 1, 2, 3].map(|axis| from[axis] + (to[axis] - from[axis]) * fraction))
+            }
+        }
+    }
+
+    /// Fails with the first axis that moves from `from` to `to` but has no stepper output.
+    fn check_driven(&self, from: &[f32; AXES], to: &[f32; AXES]) -> Result<(), PlannerError> {
+        match (0..AXES).find(|axis| self.undriven_axes >> axis & 1 == 1 && from[*axis] != to[*axis])
+        {
+            Some(axis) => Err(PlannerError::NoStepper(axis)),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails with the first axis whose travel does not contain `target` if soft limits reject
+    /// such moves.
+    fn check_soft_limits(&self, target: &[f32; AXES]) -> Result<(), PlannerError> {
+        if self.config.soft_limits != SoftLimits::Reject {
+            return Ok(());
+        }
+        match target
+            .iter()
+            .zip(&self.config.axes)
+            .position(|(value, axis)| *value < axis.min_travel || *value > axis.max_travel)
+        {
+            Some(axis) => Err(PlannerError::SoftLimit(axis)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the highest speed squared at which the junction into `block` can be traversed.
+    ///
+    /// This is Grbl's junction-deviation model: the corner is treated as a circular arc that
+    /// deviates from the sharp corner by the configured junction deviation, and the speed is the
+    /// one at which centripetal acceleration around that arc reaches the axis limits along the
+    /// direction of the velocity change.
+    fn junction_speed_sqr(&self, block: &Block) -> f32 {
+        let cos_theta = -self
+            .previous_unit_vector
+            .iter()
+            .zip(&block.unit_vector)
+            .map(|(previous, current)| previous * current)
+            .sum::<f32>();
+
+        if cos_theta > 0.999_999 {
+            // The path reverses, so the machine has to stop.
+            MINIMUM_JUNCTION_SPEED.powi(2)
+        } else if cos_theta < -0.999_999 {
+            // The path is straight and imposes no junction limit.
+            f32::INFINITY
+        } else {
+            let mut junction_vector = [0.0; AXES];
+            for (axis, component) in junction_vector.iter_mut().enumerate() {
+                *component = block.unit_vector[axis] - self.previous_unit_vector[axis];
+            }
+            let length = junction_vector.iter().map(|c| c * c).sum::<f32>().sqrt();
+            let junction_acceleration = self
+                .config
+                .max_acceleration_along(&junction_vector.map(|component| component / length));
+
+            let sin_theta_d2 = (0.5 * (1.0 - cos_theta)).sqrt();
+            (junction_acceleration * self.config.junction_deviation * sin_theta_d2
+                / (1.0 - sin_theta_d2))
+                .max(MINIMUM_JUNCTION_SPEED.powi(2))
+        }
+    }
+
+    /// Returns `true` when no blocks are queued.
+    pub fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    /// Returns the oldest queued block, the next one to execute.
+    pub fn current_block(&self) -> Option<&Block> {
+        (!self.is_empty()).then(|| &self.block_buffer[self.tail])
+    }
+
+    /// Returns the queued blocks from the current one to the newest.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> + '_ {
         let mut block_index = self.tail;
-        let mut previous_exit_rate = 0.0;
+        core::iter::from_fn(move || {
+            if block_index == self.head {
+                return None;
+            }
+            let block = &self.block_buffer[block_index];
+            block_index = self.next_index(block_index);
+            Some(block)
+        })
+    }
 
-        while block_index != self.head {
+    /// Returns the number of moves that can still be queued.
+    pub fn free_slots(&self) -> usize {
+        self.block_buffer.len() - 1 - self.blocks().count()
+    }
+
+    /// Returns `true` once the segment preparer has started on the current block, whose steps are
+    /// then being executed.
+    pub fn is_current_block_started(&self) -> bool {
+        !self.is_empty() && self.tail_in_progress
+    }
+
+    /// Returns the planned exit speed squared of the current block: the next block's entry speed,
+    /// or zero if it is the last queued block.
+    pub fn exit_speed_sqr(&self) -> f32 {
+        let next_index = self.next_index(self.tail);
+        if self.is_empty() || next_index == self.head {
+            0.0
+        } else {
+            self.block_buffer[next_index].entry_speed_sqr
+        }
+    }
+
+    /// Records the execution state of the partially consumed current block.
+    ///
+    /// The current block becomes the fixed starting point of look-ahead: its entry speed is
+    /// replaced by the speed already reached and its length by the distance still to run, so that
+    /// later planning only asks for exit speeds that are still reachable.
+    pub fn update_current_block(&mut self, speed_sqr: f32, mm_remaining: f32) {
+        if self.is_empty() {
+            return;
+        }
+        let block = &mut self.block_buffer[self.tail];
+        block.entry_speed_sqr = speed_sqr;
+        block.millimetres = mm_remaining;
+        self.tail_in_progress = true;
+    }
+
+    /// Replans the whole queue from the current block's recorded entry speed.
+    ///
+    /// Used after a feed hold has slowed the current block below the speed the look-ahead
+    /// expected; later blocks that were already optimal may no longer be reachable.
+    pub fn replan_from_current_block(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        self.planned = self.tail;
+        self.recalculate_trapezoids();
+    }
+
+    /// Removes the current block once it has been fully handed to the step generator.
+    pub fn discard_current_block(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        if self.planned == self.tail {
+            self.planned = self.next_index(self.tail);
+        }
+        self.tail = self.next_index(self.tail);
+        self.tail_in_progress = false;
+    }
+
+    /// Returns the position after all queued moves in X, Y, Z, E order, in millimetres.
+    pub fn position(&self) -> [f32; AXES] {
+        let steps_per_mm = self.config.steps_per_mm();
+        [
+            self.position.x as f32 / steps_per_mm[0],
+            self.position.y as f32 / steps_per_mm[1],
+            self.position.z as f32 / steps_per_mm[2],
+            self.position.e as f32 / steps_per_mm[3],
+        ]
+    }
+
+    /// Returns the position after all queued moves in steps from the origin.
+    pub fn position_steps(&self) -> Target {
+        self.position
+    }
+
+    /// Redefines the position after all queued moves without moving, as homing does once it finds
+    /// the machine origin.
+    ///
+    /// Queued blocks are unaffected; only moves buffered afterwards are measured from the new
+    /// position.
+    pub fn set_position(&mut self, x: f32, y: f32, z: f32, e: f32) {
+        self.position = self.steps_from_millimetres(x, y, z, e);
+    }
+
+    /// Discards every queued block and redefines the position as `position` in steps.
+    ///
+    /// Used when step generation stopped partway through the queue, so that later moves start
+    /// from where the machine actually is.
+    pub fn clear(&mut self, position: Target) {
+        self.head = 0;
+        self.tail = 0;
+        self.planned = 0;
+        self.tail_in_progress = false;
+        self.position = position;
+        self.previous_unit_vector = [0.0; AXES];
+        self.previous_nominal_speed = 0.0;
+    }
+
+    /// Converts a position in millimetres to the nearest whole step on each axis.
+    fn steps_from_millimetres(&self, x: f32, y: f32, z: f32, e: f32) -> Target {
+        let steps_per_mm = self.config.steps_per_mm();
+        Target {
+            x: (x * steps_per_mm[0]).round() as i32,
+            y: (y * steps_per_mm[1]).round() as i32,
+            z: (z * steps_per_mm[2]).round() as i32,
+            e: (e * steps_per_mm[3]).round() as i32,
+        }
+    }
+
+    /// Plans entry speeds across the queue and recomputes the profiles that changed.
+    ///
+    /// A reverse pass from the newest block raises each entry speed as far as its junction limit
+    /// and the distance needed to stop by the end of the queue allow. A forward pass then lowers
+    /// entry speeds that cannot be reached by accelerating from the block before. Blocks whose
+    /// entry speed can no longer change advance the planned pointer and are skipped by later
+    /// passes.
+    pub fn recalculate_trapezoids(&mut self) {
+        if self.head == self.tail {
+            return;
+        }
+        let first_changed = self.planned;
+
+        // Reverse pass: the newest block must be able to stop before the queue runs out.
+        let mut block_index = self.previous_index(self.head);
+        let mut next_entry_speed_sqr = 0.0;
+        while block_index != self.planned {
             let block = &mut self.block_buffer[block_index];
-            block.calculate_trapezoid(previous_exit_rate);
-            previous_exit_rate = block.exit_rate;
-            block_index = (block_index + 1) % self.block_buffer.len();
+            block.entry_speed_sqr = block
+                .max_entry_speed_sqr
+                .min(next_entry_speed_sqr + 2.0 * block.deceleration * block.millimetres);
+            next_entry_speed_sqr = block.entry_speed_sqr;
+            block_index = self.previous_index(block_index);
+        }
+
+        // Forward pass: entry speeds may not exceed what the previous block can accelerate to.
+        let mut block_index = self.planned;
+        let mut next_index = self.next_index(block_index);
+        while next_index != self.head {
+            let current = &self.block_buffer[block_index];
+            let reachable_speed_sqr =
+                current.entry_speed_sqr + 2.0 * current.acceleration * current.millimetres;
+            let next = &mut self.block_buffer[next_index];
+            if reachable_speed_sqr < next.entry_speed_sqr {
+                // Acceleration-limited entries cannot improve when more blocks are queued.
+                next.entry_speed_sqr = reachable_speed_sqr;
+                self.planned = next_index;
+            }
+            if next.entry_speed_sqr >= next.max_entry_speed_sqr {
+                self.planned = next_index;
+            }
+            block_index = next_index;
+            next_index = self.next_index(next_index);
         }
+
+        // The block being executed keeps its profile; only its exit speed matters downstream.
+        let mut block_index = first_changed;
+        if self.tail_in_progress && block_index == self.tail {
+            block_index = self.next_index(block_index);
+        }
+        while block_index != self.head {
+            let next_index = self.next_index(block_index);
+            let exit_speed_sqr = if next_index == self.head {
+                0.0
+            } else {
+                self.block_buffer[next_index].entry_speed_sqr
+            };
+            self.block_buffer[block_index].calculate_trapezoid(exit_speed_sqr);
+            block_index = next_index;
+        }
+    }
+
+    fn next_index(&self, block_index: usize) -> usize {
+        (block_index + 1) % self.block_buffer.len()
+    }
+
+    fn previous_index(&self, block_index: usize) -> usize {
+        (block_index + self.block_buffer.len() - 1) % self.block_buffer.len()
+    }
+}
+
+/// Checks the configuration values that would otherwise surface as hangs or NaN coordinates deep
+/// inside motion planning.
+fn assert_valid_config(config: &MachineConfig) {
+    assert!(
+        config.arc_tolerance > 0.0,
+        "arc tolerance must be positive, not {}",
+        config.arc_tolerance
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "arc tolerance must be positive")]
+    fn zero_arc_tolerance_is_refused() {
+        let config = MachineConfig {
+            arc_tolerance: 0.0,
+            ..MachineConfig::default()
+        };
+        Planner::new(4, config);
+    }
+
+    /// Queues each of `targets` in the XY plane at `feed_rate` and plans the queue.
+    fn plan(targets: &[(f32, f32)], feed_rate: f32) -> Planner {
+        let mut planner = Planner::new(8, MachineConfig::default());
+        for &(x, y) in targets {
+            planner.buffer_line(x, y, 0.0, 0.0, feed_rate).unwrap();
+        }
+        planner.recalculate_trapezoids();
+        planner
+    }
+
+    #[test]
+    fn collinear_junction_is_limited_by_nominal_speed_only() {
+        let planner = plan(&[(10.0, 0.0), (20.0, 0.0)], 600.0);
+        let second = planner.blocks().nth(1).unwrap();
+        assert_eq!(second.max_junction_speed_sqr, f32::INFINITY);
+        assert_eq!(second.max_entry_speed_sqr, 10.0_f32.powi(2));
+    }
+
+    #[test]
+    fn reversal_stops_at_the_junction() {
+        let planner = plan(&[(10.0, 0.0), (0.0, 0.0)], 600.0);
+        let second = planner.blocks().nth(1).unwrap();
+        assert_eq!(second.max_junction_speed_sqr, 0.0);
+        assert_eq!(second.entry_speed_sqr, 0.0);
+    }
+
+    #[test]
+    fn right_angle_follows_junction_deviation() {
+        let planner = plan(&[(10.0, 0.0), (10.0, 10.0)], 6_000.0);
+        let config = MachineConfig::default();
+        // The velocity changes along (-1, 1)/√2, where both axes share the acceleration.
+        let acceleration = config.axes[0].max_acceleration * 2.0_f32.sqrt();
+        let sin_half_angle = 0.5_f32.sqrt();
+        let expected =
+            acceleration * config.junction_deviation * sin_half_angle / (1.0 - sin_half_angle);
+        let second = planner.blocks().nth(1).unwrap();
+        assert!((second.max_junction_speed_sqr - expected).abs() < 1e-3 * expected);
+        assert_eq!(second.entry_speed_sqr, second.max_junction_speed_sqr);
+    }
+
+    #[test]
+    fn backward_pass_leaves_room_to_stop() {
+        // Short moves at a high feed rate, so stopping distance limits every entry speed.
+        let targets = [(0.2, 0.0), (0.4, 0.0), (0.6, 0.0), (0.8, 0.0)];
+        let planner = plan(&targets, 6_000.0);
+        let blocks = planner.blocks().collect::<Vec<_>>();
+        let mut exit_speed_sqr = 0.0;
+        for block in blocks.iter().rev() {
+            let stoppable = exit_speed_sqr + 2.0 * block.deceleration * block.millimetres;
+            assert!(block.entry_speed_sqr <= stoppable * (1.0 + 1e-6));
+            exit_speed_sqr = block.entry_speed_sqr;
+        }
+        let last = blocks.last().unwrap();
+        assert_eq!(
+            last.entry_speed_sqr,
+            2.0 * last.deceleration * last.millimetres
+        );
+        assert!(last.decel_after < last.steps.step_event_count);
+        assert_eq!(last.exit_rate, 0.0);
+    }
+
+    #[test]
+    fn planned_blocks_are_not_recalculated() {
+        let mut planner = plan(&[(1.0, 0.0), (2.0, 0.0), (3.0, 0.0)], 1_500.0);
+        // Each block reaches its nominal speed, so their entries are final.
+        assert_eq!(planner.planned, 2);
+        for block in &mut planner.block_buffer[..2] {
+            block.nominal_rate = -1.0;
+        }
+        planner.buffer_line(4.0, 0.0, 0.0, 0.0, 1_500.0).unwrap();
+        planner.recalculate_trapezoids();
+        let rates = planner
+            .blocks()
+            .map(|block| block.nominal_rate)
+            .collect::<Vec<_>>();
+        assert_eq!(rates[..2], [-1.0, -1.0]);
+        assert!(rates[2..].iter().all(|rate| *rate > 0.0));
+    }
+
+    fn planner(soft_limits: SoftLimits) -> Planner {
+        let config = MachineConfig {
+            soft_limits,
+            ..MachineConfig::default()
+        };
+        Planner::new(8, config)
+    }
+
+    #[test]
+    fn soft_limits_refuse_moves_outside_the_travel() {
+        let mut planner = planner(SoftLimits::Reject);
+        assert_eq!(
+            planner.buffer_line(10.0, 201.0, 0.0, 0.0, 100.0),
+            Err(PlannerError::SoftLimit(1))
+        );
+        assert!(planner.is_empty());
+        assert_eq!(
+            planner.buffer_line(10.0, 200.0, 0.0, -500.0, 100.0),
+            Ok([10.0, 200.0, 0.0, -500.0])
+        );
+    }
+
+    #[test]
+    fn clipped_move_keeps_its_direction() {
+        let mut planner = planner(SoftLimits::Clip);
+        planner.buffer_line(100.0, 100.0, 0.0, 0.0, 100.0).unwrap();
+        // Heading for (300, 200), the move reaches X200 halfway and stops there.
+        let end = planner.buffer_line(300.0, 200.0, 0.0, 0.0, 100.0).unwrap();
+        assert_eq!(end, [200.0, 150.0, 0.0, 0.0]);
+        assert_eq!(planner.position(), end);
+
+        // At the edge, a move further out goes nowhere while one along the edge runs in full.
+        assert_eq!(
+            planner.buffer_line(250.0, 100.0, 0.0, 0.0, 100.0),
+            Ok([200.0, 150.0, 0.0, 0.0])
+        );
+        assert_eq!(
+            planner.buffer_line(200.0, 100.0, 0.0, 0.0, 100.0),
+            Ok([200.0, 100.0, 0.0, 0.0])
+        );
+    }
+
+    #[test]
+    fn moves_on_undriven_axes_are_refused() {
+        let mut planner = planner(SoftLimits::Reject);
+        planner.set_undriven_axes(1 << 3);
+        assert_eq!(
+            planner.buffer_line(10.0, 0.0, 0.0, 5.0, 100.0),
+            Err(PlannerError::NoStepper(3))
+        );
+        assert_eq!(
+            planner.buffer_jog(0.0, 0.0, 0.0, 5.0, 100.0),
+            Err(PlannerError::NoStepper(3))
+        );
+        assert_eq!(
+            planner.buffer_line_without_limits(0.0, 0.0, 0.0, 5.0, 100.0),
+            Err(PlannerError::NoStepper(3))
+        );
+        assert!(planner.is_empty());
+        assert_eq!(
+            planner.buffer_line(10.0, 20.0, 1.0, 0.0, 100.0),
+            Ok([10.0, 20.0, 1.0, 0.0])
+        );
+    }
+
+    #[test]
+    fn disabled_soft_limits_allow_any_target() {
+        let mut planner = planner(SoftLimits::Disabled);
+        let end = planner.buffer_line(-5.0, 250.0, 3.0, 0.0, 100.0).unwrap();
+        assert_eq!(end, [-5.0, 250.0, 3.0, 0.0]);
+        assert_eq!(planner.position(), end);
     }
 }
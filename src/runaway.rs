@@ -0,0 +1,272 @@
+//! Thermal runaway protection.
+//!
+//! A [`RunawayMonitor`] watches one heater's readings against its target and reports a
+//! [`ThermalFault`] when the heater stops behaving like a heater: the temperature does not rise
+//! while heating, as with a loose sensor or a failed heater cartridge; it falls away from the
+//! target while holding, as with a sensor that slipped out of its block; it exceeds a hard
+//! cutoff, as with a shorted MOSFET; or the sensor fails while the heater is on.
+
+use core::fmt;
+use std::time::Duration;
+
+use crate::temperature::SensorFault;
+
+/// Limits a heater must keep to, modelled on Marlin's watch and thermal protection settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RunawayConfig {
+    /// While heating towards the target, the temperature must rise by `watch_rise` °C within
+    /// each `watch_period`.
+    pub watch_period: Duration,
+    pub watch_rise: f32,
+    /// Once the target is reached, the temperature may stay more than `hold_hysteresis` °C below
+    /// it for at most `hold_period`.
+    pub hold_period: Duration,
+    pub hold_hysteresis: f32,
+    /// Temperature in °C above which the heater is shut down whatever its target.
+    pub cutoff: f32,
+}
+
+impl RunawayConfig {
+    /// Limits for a cartridge hotend, which heats within seconds.
+    pub const HOTEND: Self = Self {
+        watch_period: Duration::from_secs(20),
+        watch_rise: 2.0,
+        hold_period: Duration::from_secs(40),
+        hold_hysteresis: 4.0,
+        cutoff: 290.0,
+    };
+
+    /// Limits for a heated bed, whose thermal mass slows both heating and recovery.
+    pub const BED: Self = Self {
+        watch_period: Duration::from_secs(60),
+        watch_rise: 2.0,
+        hold_period: Duration::from_secs(60),
+        hold_hysteresis: 5.0,
+        cutoff: 130.0,
+    };
+}
+
+/// Why a heater was shut down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThermalFault {
+    /// The temperature did not rise while heating towards the target.
+    NotHeating,
+    /// The temperature fell away from the target after reaching it.
+    Falling,
+    /// The temperature exceeded the heater's cutoff.
+    MaxTemperature,
+    /// The sensor failed while the heater was on.
+    Sensor(SensorFault),
+}
+
+impl fmt::Display for ThermalFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotHeating => f.write_str("temperature did not rise while heating"),
+            Self::Falling => f.write_str("temperature fell away from the target"),
+            Self::MaxTemperature => f.write_str("temperature exceeded the cutoff"),
+            Self::Sensor(fault) => write!(f, "sensor {fault}"),
+        }
+    }
+}
+
+impl std::error::Error for ThermalFault {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Sensor(fault) => Some(fault),
+            Self::NotHeating | Self::Falling | Self::MaxTemperature => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Off,
+    /// Approaching the target. `reference` is the temperature the next rise is measured from,
+    /// and `elapsed` the seconds since it was taken.
+    Heating {
+        reference: Option<f32>,
+        elapsed: f32,
+    },
+    /// Holding the target. `below` is how many seconds the temperature has been under the
+    /// hysteresis band.
+    Holding {
+        below: f32,
+    },
+}
+
+/// Runaway detection for one heater.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RunawayMonitor {
+    config: RunawayConfig,
+    phase: Phase,
+    /// Target the phase was chosen for.
+    target: f32,
+}
+
+impl RunawayMonitor {
+    pub fn new(config: RunawayConfig) -> Self {
+        Self {
+            config,
+            phase: Phase::Off,
+            target: 0.0,
+        }
+    }
+
+    /// Checks a reading taken `interval` after the previous one against `target` °C, where zero
+    /// means the heater is off.
+    ///
+    /// A new target restarts the watch, so that each change of target gets the full heating
+    /// period. Missing readings, as after a failed ADC read, are skipped.
+    pub fn check(
+        &mut self,
+        target: f32,
+        reading: Option<Result<f32, SensorFault>>,
+        interval: Duration,
+    ) -> Result<(), ThermalFault> {
+        self.check_phase(target, reading, interval, true)
+    }
+
+    /// Checks a reading like [`check`](Self::check), but without the falling check once the
+    /// target is reached, for a tuning run whose relay swings the temperature around the target
+    /// on purpose. The heating, cutoff, and sensor checks still apply.
+    pub fn check_tuning(
+        &mut self,
+        target: f32,
+        reading: Option<Result<f32, SensorFault>>,
+        interval: Duration,
+    ) -> Result<(), ThermalFault> {
+        self.check_phase(target, reading, interval, false)
+    }
+
+    fn check_phase(
+        &mut self,
+        target: f32,
+        reading: Option<Result<f32, SensorFault>>,
+        interval: Duration,
+        holding: bool,
+    ) -> Result<(), ThermalFault> {
+        if target != self.target {
+            self.target = target;
+            self.phase = match target > 0.0 {
+                true => Phase::Heating {
+                    reference: None,
+                    elapsed: 0.0,
+                },
+                false => Phase::Off,
+            };
+        }
+        let celsius = match reading {
+            Some(Ok(celsius)) => celsius,
+            Some(Err(fault)) if target > 0.0 => return Err(ThermalFault::Sensor(fault)),
+            Some(Err(_)) | None => return Ok(()),
+        };
+        if celsius > self.config.cutoff {
+            return Err(ThermalFault::MaxTemperature);
+        }
+
+        let seconds = interval.as_secs_f32();
+        let floor = target - self.config.hold_hysteresis;
+        match &mut self.phase {
+            Phase::Off => {}
+            Phase::Heating { .. } if celsius >= floor => {
+                self.phase = Phase::Holding { below: 0.0 };
+            }
+            Phase::Heating { reference, elapsed } => match *reference {
+                Some(from) if celsius < from + self.config.watch_rise => {
+                    *elapsed += seconds;
+                    if *elapsed > self.config.watch_period.as_secs_f32() {
+                        return Err(ThermalFault::NotHeating);
+                    }
+                }
+                _ => {
+                    *reference = Some(celsius);
+                    *elapsed = 0.0;
+                }
+            },
+            Phase::Holding { below } if holding && celsius < floor => {
+                *below += seconds;
+                if *below > self.config.hold_period.as_secs_f32() {
+                    return Err(ThermalFault::Falling);
+                }
+            }
+            Phase::Holding { below } => *below = 0.0,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn heater_that_does_not_warm_trips() {
+        let mut monitor = RunawayMonitor::new(RunawayConfig::HOTEND);
+        // Noise below the required rise does not count as heating.
+        let tripped = (0..400).position(|tick| {
+            let celsius = 25.0 + (tick % 3) as f32 * 0.3;
+            monitor.check(200.0, Some(Ok(celsius)), TICK).is_err()
+        });
+        assert!(matches!(tripped, Some(200..=202)), "tripped at {tripped:?}");
+        assert_eq!(
+            monitor.check(200.0, Some(Ok(25.0)), TICK),
+            Err(ThermalFault::NotHeating)
+        );
+
+        // A new target restarts the watch.
+        monitor.check(150.0, Some(Ok(25.0)), TICK).unwrap();
+    }
+
+    #[test]
+    fn temperature_falling_from_the_target_trips() {
+        let mut monitor = RunawayMonitor::new(RunawayConfig::HOTEND);
+        monitor.check(200.0, Some(Ok(197.0)), TICK).unwrap();
+        for _ in 0..400 {
+            monitor.check(200.0, Some(Ok(150.0)), TICK).unwrap();
+        }
+        let tripped = (0..20).any(|_| monitor.check(200.0, Some(Ok(150.0)), TICK).is_err());
+        assert!(tripped);
+        assert_eq!(
+            monitor.check(200.0, Some(Ok(150.0)), TICK),
+            Err(ThermalFault::Falling)
+        );
+    }
+
+    #[test]
+    fn tuning_may_swing_below_the_target() {
+        let mut monitor = RunawayMonitor::new(RunawayConfig::HOTEND);
+        monitor.check_tuning(200.0, Some(Ok(197.0)), TICK).unwrap();
+        for _ in 0..1_000 {
+            monitor.check_tuning(200.0, Some(Ok(190.0)), TICK).unwrap();
+        }
+        assert_eq!(
+            monitor.check_tuning(200.0, Some(Ok(291.0)), TICK),
+            Err(ThermalFault::MaxTemperature)
+        );
+        assert_eq!(
+            monitor.check_tuning(200.0, Some(Err(SensorFault::Open)), TICK),
+            Err(ThermalFault::Sensor(SensorFault::Open))
+        );
+
+        // The heating watch still applies before the target is first reached.
+        let mut monitor = RunawayMonitor::new(RunawayConfig::HOTEND);
+        let tripped = (0..400).any(|_| monitor.check_tuning(200.0, Some(Ok(25.0)), TICK).is_err());
+        assert!(tripped);
+    }
+
+    #[test]
+    fn cutoff_applies_with_the_heater_off() {
+        let mut monitor = RunawayMonitor::new(RunawayConfig::BED);
+        monitor
+            .check(0.0, Some(Err(SensorFault::Short)), TICK)
+            .unwrap();
+        monitor.check(0.0, None, TICK).unwrap();
+        assert_eq!(
+            monitor.check(0.0, Some(Ok(131.0)), TICK),
+            Err(ThermalFault::MaxTemperature)
+        );
+    }
+}
@@ -0,0 +1,805 @@
+//! Segment buffer between the planner and the step interrupt.
+//!
+//! [`SegmentPreparer`] runs in task context. It takes the block at the planner's tail, integrates
+//! its velocity profile in short time slices, and pushes each slice as a constant-rate
+//! [`Segment`] onto a lock-free single-producer, single-consumer queue. The step interrupt pops
+//! segments and only has to run the Bresenham counters at each segment's fixed interval, which
+//! keeps floating point out of interrupt context.
+//!
+//! Low step rates use adaptive multi-axis step smoothing (AMASS), as in Grbl: the interrupt runs
+//! at a multiple of the step rate and the Bresenham counters are scaled by the same factor, so
+//! that non-dominant axes step at evenly spaced ticks instead of aliasing against a slow clock.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use std::sync::Arc;
+
+use crate::{config::AXES, interrupts::STEP_TIMER_FREQUENCY, planner::Planner};
+
+/// Number of queue slots; one is always left empty to tell a full queue from an empty one.
+///
+/// Queued segments run at the speed they were prepared for, so an override or feed hold reaches
+/// the motors only once they have drained. Four 10 ms segments bound that delay at 40 ms while
+/// still covering several periods of the firmware's 5 ms preparation task.
+pub const SEGMENT_BUFFER_SIZE: usize = 5;
+/// Target duration of one segment in seconds.
+const SEGMENT_DURATION: f32 = 0.01;
+/// Highest AMASS level; Bresenham step counts are scaled by `1 << MAX_AMASS_LEVEL`.
+pub const MAX_AMASS_LEVEL: u8 = 3;
+/// Interrupt intervals in timer ticks above which each AMASS level engages, i.e. step rates
+/// below 8, 4, and 2 kHz.
+const AMASS_THRESHOLDS: [u32; MAX_AMASS_LEVEL as usize] = [
+    STEP_TIMER_FREQUENCY / 8_000,
+    STEP_TIMER_FREQUENCY / 4_000,
+    STEP_TIMER_FREQUENCY / 2_000,
+];
+
+/// A run of interrupt ticks at one fixed interval.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Segment {
+    /// Per-axis steps of the owning block, scaled by `1 << MAX_AMASS_LEVEL`.
+    pub block_steps: [u32; AXES],
+    /// Step events of the owning block, scaled by `1 << MAX_AMASS_LEVEL`.
+    pub block_step_event_count: u32,
+    /// Direction bits of the owning block; a set bit moves its axis toward negative coordinates.
+    pub direction_bits: u8,
+    /// `true` for the first segment of a block, which resets the Bresenham counters.
+    pub new_block: bool,
+    /// Interrupt ticks in this segment, already multiplied by the AMASS factor.
+    pub n_step: u32,
+    /// Timer ticks between interrupts.
+    pub interval: u32,
+    /// AMASS level; the interrupt runs `1 << amass_level` times per step event.
+    pub amass_level: u8,
+}
+
+/// Storage shared by the two queue handles.
+struct Ring {
+    slots: [UnsafeCell<Segment>; SEGMENT_BUFFER_SIZE],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// The producer only writes the slot at `head` and the consumer only reads the slot at `tail`;
+// the atomic indices publish each slot before the other side may touch it.
+unsafe impl Sync for Ring {}
+
+/// Creates an empty segment queue and returns its two ends.
+pub fn segment_queue() -> (SegmentProducer, SegmentConsumer) {
+    let ring = Arc::new(Ring {
+        slots: Default::default(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (
+        SegmentProducer {
+            ring: Arc::clone(&ring),
+        },
+        SegmentConsumer { ring },
+    )
+}
+
+/// Writing end of the segment queue, owned by the preparer.
+pub struct SegmentProducer {
+    ring: Arc<Ring>,
+}
+
+impl SegmentProducer {
+    /// Returns `true` when no more segments can be pushed.
+    pub fn is_full(&self) -> bool {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        (head + 1) % SEGMENT_BUFFER_SIZE == self.ring.tail.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` when the consumer has drained every segment.
+    pub fn is_empty(&self) -> bool {
+        self.ring.head.load(Ordering::Relaxed) == self.ring.tail.load(Ordering::Acquire)
+    }
+
+    /// Appends a segment, handing it back if the queue is full.
+    pub fn push(&mut self, segment: Segment) -> Result<(), Segment> {
+        if self.is_full() {
+            return Err(segment);
+        }
+        let head = self.ring.head.load(Ordering::Relaxed);
+        unsafe { *self.ring.slots[head].get() = segment };
+        self.ring
+            .head
+            .store((head + 1) % SEGMENT_BUFFER_SIZE, Ordering::Release);
+        Ok(())
+    }
+}
+
+/// Reading end of the segment queue, owned by the step interrupt.
+pub struct SegmentConsumer {
+    ring: Arc<Ring>,
+}
+
+impl SegmentConsumer {
+    /// Removes the oldest segment.
+    pub fn pop(&mut self) -> Option<Segment> {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        if tail == self.ring.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let segment = unsafe { *self.ring.slots[tail].get() };
+        self.ring
+            .tail
+            .store((tail + 1) % SEGMENT_BUFFER_SIZE, Ordering::Release);
+        Some(segment)
+    }
+
+    /// Returns `true` when no segment is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.ring.tail.load(Ordering::Relaxed) == self.ring.head.load(Ordering::Acquire)
+    }
+}
+
+/// Progress through the planner block currently being segmented.
+struct PreparedBlock {
+    block_steps: [u32; AXES],
+    block_step_event_count: u32,
+    direction_bits: u8,
+    /// Step events of the dominant axis per millimetre of path.
+    steps_per_mm: f32,
+    acceleration: f32,
+    deceleration: f32,
+    /// Path length not yet covered by segments, in millimetres.
+    mm_remaining: f32,
+    /// Speed at the end of the last prepared segment, in millimetres per second.
+    speed: f32,
+    new_block: bool,
+}
+
+impl PreparedBlock {
+    /// Integrates the trapezoid for up to `duration` seconds and returns the time actually used,
+    /// which is shorter only when the block ends inside the slice.
+    fn advance(&mut self, duration: f32, nominal_speed: f32, exit_speed_sqr: f32) -> f32 {
+        let exit_speed = exit_speed_sqr.sqrt();
+        let mut time_left = duration;
+
+        // Each pass ends at a ramp boundary or at the end of the slice; a slice crosses at most
+        // the acceleration, cruise, and deceleration boundaries.
+        for _ in 0..4 {
+            if time_left <= 0.0 || self.mm_remaining <= 0.0 {
+                break;
+            }
+            let speed = self.speed;
+            let braking_distance =
+                (speed * speed - exit_speed_sqr).max(0.0) / (2.0 * self.deceleration);
+
+            if braking_distance >= self.mm_remaining {
+                // Decelerate to the exit speed by the end of the block.
+                let ramp_time = (speed - exit_speed).max(0.0) / self.deceleration;
+                let end_speed_sqr = speed * speed - 2.0 * self.deceleration * self.mm_remaining;
+                let block_time = if end_speed_sqr > 0.0 {
+                    (speed - end_speed_sqr.sqrt()) / self.deceleration
+                } else {
+                    ramp_time
+                };
+                if block_time > time_left {
+                    let end_speed = (speed - self.deceleration * time_left).max(exit_speed);
+                    self.mm_remaining -= (speed + end_speed) / 2.0 * time_left;
+                    self.speed = end_speed;
+                    time_left = 0.0;
+                } else {
+                    self.mm_remaining = 0.0;
+                    self.speed = end_speed_sqr.max(exit_speed_sqr).sqrt();
+                    time_left -= block_time;
+                }
+            } else if speed > nominal_speed {
+                // The nominal speed dropped below the current speed; slow down to it.
+                let ramp_time = (speed - nominal_speed) / self.deceleration;
+                let ramp_time = ramp_time.min(time_left);
+                let end_speed = speed - self.deceleration * ramp_time;
+                self.mm_remaining -= (speed + end_speed) / 2.0 * ramp_time;
+                self.speed = end_speed;
+                time_left -= ramp_time;
+            } else if speed < nominal_speed {
+                // Accelerate until the nominal speed or the braking point, whichever comes first.
+                let peak_distance = (2.0 * self.deceleration * self.mm_remaining + exit_speed_sqr
+                    - speed * speed)
+                    / (2.0 * (self.acceleration + self.deceleration));
+                let nominal_distance =
+                    (nominal_speed * nominal_speed - speed * speed) / (2.0 * self.acceleration);
+                let ramp_distance = peak_distance.min(nominal_distance).max(0.0);
+                let peak_speed = (speed * speed + 2.0 * self.acceleration * ramp_distance).sqrt();
+                let ramp_time = (peak_speed - speed) / self.acceleration;
+                if ramp_time > time_left {
+                    let end_speed = speed + self.acceleration * time_left;
+                    self.mm_remaining -= (speed + end_speed) / 2.0 * time_left;
+                    self.speed = end_speed;
+                    time_left = 0.0;
+                } else {
+                    self.mm_remaining -= ramp_distance;
+                    self.speed = peak_speed;
+                    time_left -= ramp_time;
+                    if peak_distance < nominal_distance {
+                        // Start braking on the next pass even if rounding left a sliver of path.
+                        let braking_distance = (peak_speed * peak_speed - exit_speed_sqr).max(0.0)
+                            / (2.0 * self.deceleration);
+                        self.mm_remaining = self.mm_remaining.min(braking_distance);
+                    }
+                }
+            } else {
+                // Cruise until the braking point.
+                let cruise_time = (self.mm_remaining - braking_distance) / speed;
+                if cruise_time > time_left {
+                    self.mm_remaining -= speed * time_left;
+                    time_left = 0.0;
+                } else {
+                    self.mm_remaining = braking_distance;
+                    time_left -= cruise_time;
+                }
+            }
+        }
+
+        self.mm_remaining = self.mm_remaining.max(0.0);
+        duration - time_left
+    }
+
+    /// Decelerates toward a stop for up to `duration` seconds, ignoring the planned profile, and
+    /// returns the time actually used, which is shorter when the machine stops or the block ends
+    /// inside the slice.
+    fn brake(&mut self, duration: f32) -> f32 {
+        let speed = self.speed;
+        let stop_time = speed / self.deceleration;
+        let (mut time, mut end_speed) = if stop_time <= duration {
+            (stop_time, 0.0)
+        } else {
+            (duration, speed - self.deceleration * duration)
+        };
+        let mut distance = (speed + end_speed) / 2.0 * time;
+        if distance >= self.mm_remaining {
+            // The block ends before the stop; the next block continues braking from here.
+            distance = self.mm_remaining;
+            end_speed = (speed * speed - 2.0 * self.deceleration * distance)
+                .max(0.0)
+                .sqrt();
+            time = (speed - end_speed) / self.deceleration;
+        }
+        self.mm_remaining = (self.mm_remaining - distance).max(0.0);
+        self.speed = end_speed;
+        time
+    }
+}
+
+/// Converts planner blocks into segments for the step interrupt.
+pub struct SegmentPreparer {
+    producer: SegmentProducer,
+    block: Option<PreparedBlock>,
+    /// Time in seconds already spent on a partially emitted step, carried into the next segment.
+    dt_remainder: f32,
+    /// Speed at the end of the last prepared segment, in millimetres per second.
+    speed: f32,
+    /// `true` from a feed hold until the resume; segments brake to a stop and then stay stopped.
+    holding: bool,
+}
+
+impl SegmentPreparer {
+    /// Creates a preparer that feeds `producer`.
+    pub fn new(producer: SegmentProducer) -> Self {
+        Self {
+            producer,
+            block: None,
+            dt_remainder: 0.0,
+            speed: 0.0,
+            holding: false,
+        }
+    }
+
+    /// Returns `true` while a block is partially segmented or segments are waiting to execute.
+    pub fn is_busy(&self) -> bool {
+        self.block.is_some() || !self.producer.is_empty()
+    }
+
+    /// Forgets the partially segmented block after the step interrupt has abandoned its segments.
+    ///
+    /// A feed hold in progress is cancelled as well.
+    pub fn reset(&mut self) {
+        self.block = None;
+        self.dt_remainder = 0.0;
+        self.speed = 0.0;
+        self.holding = false;
+    }
+
+    /// Starts a feed hold. Segments prepared from now on decelerate to a stop, crossing block
+    /// boundaries if necessary, and no more are prepared once the machine has stopped.
+    ///
+    /// Segments already queued still run at their planned speed, so braking starts at most one
+    /// segment buffer later.
+    pub fn feed_hold(&mut self) {
+        self.holding = true;
+    }
+
+    /// Returns `true` from [`feed_hold`](Self::feed_hold) until [`resume`](Self::resume).
+    pub fn is_holding(&self) -> bool {
+        self.holding
+    }
+
+    /// Returns `true` once a feed hold has brought the prepared motion to rest and the step
+    /// interrupt has taken every queued segment.
+    pub fn is_held(&self) -> bool {
+        self.holding && self.speed <= 0.0 && self.producer.is_empty()
+    }
+
+    /// Ends a feed hold and replans the remaining blocks from the speed the hold left behind,
+    /// which is zero once it has completed.
+    pub fn resume(&mut self, planner: &mut Planner) {
+        self.holding = false;
+        planner.replan_from_current_block();
+    }
+
+    /// Prepares segments until the queue is full or the planner runs out of blocks.
+    ///
+    /// Each finished block is discarded from the planner, advancing its tail.
+    pub fn fill(&mut self, planner: &mut Planner) {
+        while !self.producer.is_full() {
+            let Some(segment) = self.prepare_segment(planner) else {
+                return;
+            };
+            if self.producer.push(segment).is_err() {
+                unreachable!("segment queue filled while preparing");
+            }
+        }
+    }
+
+    /// Integrates the current block for one segment.
+    fn prepare_segment(&mut self, planner: &mut Planner) -> Option<Segment> {
+        if self.holding && self.speed <= 0.0 {
+            return None;
+        }
+        let block = planner.current_block()?;
+        let nominal_speed = block.nominal_speed;
+        let prepared = self.block.get_or_insert_with(|| {
+            let steps = [block.steps.x, block.steps.y, block.steps.z, block.steps.e];
+            PreparedBlock {
+                block_steps: steps.map(|steps| steps.unsigned_abs() << MAX_AMASS_LEVEL),
+                block_step_event_count: (block.steps.step_event_count as u32) << MAX_AMASS_LEVEL,
+                direction_bits: steps
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, steps)| **steps < 0)
+                    .fold(0, |bits, (axis, _)| bits | 1 << axis),
+                steps_per_mm: block.steps.step_event_count as f32 / block.millimetres,
+                acceleration: block.acceleration,
+                deceleration: block.deceleration,
+                mm_remaining: block.millimetres,
+                speed: block.entry_speed_sqr.sqrt(),
+                new_block: true,
+            }
+        });
+        let exit_speed_sqr = planner.exit_speed_sqr();
+
+        let events_before = (prepared.mm_remaining * prepared.steps_per_mm).ceil();
+        let mut dt = 0.0;
+        let mut events_after;
+        let mut stopped;
+        loop {
+            dt += if self.holding {
+                prepared.brake(SEGMENT_DURATION)
+            } else {
+                prepared.advance(SEGMENT_DURATION, nominal_speed, exit_speed_sqr)
+            };
+            events_after = prepared.mm_remaining * prepared.steps_per_mm;
+            stopped = self.holding && prepared.speed <= 0.0;
+            // Very slow moves extend the segment until it contains at least one step event.
+            if prepared.mm_remaining <= 0.0 || stopped || events_before - events_after.ceil() >= 1.0
+            {
+                break;
+            }
+        }
+        let n_step = (events_before - events_after.ceil()) as u32;
+        self.speed = prepared.speed;
+        if stopped && n_step == 0 {
+            // The hold stopped short of the next step event, which runs after the resume.
+            self.dt_remainder = 0.0;
+            planner.update_current_block(0.0, prepared.mm_remaining);
+            return None;
+        }
+
+        // Time the steps so that a partial step at the end is finished by the next segment.
+        let seconds_per_event = (dt + self.dt_remainder) / (events_before - events_after);
+        self.dt_remainder = (events_after.ceil() - events_after) * seconds_per_event;
+        let mut interval = (seconds_per_event * STEP_TIMER_FREQUENCY as f32).ceil() as u32;
+
+        let amass_level = AMASS_THRESHOLDS
+            .iter()
+            .take_while(|threshold| interval >= **threshold)
+            .count() as u8;
+        interval >>= amass_level;
+
+        let segment = Segment {
+            block_steps: prepared.block_steps,
+            block_step_event_count: prepared.block_step_event_count,
+            direction_bits: prepared.direction_bits,
+            new_block: prepared.new_block,
+            n_step: n_step << amass_level,
+            interval: interval.max(1),
+            amass_level,
+        };
+        prepared.new_block = false;
+        if stopped {
+            // Time spent on a partial step is meaningless once the machine has stopped.
+            self.dt_remainder = 0.0;
+        }
+
+        if prepared.mm_remaining <= 0.0 {
+            self.block = None;
+            planner.discard_current_block();
+            if self.holding {
+                // Keep braking through the next block from the speed reached.
+                if let Some(millimetres) = planner.current_block().map(|block| block.millimetres) {
+                    planner.update_current_block(self.speed * self.speed, millimetres);
+                }
+            }
+        } else {
+            planner.update_current_block(prepared.speed * prepared.speed, prepared.mm_remaining);
+        }
+        Some(segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commandbuffer::Target,
+        config::MachineConfig,
+        gcode::{Interpreter, parse_line},
+        interrupts::{EndstopInputs, StepOutput, Stepper},
+        planner::{OverrideCommand, Planner},
+    };
+
+    /// A machine with 100 steps/mm on every axis and the given acceleration in mm/s².
+    fn planner(acceleration: f32) -> Planner {
+        let mut config = MachineConfig::default();
+        for axis in &mut config.axes {
+            axis.steps_per_mm = 100.0;
+            axis.max_rate = 12_000.0;
+            axis.max_acceleration = acceleration;
+        }
+        Planner::new(8, config)
+    }
+
+    /// Prepares and pops segments until the planner runs dry.
+    fn drain(planner: &mut Planner) -> Vec<Segment> {
+        let (producer, mut consumer) = segment_queue();
+        let mut preparer = SegmentPreparer::new(producer);
+        let mut segments = Vec::new();
+        loop {
+            preparer.fill(planner);
+            let before = segments.len();
+            segments.extend(core::iter::from_fn(|| consumer.pop()));
+            if segments.len() == before {
+                return segments;
+            }
+        }
+    }
+
+    /// Step events the interrupt runs for `segment`.
+    fn events(segment: &Segment) -> u32 {
+        segment.n_step >> segment.amass_level
+    }
+
+    /// Step events per second of `segment`.
+    fn rate(segment: &Segment) -> f32 {
+        STEP_TIMER_FREQUENCY as f32 / (segment.interval << segment.amass_level) as f32
+    }
+
+    struct NoPins;
+
+    impl StepOutput for NoPins {
+        fn set_directions(&mut self, _direction_bits: u8) {}
+        fn step(&mut self, _step_bits: u8) {}
+    }
+
+    impl EndstopInputs for NoPins {
+        fn triggered(&mut self) -> u8 {
+            0
+        }
+    }
+
+    /// The segment preparation thread and the step interrupt, run 5 ms at a time as in the
+    /// firmware.
+    struct Simulation {
+        planner: Planner,
+        preparer: SegmentPreparer,
+        stepper: Stepper,
+        /// Interrupt time in timer ticks.
+        time: u64,
+    }
+
+    impl Simulation {
+        /// Queues X20, X40, and X60 at 30 mm/s with 40 mm/s² acceleration, so a hold at full
+        /// speed needs 11.25 mm and 0.75 s to stop.
+        fn new() -> Self {
+            let mut planner = planner(40.0);
+            for x in [20.0, 40.0, 60.0] {
+                planner.buffer_line(x, 0.0, 0.0, 0.0, 1_800.0).unwrap();
+            }
+            Self::with_planner(planner)
+        }
+
+        fn with_planner(mut planner: Planner) -> Self {
+            planner.recalculate_trapezoids();
+            let (producer, consumer) = segment_queue();
+            Self {
+                planner,
+                preparer: SegmentPreparer::new(producer),
+                stepper: Stepper::new(consumer),
+                time: 0,
+            }
+        }
+
+        /// Prepares segments and runs the interrupt for 5 ms. Returns the X speed in mm/s.
+        fn tick(&mut self) -> f32 {
+            self.preparer.fill(&mut self.planner);
+            let start = self.stepper.position()[0];
+            let end = self.time + u64::from(STEP_TIMER_FREQUENCY / 200);
+            while self.time < end {
+                let interval = self
+                    .stepper
+                    .step_interrupt_handler(&mut NoPins, &mut NoPins);
+                self.time += u64::from(interval.unwrap_or(STEP_TIMER_FREQUENCY / 1_000));
+            }
+            (self.stepper.position()[0] - start) as f32 / 100.0 / 0.005
+        }
+
+        /// Holds after one second at 30 mm/s and returns the speed of every slice until the
+        /// hold completes.
+        fn hold(&mut self) -> Vec<f32> {
+            for _ in 0..200 {
+                self.tick();
+            }
+            self.preparer.feed_hold();
+            let mut speeds = Vec::new();
+            while !self.preparer.is_held() || self.stepper.is_busy() {
+                speeds.push(self.tick());
+                assert!(speeds.len() < 1_000, "hold did not complete");
+            }
+            speeds
+        }
+
+        /// Discards the queue once a hold has completed, as a stop or jog cancel does.
+        fn flush(&mut self) {
+            self.stepper.abort();
+            let [x, y, z, e] = self.stepper.position();
+            self.preparer.reset();
+            self.planner.clear(Target { x, y, z, e });
+        }
+
+        fn run_to_completion(&mut self) {
+            for _ in 0..10_000 {
+                if self.planner.is_empty() && !self.preparer.is_busy() && !self.stepper.is_busy() {
+                    return;
+                }
+                self.tick();
+            }
+            panic!("motion did not complete");
+        }
+    }
+
+    /// Asserts that `speeds` never rise by more than the one-step jitter of a 5 ms slice.
+    fn assert_decelerating(speeds: &[f32]) {
+        for pair in speeds.windows(2) {
+            assert!(
+                pair[1] <= pair[0] + 2.5,
+                "sped up during the hold: {speeds:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn feed_hold_brakes_to_a_stop_without_losing_steps() {
+        let mut simulation = Simulation::new();
+        let speeds = simulation.hold();
+        assert!((speeds[0] - 30.0).abs() <= 2.5, "{speeds:?}");
+        assert_decelerating(&speeds);
+        // Queued segments still run at speed before the 0.75 s ramp down, which crosses from the
+        // first block into the second.
+        let seconds = speeds.len() as f32 * 0.005;
+        assert!(seconds >= 0.74, "stopped after {seconds} s");
+        assert!(seconds <= 0.76 + SEGMENT_BUFFER_SIZE as f32 * SEGMENT_DURATION);
+        let held = simulation.stepper.position();
+        assert!(held[0] > 2_000, "{held:?}");
+
+        for _ in 0..20 {
+            assert_eq!(simulation.tick(), 0.0);
+        }
+        assert_eq!(simulation.stepper.position(), held);
+
+        simulation.preparer.resume(&mut simulation.planner);
+        simulation.run_to_completion();
+        assert_eq!(simulation.stepper.position(), [6_000, 0, 0, 0]);
+    }
+
+    #[test]
+    fn resume_replans_from_rest() {
+        let mut simulation = Simulation::new();
+        simulation.hold();
+        simulation.preparer.resume(&mut simulation.planner);
+        let block = simulation.planner.current_block().unwrap();
+        assert_eq!(block.entry_speed_sqr, 0.0);
+
+        // 40 mm/s² adds 0.2 mm/s per slice, so the first slices crawl and the rest climb back.
+        let speeds = (0..150).map(|_| simulation.tick()).collect::<Vec<_>>();
+        assert!(speeds[..5].iter().all(|speed| *speed <= 2.5), "{speeds:?}");
+        for pair in speeds.windows(2) {
+            assert!(
+                pair[1] >= pair[0] - 2.5,
+                "slowed after the resume: {speeds:?}"
+            );
+        }
+        assert!(speeds[149] >= 27.0, "{speeds:?}");
+    }
+
+    #[test]
+    fn stop_brakes_before_flushing_the_queue() {
+        let mut simulation = Simulation::new();
+        // A stop holds first and discards the queue only once the hold is complete.
+        let speeds = simulation.hold();
+        assert_decelerating(&speeds);
+        assert!(speeds.len() as f32 * 0.005 >= 0.74);
+
+        let [x, y, z, e] = simulation.stepper.position();
+        simulation.flush();
+        assert!(simulation.planner.is_empty());
+        assert_eq!(simulation.planner.position_steps(), Target { x, y, z, e });
+
+        // The next move starts from where the machine stopped.
+        let planner = &mut simulation.planner;
+        planner.buffer_line(0.0, 0.0, 0.0, 0.0, 1_800.0).unwrap();
+        planner.recalculate_trapezoids();
+        simulation.run_to_completion();
+        assert_eq!(simulation.stepper.position(), [0; AXES]);
+    }
+
+    #[test]
+    fn jog_cancel_brakes_then_discards_the_remaining_jogs() {
+        let mut planner = planner(40.0);
+        let mut interpreter = Interpreter::new();
+        let jog = parse_line("G91 X10 F1800").unwrap().command().unwrap();
+        for _ in 0..4 {
+            interpreter.jog(&jog, &mut planner).unwrap();
+        }
+        let mut simulation = Simulation::with_planner(planner);
+        let speeds = simulation.hold();
+        assert_decelerating(&speeds);
+        assert!(speeds.len() as f32 * 0.005 >= 0.74);
+        assert!(!simulation.planner.is_empty());
+
+        simulation.flush();
+        interpreter.abandon_motion(simulation.planner.position());
+        // About 30 mm in, with the last jog and part of the third never run.
+        let stopped = simulation.stepper.position();
+        assert!((2_900..3_200).contains(&stopped[0]), "{stopped:?}");
+        for _ in 0..20 {
+            assert_eq!(simulation.tick(), 0.0);
+        }
+        assert_eq!(simulation.stepper.position(), stopped);
+        assert_eq!(interpreter.position[0], stopped[0] as f32 / 100.0);
+    }
+
+    #[test]
+    fn segments_cover_every_step_of_the_trapezoid() {
+        let mut planner = planner(100.0);
+        // 20 mm/s is reached after 0.2 s and 2 mm, followed by a cruise and the same ramp down.
+        planner.buffer_line(10.0, 3.0, 0.0, 0.0, 1_200.0).unwrap();
+        planner.recalculate_trapezoids();
+        let block = planner.current_block().unwrap();
+        let (total, accel_until, decel_after) = (
+            block.steps.step_event_count,
+            block.accel_until,
+            block.decel_after,
+        );
+        let nominal_rate = block.nominal_rate;
+        let decel_start = 0.2 + (block.millimetres - 4.0) / 20.0;
+        let segments = drain(&mut planner);
+        assert!(planner.is_empty());
+        assert_eq!(segments.iter().map(events).sum::<u32>(), total as u32);
+        assert!(segments[0].new_block && segments[1..].iter().all(|s| !s.new_block));
+
+        // Find where the step rate reaches and leaves the plateau, in step events and seconds.
+        let mut boundaries = Vec::new();
+        let (mut steps, mut seconds) = (0, 0.0);
+        let mut cruising = false;
+        for segment in &segments {
+            let at_plateau = rate(segment) >= 0.99 * nominal_rate;
+            if at_plateau != cruising {
+                boundaries.push((steps, seconds));
+                cruising = at_plateau;
+            }
+            steps += events(segment) as i32;
+            seconds += (segment.n_step * segment.interval) as f32 / STEP_TIMER_FREQUENCY as f32;
+        }
+        let [(accel_steps, accel_time), (cruise_end, cruise_end_time)] = boundaries[..] else {
+            panic!("expected one plateau, got boundaries {boundaries:?}");
+        };
+        // Within one 10 ms segment, about 20 steps at the plateau.
+        assert!(
+            (accel_steps - accel_until).abs() <= 20,
+            "{accel_steps} vs {accel_until}"
+        );
+        assert!(
+            (cruise_end - decel_after).abs() <= 20,
+            "{cruise_end} vs {decel_after}"
+        );
+        assert!(
+            (accel_time - 0.2).abs() <= 0.011,
+            "accelerated for {accel_time} s"
+        );
+        assert!(
+            (cruise_end_time - decel_start).abs() <= 0.011,
+            "cruised until {cruise_end_time} s"
+        );
+        assert!(
+            (seconds - decel_start - 0.2).abs() <= 0.011,
+            "took {seconds} s"
+        );
+    }
+
+    #[test]
+    fn amass_level_rises_at_each_threshold() {
+        // Plateau step rates of 10, 6, 3, and 1 kHz, either side of the 8, 4, and 2 kHz
+        // thresholds.
+        for (speed, level) in [(100.0, 0), (60.0, 1), (30.0, 2), (10.0, 3)] {
+            let mut planner = planner(100_000.0);
+            planner
+                .buffer_line(50.0, 0.0, 0.0, 0.0, speed * 60.0)
+                .unwrap();
+            planner.recalculate_trapezoids();
+            let segments = drain(&mut planner);
+            let cruise = segments[segments.len() / 2];
+            assert_eq!(cruise.amass_level, level, "at {speed} mm/s");
+            // The interrupt runs 2^level times per step event at a 2^level shorter interval.
+            assert_eq!(cruise.n_step % (1 << level), 0);
+            let ticks_per_event = STEP_TIMER_FREQUENCY as f32 / (speed * 100.0);
+            let interval = (cruise.interval << level) as f32;
+            assert!((interval - ticks_per_event).abs() <= (1 << level) as f32);
+            assert_eq!(cruise.block_step_event_count, 5_000 << MAX_AMASS_LEVEL);
+            assert_eq!(cruise.block_steps[0], 5_000 << MAX_AMASS_LEVEL);
+        }
+    }
+
+    #[test]
+    fn override_reaches_the_queue_within_the_buffer() {
+        let mut planner = Planner::new(4, MachineConfig::default());
+        planner.buffer_line(100.0, 0.0, 0.0, 0.0, 600.0).unwrap();
+        planner.recalculate_trapezoids();
+        let (producer, mut consumer) = segment_queue();
+        let mut preparer = SegmentPreparer::new(producer);
+
+        // Run at the programmed 10 mm/s until the queue holds cruise segments only.
+        let mut intervals = Vec::new();
+        for _ in 0..10 {
+            preparer.fill(&mut planner);
+            intervals.extend(core::iter::from_fn(|| consumer.pop()).map(|s| s.interval));
+        }
+        let cruise = *intervals.last().unwrap();
+        preparer.fill(&mut planner);
+
+        let mut overrides = planner.overrides();
+        overrides.apply(OverrideCommand::Feed(Some(100)));
+        planner.set_overrides(overrides);
+
+        let mut stale = 0;
+        loop {
+            let segment = consumer.pop().unwrap_or_else(|| {
+                preparer.fill(&mut planner);
+                consumer.pop().unwrap()
+            });
+            if segment.interval != cruise {
+                assert!(segment.interval < cruise);
+                break;
+            }
+            stale += 1;
+        }
+        assert!(
+            stale < SEGMENT_BUFFER_SIZE,
+            "{stale} segments ran at the old speed"
+        );
+    }
+}
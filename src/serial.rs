@@ -1,4 +1,7 @@
 //! Serial transport support.
 //!
 //! The module is reserved for future UART and RS-485 command transports. I2C, I2S, and SPI are
-//! peripheral buses and will live in their respective device drivers.
+//! peripheral buses and will live in their respective device drivers. Like `POST /queue`, a
+//! transport must pass each received byte through
+//! [`RealtimeCommand::from_byte`](crate::machine::RealtimeCommand::from_byte) before assembling
+//! G-code lines, so feed hold, cycle start, and stop bypass the line queue.
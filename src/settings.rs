@@ -0,0 +1,65 @@
+//! Settings kept in the default NVS partition across restarts.
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_sys::EspError;
+
+use crate::heater::PidGains;
+
+const NAMESPACE: &str = "alumina";
+/// Prefix of each heater's gains key. NVS keys hold at most 15 bytes.
+const PID_KEY_PREFIX: &str = "pid_";
+/// `kp`, `ki`, and `kd` as little-endian `f32`s.
+const PID_LENGTH: usize = 12;
+
+/// The firmware's NVS namespace.
+pub struct Settings {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl Settings {
+    pub fn open(partition: EspDefaultNvsPartition) -> Result<Self, EspError> {
+        Ok(Self {
+            nvs: EspNvs::new(partition, NAMESPACE, true)?,
+        })
+    }
+
+    /// Returns the PID gains saved for the heater called `heater`.
+    pub fn pid_gains(&self, heater: &str) -> Result<Option<PidGains>, EspError> {
+        let mut buffer = [0; PID_LENGTH];
+        let Some(bytes) = self.nvs.get_blob(&pid_key(heater), &mut buffer)? else {
+            return Ok(None);
+        };
+        let Ok(bytes) = <[u8; PID_LENGTH]>::try_from(bytes) else {
+            log::warn!(
+                "Ignoring saved {heater} gains of unexpected length {}",
+                bytes.len()
+            );
+            return Ok(None);
+        };
+        let value = |index: usize| {
+            let mut word = [0; 4];
+            word.copy_from_slice(&bytes[index * 4..index * 4 + 4]);
+            f32::from_le_bytes(word)
+        };
+        Ok(Some(PidGains {
+            kp: value(0),
+            ki: value(1),
+            kd: value(2),
+        }))
+    }
+
+    pub fn set_pid_gains(&mut self, heater: &str, gains: PidGains) -> Result<(), EspError> {
+        let mut bytes = [0; PID_LENGTH];
+        for (chunk, value) in bytes
+            .chunks_exact_mut(4)
+            .zip([gains.kp, gains.ki, gains.kd])
+        {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        self.nvs.set_blob(&pid_key(heater), &bytes)
+    }
+}
+
+fn pid_key(heater: &str) -> String {
+    format!("{PID_KEY_PREFIX}{heater}")
+}
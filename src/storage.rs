@@ -0,0 +1,275 @@
+//! Job file storage on the `spiffs` flash partition.
+//!
+//! [`FileStore`] mounts the partition through the ESP-IDF virtual filesystem, after which files are
+//! ordinary `std::fs` paths under `/spiffs`. SPIFFS is flat, so stored names never contain a
+//! directory separator.
+
+use core::fmt;
+use std::{
+    ffi::CStr,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use esp_idf_sys::{self as sys, EspError, esp};
+
+const MOUNT_POINT: &str = "/spiffs";
+const BASE_PATH: &CStr = c"/spiffs";
+const PARTITION_LABEL: &CStr = c"spiffs";
+const MAX_OPEN_FILES: usize = 4;
+
+/// Longest accepted file name. SPIFFS stores at most 32 bytes per object name, including the
+/// leading `/` and the terminating NUL.
+pub const MAX_NAME_LENGTH: usize = 30;
+
+/// Hidden file that receives an upload until it is complete.
+const UPLOAD_NAME: &str = ".upload";
+
+/// Why a storage operation failed.
+#[derive(Debug)]
+pub enum StorageError {
+    /// The name is empty, too long, starts with `.`, or contains characters other than ASCII
+    /// letters, digits, `.`, `_`, and `-`.
+    InvalidName,
+    NotFound,
+    /// The upload does not fit in the space that was free when it started.
+    InsufficientSpace {
+        needed: u64,
+        available: u64,
+    },
+    Io(io::Error),
+    Esp(EspError),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName => write!(
+                f,
+                "file names must be 1 to {MAX_NAME_LENGTH} letters, digits, '.', '_', or '-' \
+                 and must not start with '.'"
+            ),
+            Self::NotFound => f.write_str("file not found"),
+            Self::InsufficientSpace { needed, available } => write!(
+                f,
+                "{needed} bytes do not fit in the {available} bytes of free storage"
+            ),
+            Self::Io(error) => write!(f, "storage I/O failed: {error}"),
+            Self::Esp(error) => write!(f, "storage driver failed: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => Self::NotFound,
+            _ => Self::Io(error),
+        }
+    }
+}
+
+impl From<EspError> for StorageError {
+    fn from(error: EspError) -> Self {
+        Self::Esp(error)
+    }
+}
+
+/// A stored file and its size in bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileEntry {
+    pub name: String,
+    pub size: u64,
+}
+
+/// Space on the partition, in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Usage {
+    pub total: u64,
+    pub used: u64,
+}
+
+impl Usage {
+    pub fn free(&self) -> u64 {
+        self.total.saturating_sub(self.used)
+    }
+}
+
+/// The mounted `spiffs` partition.
+pub struct FileStore {
+    _mounted: (),
+}
+
+impl FileStore {
+    /// Mounts the partition at `/spiffs`, formatting it if it holds no valid filesystem, and
+    /// removes any upload left behind by a reset.
+    pub fn mount() -> Result<Self, EspError> {
+        let config = sys::esp_vfs_spiffs_conf_t {
+            base_path: BASE_PATH.as_ptr(),
+            partition_label: PARTITION_LABEL.as_ptr(),
+            max_files: MAX_OPEN_FILES,
+            format_if_mount_failed: true,
+        };
+        esp!(unsafe { sys::esp_vfs_spiffs_register(&config) })?;
+
+        let store = Self { _mounted: () };
+        let _ = fs::remove_file(store.upload_path());
+        let usage = store.usage()?;
+        log::info!(
+            "Mounted {MOUNT_POINT}: {} of {} bytes used",
+            usage.used,
+            usage.total
+        );
+        Ok(store)
+    }
+
+    /// Returns the partition's size and how much of it is in use.
+    pub fn usage(&self) -> Result<Usage, EspError> {
+        let mut total = 0;
+        let mut used = 0;
+        esp!(unsafe { sys::esp_spiffs_info(PARTITION_LABEL.as_ptr(), &mut total, &mut used) })?;
+        Ok(Usage {
+            total: total as u64,
+            used: used as u64,
+        })
+    }
+
+    /// Lists the stored files in name order.
+    pub fn list(&self) -> Result<Vec<FileEntry>, StorageError> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(MOUNT_POINT)? {
+            let entry = entry?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            let size = entry.metadata()?.len();
+            files.push(FileEntry { name, size });
+        }
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(files)
+    }
+
+    /// Opens a stored file for reading.
+    pub fn open(&self, name: &str) -> Result<File, StorageError> {
+        Ok(File::open(path(name)?)?)
+    }
+
+    pub fn remove(&self, name: &str) -> Result<(), StorageError> {
+        Ok(fs::remove_file(path(name)?)?)
+    }
+
+    /// Starts storing `name`, which replaces any existing file of that name once
+    /// [`Upload::finish`] succeeds.
+    ///
+    /// `length` is the announced upload size. It is checked against the free space before
+    /// anything is written, and the bytes actually written are checked again as they arrive.
+    pub fn begin_upload(&self, name: &str, length: Option<u64>) -> Result<Upload, StorageError> {
+        let target = path(name)?;
+        let available = self.usage()?.free();
+        if let Some(needed) = length.filter(|&needed| needed > available) {
+            return Err(StorageError::InsufficientSpace { needed, available });
+        }
+
+        let partial = self.upload_path();
+        Ok(Upload {
+            file: Some(File::create(&partial)?),
+            partial,
+            target,
+            written: 0,
+            available,
+        })
+    }
+
+    fn upload_path(&self) -> PathBuf {
+        Path::new(MOUNT_POINT).join(UPLOAD_NAME)
+    }
+}
+
+/// A file being written by [`FileStore::begin_upload`].
+///
+/// Data goes to a hidden file that is renamed over the target only once the upload completes,
+/// so a failed or abandoned upload leaves the previous file untouched. Dropping an unfinished
+/// upload deletes what was written.
+pub struct Upload {
+    file: Option<File>,
+    partial: PathBuf,
+    target: PathBuf,
+    written: u64,
+    available: u64,
+}
+
+impl Upload {
+    /// Appends `bytes`, failing before the write if they would exceed the free space, or when the
+    /// filesystem fills up first.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), StorageError> {
+        let needed = self.written + bytes.len() as u64;
+        if needed > self.available {
+            return Err(StorageError::InsufficientSpace {
+                needed,
+                available: self.available,
+            });
+        }
+        let file = self
+            .file
+            .as_mut()
+            .expect("upload file is open until finished");
+        match file.write_all(bytes) {
+            // The free space SPIFFS reports includes room its metadata may still claim.
+            Err(error) if error.kind() == io::ErrorKind::StorageFull => {
+                return Err(StorageError::InsufficientSpace {
+                    needed,
+                    available: self.written,
+                });
+            }
+            result => result?,
+        }
+        self.written = needed;
+        Ok(())
+    }
+
+    /// Moves the upload into place and returns its size.
+    pub fn finish(mut self) -> Result<u64, StorageError> {
+        let mut file = self
+            .file
+            .take()
+            .expect("upload file is open until finished");
+        file.flush()?;
+        drop(file);
+
+        // SPIFFS refuses to rename onto an existing name.
+        match fs::remove_file(&self.target) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+        fs::rename(&self.partial, &self.target)?;
+        Ok(self.written)
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        // Close the file before removing it. After a successful rename there is nothing left.
+        self.file = None;
+        let _ = fs::remove_file(&self.partial);
+    }
+}
+
+/// Maps a user-supplied name to its path, rejecting names that could escape the mount point or
+/// that SPIFFS cannot store.
+fn path(name: &str) -> Result<PathBuf, StorageError> {
+    let valid = (1..=MAX_NAME_LENGTH).contains(&name.len())
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'_' | b'-'));
+    match valid {
+        true => Ok(Path::new(MOUNT_POINT).join(name)),
+        false => Err(StorageError::InvalidName),
+    }
+}
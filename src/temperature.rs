@@ -0,0 +1,348 @@
+//! Temperature sensor conversion and fault detection.
+//!
+//! The firmware's `AdcInputs` samples the sensor inputs as calibrated millivolts, reporting a
+//! saturated input as infinity. A [`Sensor`] filters those samples, rejects open- and
+//! short-circuit readings, and converts the rest to degrees Celsius through the sensor's
+//! [`Circuit`] and [`Curve`].
+
+use core::fmt;
+
+/// Offset between degrees Celsius and kelvin.
+pub const KELVIN_OFFSET: f32 = 273.15;
+
+/// Resistance in ohms of a generic 100 kΩ NTC 3950 thermistor in 10 °C steps, computed from its
+/// nominal beta. A part's datasheet table is more accurate above 200 °C.
+pub const NTC_100K_3950: &[(f32, f32)] = &[
+    (-20.0, 1_053_847.0),
+    (-10.0, 582_457.0),
+    (0.0, 336_206.0),
+    (10.0, 201_746.0),
+    (20.0, 125_353.0),
+    (30.0, 80_371.0),
+    (40.0, 53_015.0),
+    (50.0, 35_882.0),
+    (60.0, 24_862.0),
+    (70.0, 17_598.0),
+    (80.0, 12_703.0),
+    (90.0, 9_336.0),
+    (100.0, 6_975.0),
+    (110.0, 5_291.0),
+    (120.0, 4_071.0),
+    (130.0, 3_173.0),
+    (140.0, 2_503.0),
+    (150.0, 1_997.0),
+    (160.0, 1_610.0),
+    (170.0, 1_310.0),
+    (180.0, 1_076.0),
+    (190.0, 892.0),
+    (200.0, 745.0),
+    (210.0, 626.0),
+    (220.0, 531.0),
+    (230.0, 453.0),
+    (240.0, 388.0),
+    (250.0, 335.0),
+    (260.0, 291.0),
+    (270.0, 254.0),
+    (280.0, 223.0),
+    (290.0, 196.0),
+    (300.0, 174.0),
+];
+
+/// Callendar–Van Dusen coefficients for platinum RTDs (IEC 60751).
+const RTD_A: f32 = 3.9083e-3;
+const RTD_B: f32 = -5.775e-7;
+
+/// How a sensor's resistance depends on temperature.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Curve {
+    /// NTC thermistor with resistance `r25` ohms at 25 °C and coefficient `beta` in kelvin.
+    Beta { r25: f32, beta: f32 },
+    /// Steinhart–Hart coefficients for `1/T = a + b·ln R + c·(ln R)³`, with `T` in kelvin.
+    SteinhartHart { a: f32, b: f32, c: f32 },
+    /// Pairs of temperature in °C and resistance in ohms, in ascending temperature order.
+    ///
+    /// Lookups interpolate in log resistance, which is nearly linear in temperature for NTC
+    /// thermistors, and clamp to the table's ends.
+    Table(&'static [(f32, f32)]),
+    /// Platinum RTD with resistance `r0` ohms at 0 °C, such as a PT100 or PT1000, following the
+    /// Callendar–Van Dusen equation for temperatures above 0 °C.
+    Rtd { r0: f32 },
+}
+
+impl Curve {
+    /// Converts a resistance in ohms to degrees Celsius.
+    pub fn celsius(&self, ohms: f32) -> f32 {
+        match *self {
+            Self::Beta { r25, beta } => {
+                let inverse = 1.0 / (25.0 + KELVIN_OFFSET) + (ohms / r25).ln() / beta;
+                1.0 / inverse - KELVIN_OFFSET
+            }
+            Self::SteinhartHart { a, b, c } => {
+                let ln = ohms.ln();
+                1.0 / (a + b * ln + c * ln * ln * ln) - KELVIN_OFFSET
+            }
+            Self::Table(table) => interpolate(table, ohms),
+            Self::Rtd { r0 } => {
+                // Solves r0·(1 + A·t + B·t²) = ohms for t.
+                let discriminant = RTD_A * RTD_A - 4.0 * RTD_B * (1.0 - ohms / r0);
+                (-RTD_A + discriminant.max(0.0).sqrt()) / (2.0 * RTD_B)
+            }
+        }
+    }
+}
+
+fn interpolate(table: &[(f32, f32)], ohms: f32) -> f32 {
+    let Some(&(first_celsius, first_ohms)) = table.first() else {
+        return f32::NAN;
+    };
+    if ohms >= first_ohms {
+        return first_celsius;
+    }
+    let ln = ohms.ln();
+    for pair in table.windows(2) {
+        let [(low_celsius, low_ohms), (high_celsius, high_ohms)] = [pair[0], pair[1]];
+        if ohms >= high_ohms {
+            let fraction = (low_ohms.ln() - ln) / (low_ohms.ln() - high_ohms.ln());
+            return low_celsius + fraction * (high_celsius - low_celsius);
+        }
+    }
+    table[table.len() - 1].0
+}
+
+/// How a sensor's resistance reaches the ADC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Circuit {
+    /// The sensor runs to ground with a `pullup` resistor in ohms to `supply_mv`.
+    Divider { pullup: f32, supply_mv: f32 },
+    /// An amplifier whose output rises linearly from 0 mV at `offset_ohms` by `ohms_per_mv`.
+    Amplifier { offset_ohms: f32, ohms_per_mv: f32 },
+}
+
+impl Circuit {
+    /// Returns the sensor resistance in ohms that produces `millivolts` at the ADC.
+    pub fn ohms(&self, millivolts: f32) -> f32 {
+        match *self {
+            Self::Divider { pullup, supply_mv } => {
+                pullup * millivolts / (supply_mv - millivolts).max(f32::EPSILON)
+            }
+            Self::Amplifier {
+                offset_ohms,
+                ohms_per_mv,
+            } => offset_ohms + millivolts * ohms_per_mv,
+        }
+    }
+}
+
+/// A sensor reading that cannot be a temperature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SensorFault {
+    /// The input is at or near its upper rail, as with a disconnected sensor.
+    Open,
+    /// The input is at or near ground, as with shorted sensor leads.
+    Short,
+}
+
+impl fmt::Display for SensorFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Open => "open circuit",
+            Self::Short => "short circuit",
+        })
+    }
+}
+
+impl std::error::Error for SensorFault {}
+
+/// A sensor type and the circuit that connects it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorConfig {
+    pub curve: Curve,
+    pub circuit: Circuit,
+    /// Readings at or below this voltage are reported as [`SensorFault::Short`].
+    pub short_mv: f32,
+    /// Readings at or above this voltage are reported as [`SensorFault::Open`]. It must sit
+    /// below the ADC's saturation at about 3.1 V to be reachable.
+    pub open_mv: f32,
+    /// Weight of each new sample in the exponential moving average, from 0 to 1.
+    pub smoothing: f32,
+}
+
+impl SensorConfig {
+    /// A 100 kΩ NTC 3950 thermistor with a 4.7 kΩ pull-up to 3.3 V, as on the MKS TinyBee.
+    ///
+    /// The ADC saturates near 3.1 V, so the open threshold sits just below it, at about 38 °C on
+    /// this divider. A colder thermistor reads as open, like a disconnected one. The short
+    /// threshold corresponds to 43 Ω, well past the table's 300 °C end.
+    pub const NTC_100K_3950: Self = Self {
+        curve: Curve::Table(NTC_100K_3950),
+        circuit: Circuit::Divider {
+            pullup: 4_700.0,
+            supply_mv: 3_300.0,
+        },
+        short_mv: 30.0,
+        open_mv: 3_050.0,
+        smoothing: 0.25,
+    };
+
+    /// A PT1000 behind an amplifier that maps 900–2,550 Ω, about −25 °C to 420 °C, onto
+    /// 0–3.3 V. Amplifier boards differ, so check the offset and gain against the one fitted.
+    pub const PT1000_AMPLIFIER: Self = Self {
+        curve: Curve::Rtd { r0: 1_000.0 },
+        circuit: Circuit::Amplifier {
+            offset_ohms: 900.0,
+            ohms_per_mv: 0.5,
+        },
+        short_mv: 10.0,
+        open_mv: 3_050.0,
+        smoothing: 0.25,
+    };
+
+    /// Converts one voltage sample to degrees Celsius.
+    pub fn celsius(&self, millivolts: f32) -> Result<f32, SensorFault> {
+        if millivolts <= self.short_mv {
+            Err(SensorFault::Short)
+        } else if millivolts >= self.open_mv {
+            Err(SensorFault::Open)
+        } else {
+            Ok(self.curve.celsius(self.circuit.ohms(millivolts)))
+        }
+    }
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        Self::NTC_100K_3950
+    }
+}
+
+/// A temperature sensor's filtered state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sensor {
+    config: SensorConfig,
+    /// Moving average of the input voltage, or `None` until a valid sample arrives.
+    millivolts: Option<f32>,
+    reading: Option<Result<f32, SensorFault>>,
+}
+
+impl Sensor {
+    pub fn new(config: SensorConfig) -> Self {
+        Self {
+            config,
+            millivolts: None,
+            reading: None,
+        }
+    }
+
+    pub fn config(&self) -> &SensorConfig {
+        &self.config
+    }
+
+    /// Adds a sample and returns the new reading.
+    ///
+    /// Faults are judged on the unfiltered sample so that a broken wire is reported at once, and
+    /// they restart the average so that the fault's voltage never mixes into a temperature.
+    pub fn update(&mut self, millivolts: f32) -> Result<f32, SensorFault> {
+        let reading = match self.config.celsius(millivolts) {
+            Ok(_) => {
+                let average = match self.millivolts {
+                    Some(average) => average + (millivolts - average) * self.config.smoothing,
+                    None => millivolts,
+                };
+                self.millivolts = Some(average);
+                self.config.celsius(average)
+            }
+            Err(fault) => {
+                self.millivolts = None;
+                Err(fault)
+            }
+        };
+        self.reading = Some(reading);
+        reading
+    }
+
+    /// Returns the latest reading, or `None` before the first sample.
+    pub fn reading(&self) -> Option<Result<f32, SensorFault>> {
+        self.reading
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resistance of a 100 kΩ NTC 3950 thermistor at `celsius`.
+    fn ntc_ohms(celsius: f32) -> f32 {
+        100_000.0 * (3_950.0 * (1.0 / (celsius + KELVIN_OFFSET) - 1.0 / 298.15)).exp()
+    }
+
+    fn divider_mv(ohms: f32) -> f32 {
+        3_300.0 * ohms / (ohms + 4_700.0)
+    }
+
+    #[test]
+    fn thermistor_table_follows_its_beta() {
+        let beta = Curve::Beta {
+            r25: 100_000.0,
+            beta: 3_950.0,
+        };
+        for celsius in [-15.0, 25.0, 65.0, 155.0, 215.0, 295.0] {
+            let ohms = ntc_ohms(celsius);
+            assert!((beta.celsius(ohms) - celsius).abs() < 0.05);
+            assert!((Curve::Table(NTC_100K_3950).celsius(ohms) - celsius).abs() < 0.6);
+        }
+        assert_eq!(Curve::Table(NTC_100K_3950).celsius(5e6), -20.0);
+        assert_eq!(Curve::Table(NTC_100K_3950).celsius(10.0), 300.0);
+    }
+
+    #[test]
+    fn thermistor_divider_reads_within_adc_range() {
+        let ntc = SensorConfig::NTC_100K_3950;
+        for celsius in [40.0, 100.0, 215.0, 280.0] {
+            let reading = ntc.celsius(divider_mv(ntc_ohms(celsius))).unwrap();
+            assert!(
+                (reading - celsius).abs() < 0.7,
+                "{celsius} read as {reading}"
+            );
+        }
+        // The open threshold must be reachable below the ADC's saturation at about 3.1 V.
+        assert!(ntc.open_mv < 3_100.0);
+        assert_eq!(ntc.celsius(3_090.0), Err(SensorFault::Open));
+        assert_eq!(ntc.celsius(f32::INFINITY), Err(SensorFault::Open));
+        assert_eq!(ntc.celsius(5.0), Err(SensorFault::Short));
+    }
+
+    #[test]
+    fn rtd_amplifier_reads_within_adc_range() {
+        let pt = SensorConfig::PT1000_AMPLIFIER;
+        for celsius in [0.0, 100.0, 250.0, 370.0] {
+            let ohms = 1_000.0 * (1.0 + RTD_A * celsius + RTD_B * celsius * celsius);
+            let reading = pt.celsius((ohms - 900.0) / 0.5).unwrap();
+            assert!(
+                (reading - celsius).abs() < 0.05,
+                "{celsius} read as {reading}"
+            );
+        }
+        assert!(pt.open_mv < 3_100.0);
+        assert_eq!(pt.celsius(f32::INFINITY), Err(SensorFault::Open));
+    }
+
+    #[test]
+    fn sensor_smooths_samples_and_reports_faults_at_once() {
+        let mut sensor = Sensor::new(SensorConfig::NTC_100K_3950);
+        assert_eq!(sensor.reading(), None);
+        let hot = divider_mv(ntc_ohms(100.0));
+        sensor.update(divider_mv(ntc_ohms(50.0))).unwrap();
+        let mut last = 50.0;
+        for _ in 0..40 {
+            let celsius = sensor.update(hot).unwrap();
+            assert!(celsius >= last - 1e-3);
+            last = celsius;
+        }
+        assert!((last - 100.0).abs() < 0.5, "{last}");
+
+        assert_eq!(sensor.update(f32::INFINITY), Err(SensorFault::Open));
+        assert_eq!(sensor.reading(), Some(Err(SensorFault::Open)));
+        // The fault restarts the average instead of dragging the next reading towards it.
+        assert!((sensor.update(hot).unwrap() - 100.0).abs() < 0.5);
+    }
+}